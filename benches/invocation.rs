@@ -0,0 +1,120 @@
+//! Benchmarks of the invocation path: request decoding, response encoding for every content
+//! type, and whole invocations through the request pipeline.
+//!
+//! Run with `cargo bench --features testing`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use fdk::testing::TestFunction;
+use fdk::{InputCoercible, OutputCoercible, RuntimeContext};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Payload {
+    name: String,
+    id: u64,
+    active: bool,
+    score: f64,
+}
+
+fn payload() -> Payload {
+    Payload {
+        name: "a name long enough to matter".to_owned(),
+        id: 1_234_567,
+        active: true,
+        score: 0.75,
+    }
+}
+
+type Decoder = fn(&[u8]) -> fdk::Result<Payload>;
+type Encoder = fn(Payload) -> fdk::Result<Vec<u8>>;
+
+fn formats() -> Vec<(&'static str, Encoder, Decoder)> {
+    vec![
+        (
+            "json",
+            OutputCoercible::try_encode_json,
+            InputCoercible::try_decode_json,
+        ),
+        (
+            "xml",
+            OutputCoercible::try_encode_xml,
+            InputCoercible::try_decode_xml,
+        ),
+        (
+            "yaml",
+            OutputCoercible::try_encode_yaml,
+            InputCoercible::try_decode_yaml,
+        ),
+        (
+            "urlencoded",
+            OutputCoercible::try_encode_urlencoded,
+            InputCoercible::try_decode_urlencoded,
+        ),
+    ]
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    for (name, encode, decode) in formats() {
+        let input = encode(payload()).unwrap();
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &input, |b, input| {
+            b.iter(|| decode(input).unwrap())
+        });
+    }
+    let text = vec![b'x'; 4096];
+    group.throughput(Throughput::Bytes(text.len() as u64));
+    group.bench_with_input(BenchmarkId::from_parameter("plain"), &text, |b, text| {
+        b.iter(|| String::try_decode_plain(text).unwrap())
+    });
+    group.finish();
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    for (name, encode, _) in formats() {
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter_batched(payload, encode, criterion::BatchSize::SmallInput)
+        });
+    }
+    let text = "x".repeat(4096);
+    group.bench_function(BenchmarkId::from_parameter("plain"), |b| {
+        b.iter_batched(
+            || text.clone(),
+            OutputCoercible::try_encode_plain,
+            criterion::BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn invoke(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let function = TestFunction::new(|_: &mut RuntimeContext, mut payload: Payload| {
+        payload.id += 1;
+        Ok(payload)
+    });
+    let body = serde_json::to_vec(&payload()).unwrap();
+
+    let mut group = c.benchmark_group("invoke");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("json", |b| {
+        b.to_async(&runtime)
+            .iter(|| async { function.invoke(body.clone()).await })
+    });
+    group.bench_function("xml", |b| {
+        b.to_async(&runtime).iter(|| async {
+            function
+                .invoke(body.clone())
+                .with_header("Accept", "application/xml")
+                .await
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode, encode, invoke);
+criterion_main!(benches);
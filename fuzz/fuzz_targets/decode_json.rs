@@ -0,0 +1,13 @@
+#![no_main]
+
+use fdk::InputCoercible;
+use libfuzzer_sys::fuzz_target;
+use serde_json::Value;
+use std::collections::HashMap;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = String::try_decode_json(data);
+    let _ = Value::try_decode_json(data);
+    let _ = HashMap::<String, String>::try_decode_json(data);
+    let _ = Value::try_decode_json_first_value(data);
+});
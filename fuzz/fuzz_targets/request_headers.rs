@@ -0,0 +1,39 @@
+#![no_main]
+
+use fdk::RuntimeContext;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::Request;
+use libfuzzer_sys::fuzz_target;
+
+// Each line of the input is a `name: value` header. Lines that are not valid headers are
+// skipped, as hyper would reject them before they reach the FDK.
+fuzz_target!(|data: &[u8]| {
+    let mut req = Request::new(());
+    for line in data.split(|b| *b == b'\n') {
+        let split = match line.iter().position(|b| *b == b':') {
+            Some(split) => split,
+            None => continue,
+        };
+        let (name, value) = (&line[..split], &line[split + 1..]);
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name),
+            HeaderValue::from_bytes(value.strip_prefix(b" ").unwrap_or(value)),
+        ) {
+            req.headers_mut().append(name, value);
+        }
+    }
+
+    let ctx = RuntimeContext::from_req(&req);
+    let _ = ctx.content_type();
+    let _ = ctx.accept_type();
+    let _ = ctx.deadline();
+    let _ = ctx.time_remaining();
+    let _ = ctx.method();
+    let _ = ctx.request_url();
+    let _ = ctx.query_params();
+    let _ = ctx.http_headers();
+    let _ = ctx.oci_headers();
+    let _ = ctx.trace_context();
+    let _ = ctx.api_gateway().client_ip();
+    let _ = ctx.call_id();
+});
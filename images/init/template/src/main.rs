@@ -1,8 +1,11 @@
-use fdk::{Function, FunctionError, RuntimeContext};
+use fdk::prelude::*;
 use tokio;
 
 #[tokio::main]
-async fn main() -> Result<(), FunctionError> {
+async fn main() -> Result<()> {
+    // `<binary> selftest` checks which content types String can be decoded from and encoded to.
+    fdk::run_selftest_command::<String, String>();
+
     if let Err(e) = Function::run(|_: &mut RuntimeContext, i: String| {
         Ok(format!(
             "Hello {}!",
@@ -19,6 +19,6 @@
 user="fnproject"
 image="rust"
 
-rust153="1.53"
-docker push ${user}/${image}:${rust153}
-docker push ${user}/${image}:${rust153}-dev
+rust188="1.88"
+docker push ${user}/${image}:${rust188}
+docker push ${user}/${image}:${rust188}-dev
@@ -0,0 +1,153 @@
+//! The request model of functions fronted by OCI API Gateway.
+//!
+//! API Gateway invokes a function with the original method and URL in `Fn-Http-Method` and
+//! `Fn-Http-Request-Url`, the client headers with the `Fn-Http-H-` prefix, including the
+//! `X-Real-IP` and `X-Forwarded-*` headers it adds, and reads the response status and headers
+//! from `Fn-Http-Status` and `Fn-Http-H-` headers, which `FnResponse` and
+//! `RuntimeContext::set_status_code` set. OCI API Gateway has no stages: deployments are told
+//! apart by their path prefix, which `ApiGatewayRequest::route_path` strips.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! let gateway = ctx.api_gateway();
+//! let params = gateway
+//!     .route_params("/v1", "/users/{id}")
+//!     .ok_or_else(|| FunctionError::new_user_error("not found".into()))?;
+//! let user = load_user(&params["id"], gateway.client_ip())?;
+//! ```
+
+use crate::context::RuntimeContext;
+use crate::oci::forwarded_header;
+use hyper::{Method, Uri};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// ApiGatewayRequest is the view API Gateway gives of the HTTP request of a client.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ApiGatewayRequest {
+    method: Option<Method>,
+    url: Option<Uri>,
+    host: Option<String>,
+    real_ip: Option<String>,
+    forwarded_for: Vec<String>,
+    forwarded_proto: Option<String>,
+    request_id: Option<String>,
+}
+
+impl ApiGatewayRequest {
+    pub(crate) fn from_context(ctx: &RuntimeContext) -> Self {
+        let headers = ctx.headers_ref();
+        let header = |name: &str| forwarded_header(headers, name).map(str::to_owned);
+        Self {
+            method: ctx.method().cloned(),
+            url: ctx.request_url().cloned(),
+            host: header("X-Forwarded-Host").or_else(|| header("Host")),
+            real_ip: header("X-Real-IP"),
+            forwarded_for: forwarded_header(headers, "X-Forwarded-For")
+                .map(|v| {
+                    v.split(',')
+                        .map(|ip| ip.trim().to_owned())
+                        .filter(|ip| !ip.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            forwarded_proto: header("X-Forwarded-Proto"),
+            request_id: header("opc-request-id"),
+        }
+    }
+
+    /// Returns the method of the client request.
+    pub fn method(&self) -> Option<&Method> {
+        self.method.as_ref()
+    }
+
+    /// Returns the URL of the client request, including the deployment path prefix.
+    pub fn url(&self) -> Option<&Uri> {
+        self.url.as_ref()
+    }
+
+    /// Returns the path of the client request, including the deployment path prefix, or `/`.
+    pub fn path(&self) -> &str {
+        self.url.as_ref().map_or("/", Uri::path)
+    }
+
+    /// Returns the path of the client request relative to the deployment at
+    /// `deployment_path`, e.g. `/users/42` for `/v1/users/42` and `/v1`, or None if the path
+    /// is outside of the deployment.
+    pub fn route_path(&self, deployment_path: &str) -> Option<&str> {
+        let prefix = deployment_path.trim_end_matches('/');
+        match self.path().strip_prefix(prefix)? {
+            "" => Some("/"),
+            rest if rest.starts_with('/') => Some(rest),
+            _ => None,
+        }
+    }
+
+    /// Matches the path of the client request against the API Gateway route `route` of the
+    /// deployment at `deployment_path`, and returns the values of its `{name}` parameters, or
+    /// None if it does not match. A trailing `{name*}` parameter matches the rest of the path.
+    pub fn route_params(
+        &self,
+        deployment_path: &str,
+        route: &str,
+    ) -> Option<HashMap<String, String>> {
+        match_route(self.route_path(deployment_path)?, route)
+    }
+
+    /// Returns the host the client called, from `X-Forwarded-Host` or `Host`.
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    /// Returns the IP address of the client, from `X-Real-IP` or else the first address of
+    /// `X-Forwarded-For`.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.real_ip
+            .iter()
+            .chain(self.forwarded_for.first())
+            .find_map(|ip| ip.parse().ok())
+    }
+
+    /// Returns the addresses of `X-Forwarded-For`, the client first.
+    pub fn forwarded_for(&self) -> &[String] {
+        &self.forwarded_for
+    }
+
+    /// Returns the protocol the client used, from `X-Forwarded-Proto`, e.g. `https`.
+    pub fn forwarded_proto(&self) -> Option<&str> {
+        self.forwarded_proto.as_deref()
+    }
+
+    /// Returns the `opc-request-id` of the request, to quote in support cases.
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+}
+
+/// Matches `path` against the API Gateway route `route`, and returns the values of its `{name}`
+/// parameters, or None if it does not match. A trailing `{name*}` parameter matches the rest of
+/// the path.
+pub(crate) fn match_route(path: &str, route: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let mut segments = path.trim_matches('/').split('/');
+    for pattern in route.trim_matches('/').split('/') {
+        let name = pattern.strip_prefix('{').and_then(|p| p.strip_suffix('}'));
+        if let Some(name) = name.and_then(|name| name.strip_suffix('*')) {
+            params.insert(name.to_owned(), segments.collect::<Vec<_>>().join("/"));
+            return Some(params);
+        }
+        let segment = segments.next()?;
+        match name {
+            Some(name) if !segment.is_empty() => {
+                params.insert(name.to_owned(), segment.to_owned());
+            }
+            None if segment == pattern => {}
+            _ => return None,
+        }
+    }
+    match segments.next() {
+        None => Some(params),
+        Some(_) => None,
+    }
+}
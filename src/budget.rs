@@ -0,0 +1,91 @@
+use std::time::{Duration, Instant};
+
+/// Share of the remaining deadline `RuntimeContext::timeout_budget` hands to outbound calls,
+/// leaving the rest to process their results and respond.
+pub const DEFAULT_BUDGET_SHARE: f64 = 0.8;
+
+/// Share of an attempt given to DNS resolution and connection establishment by default.
+const DEFAULT_CONNECT_SHARE: f64 = 0.25;
+
+/// TimeoutBudget splits a time budget across the attempts of an outbound call, so timeouts
+/// follow the Fn deadline instead of being hard-coded.
+///
+/// Each attempt gets an even share of the time left when it starts, so time not used by a
+/// failed attempt goes to the following ones.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut budget = ctx.timeout_budget(3).unwrap_or_else(|| TimeoutBudget::new(FALLBACK, 3));
+/// while let Some(attempt) = budget.next_attempt() {
+///     let client = Client::builder()
+///         .connect_timeout(attempt.connect)
+///         .timeout(attempt.total)
+///         .build()?;
+///     if let Ok(response) = client.get(url).send() {
+///         return Ok(response);
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct TimeoutBudget {
+    deadline: Instant,
+    attempts_left: u32,
+    connect_share: f64,
+}
+
+/// AttemptTimeouts are the timeouts of one attempt of an outbound call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AttemptTimeouts {
+    /// Time for the whole attempt.
+    pub total: Duration,
+    /// Time for DNS resolution and connection establishment.
+    pub connect: Duration,
+    /// Time for reading the response once connected.
+    pub read: Duration,
+}
+
+impl TimeoutBudget {
+    /// Creates a budget of `available` time split across at most `attempts` attempts.
+    pub fn new(available: Duration, attempts: u32) -> Self {
+        Self {
+            deadline: Instant::now() + available,
+            attempts_left: attempts,
+            connect_share: DEFAULT_CONNECT_SHARE,
+        }
+    }
+
+    /// Sets the share of each attempt, between 0 and 1, given to DNS resolution and
+    /// connection establishment. Defaults to 0.25.
+    pub fn connect_share(mut self, share: f64) -> Self {
+        self.connect_share = share.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Returns the time left in the budget.
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// Returns the number of attempts left.
+    pub fn attempts_left(&self) -> u32 {
+        self.attempts_left
+    }
+
+    /// Returns the timeouts of the next attempt, or None once the attempts or the time are
+    /// exhausted.
+    pub fn next_attempt(&mut self) -> Option<AttemptTimeouts> {
+        let remaining = self.remaining();
+        if self.attempts_left == 0 || remaining.is_zero() {
+            return None;
+        }
+        let total = remaining / self.attempts_left;
+        self.attempts_left -= 1;
+        let connect = total.mul_f64(self.connect_share);
+        Some(AttemptTimeouts {
+            total,
+            connect,
+            read: total - connect,
+        })
+    }
+}
@@ -0,0 +1,100 @@
+//! Binary fields of payloads, encoded in base64.
+//!
+//! JSON and the other text formats have no binary type, so binary fields such as images are
+//! sent as base64 strings. Annotate a `Vec<u8>` field with
+//! `#[serde(with = "fdk::bytes_base64")]`, or declare it as `Base64Bytes`, to encode and
+//! decode it automatically. Standard base64 is written, and read with or without padding.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! #[derive(Deserialize, Serialize)]
+//! struct Thumbnail {
+//!     name: String,
+//!     #[serde(with = "fdk::bytes_base64")]
+//!     image: Vec<u8>,
+//!     mask: Option<Base64Bytes>,
+//! }
+//! ```
+
+use base64::alphabet;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+const ENGINE: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Serializes `bytes` as a base64 string.
+pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&ENGINE.encode(bytes))
+}
+
+/// Deserializes a base64 string into bytes.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    deserializer.deserialize_str(Base64Visitor)
+}
+
+struct Base64Visitor;
+
+impl Visitor<'_> for Base64Visitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a base64 string")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Vec<u8>, E> {
+        ENGINE
+            .decode(v.trim())
+            .map_err(|e| E::custom(format!("invalid base64: {}", e)))
+    }
+}
+
+/// Base64Bytes is a binary field of a payload, sent as a base64 string.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Base64Bytes(pub Vec<u8>);
+
+impl Serialize for Base64Bytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Base64Bytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize(deserializer).map(Base64Bytes)
+    }
+}
+
+impl Deref for Base64Bytes {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.0
+    }
+}
+
+impl DerefMut for Base64Bytes {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.0
+    }
+}
+
+impl From<Vec<u8>> for Base64Bytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Base64Bytes(bytes)
+    }
+}
+
+impl From<Base64Bytes> for Vec<u8> {
+    fn from(bytes: Base64Bytes) -> Self {
+        bytes.0
+    }
+}
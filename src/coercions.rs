@@ -1,8 +1,21 @@
 use crate::FunctionError;
 use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+/// Name of the root element of XML outputs without a name of their own, such as strings,
+/// sequences and maps, unless `FunctionBuilder::xml_root` sets one.
+const DEFAULT_XML_ROOT: &str = "response";
+
+static XML_ROOT: OnceLock<String> = OnceLock::new();
+
+/// Sets the name of the root element of every XML output for the lifetime of the process.
+/// Only the first call has an effect.
+pub(crate) fn set_xml_root(root: String) {
+    let _ = XML_ROOT.set(root);
+}
 
 /// ContentType represents the supported content types in the FDK.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ContentType {
     JSON,
     YAML,
@@ -12,14 +25,29 @@ pub enum ContentType {
 }
 
 impl ContentType {
+    /// Every content type the FDK can decode and encode.
+    pub const ALL: [ContentType; 5] = [
+        ContentType::JSON,
+        ContentType::YAML,
+        ContentType::XML,
+        ContentType::Plain,
+        ContentType::URLEncoded,
+    ];
+
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
+        Self::parse(s).unwrap_or(ContentType::JSON)
+    }
+
+    /// Returns the content type named by a header value, or None if it isn't supported.
+    pub fn parse(s: &str) -> Option<Self> {
         match s {
-            "application/json" => ContentType::JSON,
-            "text/yaml" | "application/yaml" => ContentType::YAML,
-            "text/xml" | "application/xml" => ContentType::XML,
-            "text/plain" => ContentType::Plain,
-            "application/x-www-form-urlencoded" => ContentType::URLEncoded,
-            _ => ContentType::JSON,
+            "application/json" => Some(ContentType::JSON),
+            "text/yaml" | "application/yaml" => Some(ContentType::YAML),
+            "text/xml" | "application/xml" => Some(ContentType::XML),
+            "text/plain" => Some(ContentType::Plain),
+            "application/x-www-form-urlencoded" => Some(ContentType::URLEncoded),
+            _ => None,
         }
     }
 
@@ -34,13 +62,64 @@ impl ContentType {
     }
 }
 
-/// An `InputCoercible` type can be generated from a `Vec<u8>`.
+/// JsonStrictness decides how JSON request bodies holding more than one value are decoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JsonStrictness {
+    /// Reject bodies with anything but whitespace after the first value.
+    #[default]
+    Strict,
+    /// Decode the first value and ignore whatever follows it.
+    FirstValue,
+}
+
+/// Converts a JSON decoding error to an error pointing to the offending field.
+fn json_field_error(e: serde_path_to_error::Error<serde_json::Error>) -> FunctionError {
+    let path = e.path().to_string();
+    let e = e.into_inner();
+    let (line, column) = (e.line(), e.column());
+    let message = e.to_string();
+    let position = format!(" at line {} column {}", line, column);
+    FunctionError::InvalidField {
+        inner: message
+            .strip_suffix(position.as_str())
+            .unwrap_or(&message)
+            .to_owned(),
+        path,
+        line,
+        column,
+        source: Some(Box::new(e)),
+    }
+}
+
+/// Returns `input` as text, or a coercion error naming the offset of the first byte that is
+/// not valid UTF-8.
+fn utf8_input<'a>(input: &'a [u8], format: &str) -> Result<&'a str, FunctionError> {
+    std::str::from_utf8(input).map_err(|e| FunctionError::Coercion {
+        inner: format!("{} input is not valid UTF-8: {}", format, e),
+        source: Some(Box::new(e)),
+    })
+}
+
+/// An `InputCoercible` type can be generated from a byte slice.
 pub trait InputCoercible: Sized {
-    fn try_decode_plain(input: Vec<u8>) -> Result<Self, FunctionError>;
-    fn try_decode_json(input: Vec<u8>) -> Result<Self, FunctionError>;
-    fn try_decode_xml(input: Vec<u8>) -> Result<Self, FunctionError>;
-    fn try_decode_yaml(input: Vec<u8>) -> Result<Self, FunctionError>;
-    fn try_decode_urlencoded(input: Vec<u8>) -> Result<Self, FunctionError>;
+    fn try_decode_plain(input: &[u8]) -> Result<Self, FunctionError>;
+    fn try_decode_json(input: &[u8]) -> Result<Self, FunctionError>;
+    /// Decodes the first JSON value of `input`, ignoring the rest. Defaults to
+    /// `try_decode_json`.
+    fn try_decode_json_first_value(input: &[u8]) -> Result<Self, FunctionError> {
+        Self::try_decode_json(input)
+    }
+    fn try_decode_xml(input: &[u8]) -> Result<Self, FunctionError>;
+    fn try_decode_yaml(input: &[u8]) -> Result<Self, FunctionError>;
+    fn try_decode_urlencoded(input: &[u8]) -> Result<Self, FunctionError>;
+    /// Decodes the input from the whole request rather than from its body alone, or returns
+    /// None to decode the body according to its content type. Defaults to None.
+    fn try_decode_request(
+        _ctx: &crate::context::RuntimeContext,
+        _input: &[u8],
+    ) -> Option<Result<Self, FunctionError>> {
+        None
+    }
 }
 
 /// An `OutputCoercible` type can be converted to a `Vec<u8>`.
@@ -50,96 +129,194 @@ pub trait OutputCoercible: Sized {
     fn try_encode_yaml(self) -> Result<Vec<u8>, FunctionError>;
     fn try_encode_plain(self) -> Result<Vec<u8>, FunctionError>;
     fn try_encode_urlencoded(self) -> Result<Vec<u8>, FunctionError>;
+    /// Encodes the output as the first of `formats` able to represent it, and returns that
+    /// format with the body, or the error of the last one. Defaults to encoding as the first
+    /// format only, as the output is consumed.
+    fn try_encode_first_of(
+        self,
+        formats: &[ContentType],
+    ) -> Result<(ContentType, Vec<u8>), FunctionError> {
+        let format = formats.first().cloned().unwrap_or(ContentType::JSON);
+        let body = crate::function::encode_body(&format, self)?;
+        Ok((format, body))
+    }
+    /// Returns the status and headers the output sets on the response, if any. Called once,
+    /// before encoding.
+    fn take_response_parts(&mut self) -> Option<(hyper::StatusCode, hyper::HeaderMap)> {
+        None
+    }
+    /// Returns the body the output streams, if any, replacing the encoded output. Called
+    /// once, after `take_response_parts`.
+    fn take_stream(&mut self) -> Option<crate::response::StreamBody> {
+        None
+    }
 }
 
 impl<T: for<'de> Deserialize<'de>> InputCoercible for T {
-    fn try_decode_plain(input: Vec<u8>) -> Result<Self, FunctionError> {
-        match serde_plain::from_str(&input.iter().map(|&v| v as char).collect::<String>()) {
-            Ok(t) => Ok(t),
-            Err(e) => Err(FunctionError::Coercion {
-                inner: e.to_string(),
-            }),
-        }
+    fn try_decode_plain(input: &[u8]) -> Result<Self, FunctionError> {
+        Ok(serde_plain::from_str(utf8_input(input, "Plain text")?)?)
     }
 
-    fn try_decode_json(input: Vec<u8>) -> Result<Self, FunctionError> {
-        match serde_json::from_slice(input.as_slice()) {
-            Ok(t) => Ok(t),
+    fn try_decode_json(input: &[u8]) -> Result<Self, FunctionError> {
+        let mut deserializer = serde_json::Deserializer::from_slice(input);
+        let t = serde_path_to_error::deserialize(&mut deserializer).map_err(json_field_error)?;
+        match deserializer.end() {
+            Ok(()) => Ok(t),
             Err(e) => Err(FunctionError::Coercion {
-                inner: e.to_string(),
+                inner: format!(
+                    "{} (strict JSON decoding rejects data after the first value, use JsonStrictness::FirstValue to ignore it)",
+                    e
+                ),
+                source: Some(Box::new(e)),
             }),
         }
     }
 
-    fn try_decode_xml(input: Vec<u8>) -> Result<Self, FunctionError> {
-        match serde_xml_rs::from_str(&input.iter().map(|&v| v as char).collect::<String>()) {
-            Ok(t) => Ok(t),
-            Err(e) => Err(FunctionError::Coercion {
-                inner: e.to_string(),
-            }),
-        }
+    fn try_decode_json_first_value(input: &[u8]) -> Result<Self, FunctionError> {
+        let mut deserializer = serde_json::Deserializer::from_slice(input);
+        serde_path_to_error::deserialize(&mut deserializer).map_err(json_field_error)
     }
 
-    fn try_decode_yaml(input: Vec<u8>) -> Result<Self, FunctionError> {
-        match serde_yaml::from_slice(input.as_slice()) {
-            Ok(t) => Ok(t),
-            Err(e) => Err(FunctionError::Coercion {
-                inner: e.to_string(),
-            }),
-        }
+    fn try_decode_xml(input: &[u8]) -> Result<Self, FunctionError> {
+        let text = utf8_input(input, "XML")?.trim_start_matches('\u{feff}');
+        Ok(quick_xml::de::from_str(text)?)
     }
 
-    fn try_decode_urlencoded(input: Vec<u8>) -> Result<Self, FunctionError> {
-        match serde_urlencoded::from_str(&input.iter().map(|&v| v as char).collect::<String>()) {
-            Ok(t) => Ok(t),
-            Err(e) => Err(FunctionError::Coercion {
-                inner: e.to_string(),
-            }),
-        }
+    fn try_decode_yaml(input: &[u8]) -> Result<Self, FunctionError> {
+        Ok(serde_yaml::from_slice(input)?)
+    }
+
+    fn try_decode_urlencoded(input: &[u8]) -> Result<Self, FunctionError> {
+        let text = utf8_input(input, "URL-encoded")?;
+        Ok(serde_urlencoded::from_str(text)?)
+    }
+}
+
+/// RawBody is a handler input holding the request body as is, whatever its content type.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RawBody(pub Vec<u8>);
+
+impl InputCoercible for RawBody {
+    fn try_decode_plain(input: &[u8]) -> Result<Self, FunctionError> {
+        Ok(Self(input.to_vec()))
+    }
+    fn try_decode_json(input: &[u8]) -> Result<Self, FunctionError> {
+        Ok(Self(input.to_vec()))
+    }
+    fn try_decode_xml(input: &[u8]) -> Result<Self, FunctionError> {
+        Ok(Self(input.to_vec()))
+    }
+    fn try_decode_yaml(input: &[u8]) -> Result<Self, FunctionError> {
+        Ok(Self(input.to_vec()))
+    }
+    fn try_decode_urlencoded(input: &[u8]) -> Result<Self, FunctionError> {
+        Ok(Self(input.to_vec()))
     }
 }
 
 impl<T: Serialize> OutputCoercible for T {
     fn try_encode_json(self) -> Result<Vec<u8>, FunctionError> {
-        match serde_json::to_vec(&self) {
-            Ok(vector) => Ok(vector),
-            Err(e) => Err(FunctionError::Coercion {
-                inner: e.to_string(),
-            }),
-        }
+        Ok(serde_json::to_vec(&self)?)
     }
+    /// Encodes the output as an element named after its type, or after the root set with
+    /// `FunctionBuilder::xml_root`, which outputs without a type name, such as strings and
+    /// maps, default to `response`. Fields renamed `@name` are written as attributes, and a
+    /// field renamed `$text` as the text of the element.
     fn try_encode_xml(self) -> Result<Vec<u8>, FunctionError> {
-        match serde_xml_rs::to_string(&self) {
-            Ok(vector) => Ok(vector.chars().map(|ch| ch as u8).collect()),
-            Err(e) => Err(FunctionError::Coercion {
-                inner: e.to_string(),
-            }),
-        }
+        let encoded = match XML_ROOT.get() {
+            Some(root) => quick_xml::se::to_string_with_root(root, &self),
+            None => quick_xml::se::to_string(&self)
+                .or_else(|_| quick_xml::se::to_string_with_root(DEFAULT_XML_ROOT, &self)),
+        };
+        Ok(encoded?.into_bytes())
     }
     fn try_encode_yaml(self) -> Result<Vec<u8>, FunctionError> {
-        match serde_yaml::to_vec(&self) {
-            Ok(vector) => Ok(vector),
-            Err(e) => Err(FunctionError::Coercion {
-                inner: e.to_string(),
-            }),
-        }
+        Ok(serde_yaml::to_vec(&self)?)
     }
 
     fn try_encode_plain(self) -> Result<Vec<u8>, FunctionError> {
-        match serde_plain::to_string(&self) {
-            Ok(vector) => Ok(vector.chars().map(|ch| ch as u8).collect()),
-            Err(e) => Err(FunctionError::Coercion {
-                inner: e.to_string(),
-            }),
-        }
+        Ok(serde_plain::to_string(&self)?.into_bytes())
     }
 
     fn try_encode_urlencoded(self) -> Result<Vec<u8>, FunctionError> {
-        match serde_urlencoded::to_string(&self) {
-            Ok(vector) => Ok(vector.chars().map(|ch| ch as u8).collect()),
-            Err(e) => Err(FunctionError::Coercion {
-                inner: e.to_string(),
-            }),
+        Ok(serde_urlencoded::to_string(&self)?.into_bytes())
+    }
+
+    fn try_encode_first_of(
+        self,
+        formats: &[ContentType],
+    ) -> Result<(ContentType, Vec<u8>), FunctionError> {
+        let mut error = None;
+        for format in formats {
+            match crate::function::encode_body(format, &self) {
+                Ok(body) => return Ok((format.clone(), body)),
+                Err(e) => error = Some(e),
+            }
+        }
+        match error {
+            Some(e) => Err(e),
+            None => crate::function::encode_body(&ContentType::JSON, self)
+                .map(|body| (ContentType::JSON, body)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const MULTIBYTE: &str = "héllo wörld ✓ 🦀";
+
+    fn coercion_message(result: Result<String, FunctionError>) -> String {
+        match result {
+            Err(FunctionError::Coercion { inner, .. }) => inner,
+            other => panic!("expected a coercion error, got {:?}", other),
         }
     }
+
+    #[test]
+    fn plain_roundtrips_multibyte_text() {
+        let decoded = String::try_decode_plain(MULTIBYTE.as_bytes()).unwrap();
+        assert_eq!(decoded, MULTIBYTE);
+        assert_eq!(decoded.try_encode_plain().unwrap(), MULTIBYTE.as_bytes());
+    }
+
+    #[test]
+    fn urlencoded_roundtrips_multibyte_text() {
+        let form: HashMap<String, String> =
+            InputCoercible::try_decode_urlencoded("name=h%C3%A9llo&emoji=🦀".as_bytes()).unwrap();
+        assert_eq!(form["name"], "héllo");
+        assert_eq!(form["emoji"], "🦀");
+        let encoded = vec![("name", MULTIBYTE)].try_encode_urlencoded().unwrap();
+        let decoded: HashMap<String, String> =
+            InputCoercible::try_decode_urlencoded(&encoded).unwrap();
+        assert_eq!(decoded["name"], MULTIBYTE);
+    }
+
+    #[test]
+    fn xml_roundtrips_multibyte_text() {
+        let encoded = MULTIBYTE.to_owned().try_encode_xml().unwrap();
+        assert_eq!(
+            String::from_utf8(encoded.clone()).unwrap(),
+            format!("<response>{}</response>", MULTIBYTE)
+        );
+        assert_eq!(String::try_decode_xml(&encoded).unwrap(), MULTIBYTE);
+    }
+
+    #[test]
+    fn invalid_utf8_reports_the_offending_byte() {
+        assert_eq!(
+            coercion_message(String::try_decode_plain(b"caf\xe9 au lait")),
+            "Plain text input is not valid UTF-8: invalid utf-8 sequence of 1 bytes from index 3"
+        );
+        assert_eq!(
+            coercion_message(String::try_decode_xml(b"<response>\xf0\x9f\xa6</response>")),
+            "XML input is not valid UTF-8: invalid utf-8 sequence of 3 bytes from index 10"
+        );
+        assert_eq!(
+            coercion_message(String::try_decode_urlencoded(b"name=h\xc3")),
+            "URL-encoded input is not valid UTF-8: incomplete utf-8 byte sequence from index 6"
+        );
+    }
 }
@@ -0,0 +1,189 @@
+use crate::body::Body;
+use crate::logging::{self, Level};
+use crate::metrics;
+use hyper::body::{Bytes, Frame, SizeHint};
+use hyper::{HeaderMap, Response, StatusCode};
+use std::convert::{Infallible, TryFrom};
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Outcome describes a completed invocation to the callbacks registered with
+/// `RuntimeContext::on_complete`.
+#[derive(Clone, Debug)]
+pub struct Outcome {
+    /// The call ID of the invocation.
+    pub call_id: String,
+    /// The status reported to the caller, from `Fn-Http-Status` when set.
+    pub status: StatusCode,
+    /// Time from receiving the request to writing the last byte of the response.
+    pub duration: Duration,
+    /// Time spent in each phase of the invocation.
+    pub phases: Phases,
+}
+
+/// Phases splits the duration of an invocation, to tell time spent waiting on the Fn agent
+/// from time spent in the handler.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Phases {
+    /// Time to receive the whole request body from the agent.
+    pub body_read: Duration,
+    /// Time spent in the handler.
+    pub handler: Duration,
+    /// Time from producing the response to writing its last byte to the agent.
+    pub response_write: Duration,
+}
+
+/// PhaseTimings collects the phases measured while the request is handled.
+#[derive(Clone, Default)]
+pub(crate) struct PhaseTimings(Arc<[AtomicU64; 2]>);
+
+impl PhaseTimings {
+    pub(crate) fn set_body_read(&self, elapsed: Duration) {
+        self.0[0].store(micros(elapsed), Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_handler(&self, elapsed: Duration) {
+        self.0[1].store(micros(elapsed), Ordering::Relaxed);
+    }
+
+    fn phases(&self, response_write: Duration) -> Phases {
+        Phases {
+            body_read: Duration::from_micros(self.0[0].load(Ordering::Relaxed)),
+            handler: Duration::from_micros(self.0[1].load(Ordering::Relaxed)),
+            response_write,
+        }
+    }
+}
+
+fn micros(duration: Duration) -> u64 {
+    u64::try_from(duration.as_micros()).unwrap_or(u64::MAX)
+}
+
+pub(crate) type CompletionCallback = Box<dyn FnOnce(&Outcome) + Send>;
+
+/// CompletionCallbacks are the callbacks registered by an invocation, shared between its
+/// context and the response writer.
+#[derive(Clone, Default)]
+pub(crate) struct CompletionCallbacks(Arc<Mutex<Vec<CompletionCallback>>>);
+
+impl CompletionCallbacks {
+    pub(crate) fn push(&self, callback: CompletionCallback) {
+        if let Ok(mut callbacks) = self.0.lock() {
+            callbacks.push(callback);
+        }
+    }
+
+    fn take(&self) -> Vec<CompletionCallback> {
+        self.0
+            .lock()
+            .map(|mut callbacks| std::mem::take(&mut *callbacks))
+            .unwrap_or_default()
+    }
+}
+
+/// CompletionBody is a response body running the completion callbacks of the invocation once
+/// hyper is done writing it.
+pub(crate) struct CompletionBody {
+    inner: Body,
+    callbacks: CompletionCallbacks,
+    call_id: String,
+    status: StatusCode,
+    start: Instant,
+    timings: Option<PhaseTimings>,
+    produced: Instant,
+}
+
+/// Wraps the body of `response` to run `callbacks` once it is written. With `timings`, the
+/// phases of the invocation are recorded and logged at debug level once it is written.
+pub(crate) fn on_completion(
+    response: Response<Body>,
+    callbacks: CompletionCallbacks,
+    call_id: String,
+    start: Instant,
+    timings: Option<PhaseTimings>,
+) -> Response<CompletionBody> {
+    let status = reported_status(response.status(), response.headers());
+    response.map(|inner| CompletionBody {
+        inner,
+        callbacks,
+        call_id,
+        status,
+        start,
+        timings,
+        produced: Instant::now(),
+    })
+}
+
+pub(crate) fn reported_status(status: StatusCode, headers: &HeaderMap) -> StatusCode {
+    headers
+        .get("Fn-Http-Status")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u16>().ok())
+        .and_then(|v| StatusCode::from_u16(v).ok())
+        .unwrap_or(status)
+}
+
+impl hyper::body::Body for CompletionBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for CompletionBody {
+    fn drop(&mut self) {
+        let phases = match &self.timings {
+            Some(timings) => {
+                let phases = timings.phases(self.produced.elapsed());
+                metrics::global().record_phases(&phases);
+                if logging::enabled(Level::Debug) {
+                    logging::log(
+                        Level::Debug,
+                        format_args!(
+                            "call {}: {} in {:?} (body read {:?}, handler {:?}, response write {:?})",
+                            self.call_id,
+                            self.status.as_u16(),
+                            self.start.elapsed(),
+                            phases.body_read,
+                            phases.handler,
+                            phases.response_write
+                        ),
+                    );
+                }
+                phases
+            }
+            None => Phases::default(),
+        };
+        let callbacks = self.callbacks.take();
+        if callbacks.is_empty() {
+            return;
+        }
+        let outcome = Outcome {
+            call_id: std::mem::take(&mut self.call_id),
+            status: self.status,
+            duration: self.start.elapsed(),
+            phases,
+        };
+        for callback in callbacks {
+            // A panicking callback must not take the connection down with it.
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| callback(&outcome)));
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use crate::errors::FunctionError;
+use hyper::body::Bytes;
+use std::io::Read;
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+use std::io::Write;
+#[cfg(feature = "zstd")]
+use std::sync::Arc;
+
+/// UnsupportedEncoding is the source of the error failing invocations whose request body is
+/// sent with a content coding the FDK cannot decode.
+#[derive(Debug)]
+pub(crate) struct UnsupportedEncoding(String);
+
+impl std::fmt::Display for UnsupportedEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unsupported content coding {}", self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedEncoding {}
+
+/// Returns true if `e` was returned because the request Content-Encoding is not supported.
+pub(crate) fn is_unsupported_encoding(e: &FunctionError) -> bool {
+    std::error::Error::source(e).is_some_and(|source| source.is::<UnsupportedEncoding>())
+}
+
+/// Response encodings, in order of preference among those of equal quality.
+const ENCODINGS: &[&str] = &[
+    #[cfg(feature = "zstd")]
+    "zstd",
+    #[cfg(feature = "brotli")]
+    "br",
+    #[cfg(feature = "gzip")]
+    "gzip",
+    #[cfg(feature = "gzip")]
+    "deflate",
+];
+
+/// CompressionConfig configures the compression of response bodies. Request bodies with a
+/// supported `Content-Encoding` are decompressed regardless.
+#[derive(Clone, Debug)]
+pub struct CompressionConfig {
+    /// Response bodies smaller than this many bytes are sent uncompressed.
+    pub min_size: usize,
+    /// The zstd compression level.
+    #[cfg(feature = "zstd")]
+    pub zstd_level: i32,
+    /// A pre-trained zstd dictionary shared with the clients. Defaults to the contents of the
+    /// file at the path set in `FN_ZSTD_DICTIONARY`, if any.
+    #[cfg(feature = "zstd")]
+    pub zstd_dictionary: Option<Arc<Vec<u8>>>,
+    /// The gzip and deflate compression level, from 0 to 9.
+    #[cfg(feature = "gzip")]
+    pub gzip_level: u32,
+    /// The brotli quality, from 0 to 11.
+    #[cfg(feature = "brotli")]
+    pub brotli_quality: u32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            min_size: 256,
+            #[cfg(feature = "zstd")]
+            zstd_level: 3,
+            #[cfg(feature = "zstd")]
+            zstd_dictionary: None,
+            #[cfg(feature = "gzip")]
+            gzip_level: 6,
+            #[cfg(feature = "brotli")]
+            brotli_quality: 5,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Loads the dictionary set in `FN_ZSTD_DICTIONARY` unless one is configured already.
+    pub(crate) fn load_dictionary_from_env(&mut self) -> Result<(), FunctionError> {
+        #[cfg(feature = "zstd")]
+        if self.zstd_dictionary.is_none() {
+            if let Some(path) = crate::context::CONFIG_FROM_ENV.get("FN_ZSTD_DICTIONARY") {
+                let dictionary =
+                    std::fs::read(path).map_err(|e| FunctionError::Initialization {
+                        inner: format!("Failed to read FN_ZSTD_DICTIONARY {}: {}", path, e),
+                        source: Some(Box::new(e)),
+                    })?;
+                self.zstd_dictionary = Some(Arc::new(dictionary));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "zstd")]
+    fn dictionary(&self) -> &[u8] {
+        self.zstd_dictionary.as_deref().map_or(&[], Vec::as_slice)
+    }
+
+    /// Decodes a request body sent with `content_encoding`, failing with a coercion error if
+    /// the decoded body exceeds `limit`.
+    pub(crate) fn decode_request(
+        &self,
+        content_encoding: Option<&str>,
+        body: Bytes,
+        limit: Option<usize>,
+    ) -> Result<Bytes, FunctionError> {
+        let encoding = match content_encoding.map(str::trim) {
+            None | Some("") | Some("identity") => return Ok(body),
+            Some(encoding) => encoding.to_ascii_lowercase(),
+        };
+        let invalid = |e: std::io::Error| FunctionError::InvalidInput {
+            inner: format!("Failed to decompress {} request body: {}", encoding, e),
+            source: Some(Box::new(e)),
+        };
+        match encoding.as_str() {
+            #[cfg(feature = "zstd")]
+            "zstd" => {
+                let decoder = zstd::stream::read::Decoder::with_dictionary(
+                    std::io::BufReader::new(body.as_ref()),
+                    self.dictionary(),
+                )
+                .map_err(invalid)?;
+                read_decoded(decoder, limit, invalid)
+            }
+            #[cfg(feature = "gzip")]
+            "gzip" | "x-gzip" => {
+                let decoder = flate2::read::MultiGzDecoder::new(body.as_ref());
+                read_decoded(decoder, limit, invalid)
+            }
+            // The deflate content coding is the zlib format, but some clients send raw
+            // deflate data.
+            #[cfg(feature = "gzip")]
+            "deflate" if is_zlib(&body) => {
+                let decoder = flate2::read::ZlibDecoder::new(body.as_ref());
+                read_decoded(decoder, limit, invalid)
+            }
+            #[cfg(feature = "gzip")]
+            "deflate" => {
+                let decoder = flate2::read::DeflateDecoder::new(body.as_ref());
+                read_decoded(decoder, limit, invalid)
+            }
+            #[cfg(feature = "brotli")]
+            "br" => {
+                let decoder = brotli::Decompressor::new(body.as_ref(), 4096);
+                read_decoded(decoder, limit, invalid)
+            }
+            _ => Err(FunctionError::InvalidInput {
+                inner: format!("Unsupported request Content-Encoding: {}", encoding),
+                source: Some(Box::new(UnsupportedEncoding(encoding))),
+            }),
+        }
+    }
+
+    /// Compresses `body` with the preferred encoding of `accept_encoding`. Returns the body
+    /// unchanged, with no encoding, if the client accepts none or the body is small.
+    pub(crate) fn encode_response(
+        &self,
+        accept_encoding: Option<&str>,
+        body: Vec<u8>,
+    ) -> (Vec<u8>, Option<&'static str>) {
+        if body.len() < self.min_size {
+            return (body, None);
+        }
+        let encoding = match preferred_encoding(accept_encoding) {
+            Some(encoding) => encoding,
+            None => return (body, None),
+        };
+        match self.compress(encoding, &body) {
+            Ok(compressed) if compressed.len() < body.len() => (compressed, Some(encoding)),
+            _ => (body, None),
+        }
+    }
+
+    fn compress(&self, encoding: &str, body: &[u8]) -> std::io::Result<Vec<u8>> {
+        match encoding {
+            #[cfg(feature = "zstd")]
+            "zstd" => zstd::bulk::Compressor::with_dictionary(self.zstd_level, self.dictionary())
+                .and_then(|mut compressor| compressor.compress(body)),
+            #[cfg(feature = "brotli")]
+            "br" => {
+                let mut compressed = Vec::new();
+                {
+                    let mut writer = brotli::CompressorWriter::new(
+                        &mut compressed,
+                        4096,
+                        self.brotli_quality,
+                        22,
+                    );
+                    writer.write_all(body)?;
+                }
+                Ok(compressed)
+            }
+            #[cfg(feature = "gzip")]
+            "gzip" => {
+                let level = flate2::Compression::new(self.gzip_level);
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), level);
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+            // The deflate content coding is the zlib format.
+            #[cfg(feature = "gzip")]
+            "deflate" => {
+                let level = flate2::Compression::new(self.gzip_level);
+                let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), level);
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+            _ => Err(std::io::ErrorKind::Unsupported.into()),
+        }
+    }
+}
+
+/// Reads a decoded request body, failing with a coercion error if it exceeds `limit`.
+fn read_decoded<R: Read>(
+    mut decoder: R,
+    limit: Option<usize>,
+    invalid: impl Fn(std::io::Error) -> FunctionError,
+) -> Result<Bytes, FunctionError> {
+    let mut decoded = Vec::new();
+    match limit {
+        // Read one byte past the limit to tell a body of exactly `limit` bytes from a larger
+        // one.
+        Some(limit) => decoder
+            .by_ref()
+            .take(limit as u64 + 1)
+            .read_to_end(&mut decoded),
+        None => decoder.read_to_end(&mut decoded),
+    }
+    .map_err(invalid)?;
+    if let Some(limit) = limit.filter(|limit| decoded.len() > *limit) {
+        return Err(crate::limits::body_too_large(limit));
+    }
+    Ok(Bytes::from(decoded))
+}
+
+/// Returns true if `body` starts with a zlib header.
+#[cfg(feature = "gzip")]
+fn is_zlib(body: &[u8]) -> bool {
+    match body {
+        [cmf, flg, ..] => cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0,
+        _ => false,
+    }
+}
+
+/// Returns the supported encoding of highest quality in the `Accept-Encoding` value, if any.
+fn preferred_encoding(accept_encoding: Option<&str>) -> Option<&'static str> {
+    let value = accept_encoding?;
+    let mut preferred = None;
+    let mut best = 0.0;
+    for encoding in ENCODINGS {
+        let quality = quality(value, encoding);
+        if quality > best {
+            preferred = Some(*encoding);
+            best = quality;
+        }
+    }
+    preferred
+}
+
+/// Returns the quality the `Accept-Encoding` value gives to `encoding`, from its own entry or
+/// else from `*`, or zero if it lists neither.
+fn quality(accept_encoding: &str, encoding: &str) -> f32 {
+    let mut wildcard = 0.0;
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';').map(str::trim);
+        let name = parts.next().unwrap_or("");
+        let quality = parts
+            .find_map(|p| p.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(encoding) {
+            return quality;
+        }
+        if name == "*" {
+            wildcard = quality;
+        }
+    }
+    wildcard
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quality_is_read_from_the_entry_or_the_wildcard() {
+        assert_eq!(quality("gzip", "gzip"), 1.0);
+        assert_eq!(quality("br;q=0.5, GZIP ; q=0.8", "gzip"), 0.8);
+        assert_eq!(quality("gzip;q=0", "gzip"), 0.0);
+        assert_eq!(quality("*;q=0.3, gzip;q=0.7", "br"), 0.3);
+        assert_eq!(quality("*;q=0.3, gzip;q=0", "gzip"), 0.0);
+        assert_eq!(quality("identity", "gzip"), 0.0);
+        assert_eq!(quality("gzip;q=high", "gzip"), 1.0);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn encoding_of_highest_quality_is_preferred() {
+        assert_eq!(
+            preferred_encoding(Some("gzip;q=0.5, deflate")),
+            Some("deflate")
+        );
+        assert_eq!(
+            preferred_encoding(Some("deflate;q=0.5, gzip")),
+            Some("gzip")
+        );
+        assert_eq!(
+            preferred_encoding(Some("gzip;q=0, deflate;q=0.1")),
+            Some("deflate")
+        );
+        assert_eq!(preferred_encoding(Some("*;q=0")), None);
+        assert_eq!(preferred_encoding(Some("gzip;q=0, deflate;q=0")), None);
+        assert_eq!(preferred_encoding(None), None);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn identity_only_requests_are_not_compressed() {
+        let body = b"compressible ".repeat(100);
+        for accept_encoding in ["identity", "identity;q=1, *;q=0", ""] {
+            let (sent, encoding) =
+                CompressionConfig::default().encode_response(Some(accept_encoding), body.clone());
+            assert_eq!(encoding, None, "{:?}", accept_encoding);
+            assert_eq!(sent, body);
+        }
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn bodies_below_the_minimum_size_are_not_compressed() {
+        let config = CompressionConfig {
+            min_size: 64,
+            ..CompressionConfig::default()
+        };
+        let small = vec![b'a'; 63];
+        assert_eq!(
+            config.encode_response(Some("gzip"), small.clone()),
+            (small, None)
+        );
+
+        let (sent, encoding) = config.encode_response(Some("gzip"), vec![b'a'; 64]);
+        assert_eq!(encoding, Some("gzip"));
+        let decoded = config
+            .decode_request(encoding, Bytes::from(sent), None)
+            .unwrap();
+        assert_eq!(&decoded[..], &[b'a'; 64][..]);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn incompressible_bodies_are_sent_as_they_are() {
+        let body: Vec<u8> = (0..=255).collect();
+        let (sent, encoding) =
+            CompressionConfig::default().encode_response(Some("gzip"), body.clone());
+        assert_eq!(encoding, None);
+        assert_eq!(sent, body);
+    }
+
+    #[test]
+    fn unknown_request_encodings_are_rejected() {
+        let e = CompressionConfig::default()
+            .decode_request(Some("compress"), Bytes::from_static(b"x"), None)
+            .unwrap_err();
+        assert!(is_unsupported_encoding(&e));
+        let identity = CompressionConfig::default()
+            .decode_request(Some(" identity "), Bytes::from_static(b"x"), None)
+            .unwrap();
+        assert_eq!(&identity[..], b"x");
+    }
+}
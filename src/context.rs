@@ -1,25 +1,42 @@
+use crate::budget::{TimeoutBudget, DEFAULT_BUDGET_SHARE};
 use crate::coercions::ContentType;
+use crate::completion::{CompletionCallbacks, Outcome};
+use crate::cookie::Cookie;
 use crate::errors::FunctionError;
+use crate::logging::{self, LogOutput, LogWriter};
+use crate::oci::OciHeaders;
+use crate::temp_dir::InvocationDir;
+use crate::trace::TraceContext;
 use hyper::{
     header::CONTENT_TYPE,
     header::{HeaderName, HeaderValue},
+    http::Extensions,
     HeaderMap, StatusCode,
 };
 use lazy_static::lazy_static;
+use serde::de::DeserializeOwned;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 
 lazy_static! {
-    pub static ref CONFIG_FROM_ENV: Arc<HashMap<String, String>> = Arc::from(
-        std::env::vars()
-            .filter(|(_, v)| !v.as_bytes().is_empty())
-            .fold(HashMap::new(), |mut m, k| {
-                m.insert(k.0, k.1);
-                m
-            })
-    );
+    pub static ref CONFIG_FROM_ENV: Arc<HashMap<String, String>> = {
+        let mut config =
+            std::env::vars()
+                .filter(|(_, v)| !v.is_empty())
+                .fold(HashMap::new(), |mut m, k| {
+                    m.insert(k.0, k.1);
+                    m
+                });
+        crate::migration::apply_renames(&mut config);
+        Arc::from(config)
+    };
 }
 
 #[derive(Clone)]
@@ -29,13 +46,168 @@ lazy_static! {
 pub struct RuntimeContext {
     config: Arc<HashMap<String, String>>,
     headers: HeaderMap,
+    trailers: HeaderMap,
+    http_intent: bool,
     method: Option<hyper::Method>,
     content_type: ContentType,
     accept_type: ContentType,
     uri: Option<hyper::Uri>,
     call_id: String,
+    frame_line: Option<String>,
     response_headers: HeaderMap,
+    response_content_type: Option<HeaderValue>,
     response_status_code: Option<StatusCode>,
+    redirected: bool,
+    deadline: Option<SystemTime>,
+    cancellation_token: CancellationToken,
+    oci: OciHeaders,
+    trace: TraceContext,
+    completion: CompletionCallbacks,
+    temp_dir: Option<Arc<InvocationDir>>,
+    extensions: Extensions,
+    #[cfg(feature = "oci-events")]
+    body: hyper::body::Bytes,
+}
+
+/// DeadlineTimer cancels a context's token when the deadline is reached. Dropping it disarms
+/// the timer.
+pub(crate) struct DeadlineTimer(JoinHandle<()>);
+
+impl Drop for DeadlineTimer {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Parses the RFC 3339 timestamp the Fn agent sends in the `Fn-Deadline` header.
+fn parse_deadline(v: Option<&HeaderValue>) -> Option<SystemTime> {
+    let value = v?.to_str().ok()?;
+    let deadline = chrono::DateTime::parse_from_rfc3339(value).ok()?;
+    Some(SystemTime::from(deadline))
+}
+
+/// Returns true if the invocation came through an HTTP trigger, whose client only sees the
+/// response headers prefixed with `Fn-Http-H-`.
+pub(crate) fn is_http_intent(headers: &HeaderMap) -> bool {
+    headers
+        .get("Fn-Intent")
+        .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"httprequest"))
+}
+
+/// Returns the name under which a response header reaches the client of an HTTP trigger.
+/// Content-Type and the headers of the Fn protocol are left alone.
+fn http_response_header_name(name: &str) -> Cow<'_, str> {
+    if name.eq_ignore_ascii_case(CONTENT_TYPE.as_str())
+        || name.len() >= 3 && name[..3].eq_ignore_ascii_case("fn-")
+    {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(format!("Fn-Http-H-{}", name))
+    }
+}
+
+/// Validates a response header added by the handler.
+fn parse_response_header(
+    key: &str,
+    value: &str,
+) -> Result<(HeaderName, HeaderValue), FunctionError> {
+    let invalid = |e: &dyn std::fmt::Display| FunctionError::InvalidInput {
+        inner: format!("Invalid response header {}: {}", key, e),
+        source: None,
+    };
+    let name = HeaderName::from_str(key).map_err(|e| invalid(&e))?;
+    let value = HeaderValue::from_str(value).map_err(|e| invalid(&e))?;
+    Ok((name, value))
+}
+
+/// Parses a duration such as `500ms`, `30s`, `5m` or `1h`. A bare number is in seconds.
+fn parse_duration(v: &str) -> Option<Duration> {
+    let v = v.trim();
+    let split = v
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(v.len());
+    let (number, unit) = v.split_at(split);
+    let number = number.parse::<f64>().ok()?;
+    let seconds = match unit.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// Deserializes the config variables starting with `prefix`, ignoring case, into a `T` whose
+/// fields are named after the rest of the variable names in lowercase. Variables set both with
+/// and without the `FN_` prefix take the value set without it.
+pub(crate) fn deserialize_config<T: DeserializeOwned>(
+    config: &HashMap<String, String>,
+    prefix: &str,
+) -> Result<T, FunctionError> {
+    let mut fields = HashMap::new();
+    for (key, value) in config {
+        let key = key.to_ascii_lowercase();
+        let field = match key.strip_prefix(&prefix.to_ascii_lowercase()) {
+            Some(field) => field,
+            None => continue,
+        };
+        match field.strip_prefix("fn_") {
+            Some(field) => {
+                fields
+                    .entry(field.to_owned())
+                    .or_insert_with(|| value.clone());
+            }
+            None => {
+                fields.insert(field.to_owned(), value.clone());
+            }
+        }
+    }
+    envy::from_iter(fields).map_err(|e| FunctionError::Initialization {
+        inner: format!("Invalid function config: {}", e),
+        source: Some(Box::new(e)),
+    })
+}
+
+/// Returns an invalid input error if `Fn-Http-Method` or `Fn-Http-Request-Url` is set but
+/// cannot be parsed, or if a forwarded `Fn-Http-H-` header has no name, so that the
+/// invocation is rejected rather than served without them.
+pub(crate) fn check_http_headers(headers: &HeaderMap) -> Result<(), FunctionError> {
+    if headers.contains_key("Fn-Http-H-") {
+        return Err(FunctionError::InvalidInput {
+            inner: "Invalid Fn-Http-H- header without a name".to_owned(),
+            source: None,
+        });
+    }
+    let invalid = |name: &str, value: &HeaderValue| FunctionError::InvalidInput {
+        inner: format!(
+            "Invalid {} header: {:?}",
+            name,
+            String::from_utf8_lossy(value.as_bytes())
+        ),
+        source: None,
+    };
+    if let Some(value) = headers.get("Fn-Http-Method") {
+        if parse_method(headers).is_none() {
+            return Err(invalid("Fn-Http-Method", value));
+        }
+    }
+    if let Some(value) = headers.get("Fn-Http-Request-Url") {
+        if parse_request_url(headers).is_none() {
+            return Err(invalid("Fn-Http-Request-Url", value));
+        }
+    }
+    Ok(())
+}
+
+/// Parses the method of HTTP invocations from `Fn-Http-Method`.
+fn parse_method(headers: &HeaderMap) -> Option<hyper::Method> {
+    hyper::Method::from_bytes(headers.get("Fn-Http-Method")?.as_bytes()).ok()
+}
+
+/// Parses the URL of HTTP invocations from `Fn-Http-Request-Url`.
+fn parse_request_url(headers: &HeaderMap) -> Option<hyper::Uri> {
+    hyper::Uri::try_from(headers.get("Fn-Http-Request-Url")?.as_bytes()).ok()
 }
 
 fn resolve_content_type(v: Option<&hyper::header::HeaderValue>) -> ContentType {
@@ -46,32 +218,225 @@ fn resolve_content_type(v: Option<&hyper::header::HeaderValue>) -> ContentType {
 }
 
 fn get_accept_header_value(headers: &hyper::HeaderMap) -> Option<&HeaderValue> {
-    if headers.get("Fn-Http-H-Accept").is_some() {
-        headers.get("Fn-Http-H-Accept")
-    } else if headers.get(hyper::header::ACCEPT).is_some() {
-        headers.get(hyper::header::ACCEPT)
+    accept_header(headers).map(|(_, v)| v)
+}
+
+/// Returns the header the response content type is negotiated from, with its name.
+fn accept_header(headers: &hyper::HeaderMap) -> Option<(&'static str, &HeaderValue)> {
+    if let Some(v) = headers.get("Fn-Http-H-Accept") {
+        Some(("Fn-Http-H-Accept", v))
     } else {
-        None
+        headers.get(hyper::header::ACCEPT).map(|v| ("Accept", v))
+    }
+}
+
+/// Explains why a content type was picked from a header, for debug logs.
+fn describe_choice(header: Option<(&str, &HeaderValue)>, chosen: &ContentType) -> String {
+    match header {
+        None => format!(
+            "no header present, defaulting to {}",
+            chosen.as_header_value()
+        ),
+        Some((name, value)) => {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            if ContentType::parse(&value).is_some() {
+                format!("{}: {}", name, value)
+            } else {
+                format!(
+                    "unsupported {}: {}, falling back to {}",
+                    name,
+                    value,
+                    chosen.as_header_value()
+                )
+            }
+        }
+    }
+}
+
+/// Describes how the request and response content types were chosen from `headers`.
+pub(crate) fn describe_coercion_choices(
+    headers: &hyper::HeaderMap,
+    content_type: &ContentType,
+    accept_type: &ContentType,
+) -> (String, String) {
+    (
+        describe_choice(
+            headers.get(CONTENT_TYPE).map(|v| ("Content-Type", v)),
+            content_type,
+        ),
+        describe_choice(accept_header(headers), accept_type),
+    )
+}
+
+/// RuntimeContextBuilder builds a `RuntimeContext` without a request or the environment, to
+/// unit test handler logic deterministically.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut ctx = RuntimeContext::builder()
+///     .config("GREETING", "Hello")
+///     .header("Fn-Http-H-Authorization", "Bearer token")
+///     .call_id("01TEST")
+///     .build();
+/// assert_eq!(handler(&mut ctx, input)?, expected);
+/// ```
+#[derive(Default)]
+pub struct RuntimeContextBuilder {
+    config: HashMap<String, String>,
+    headers: HeaderMap,
+    trailers: HeaderMap,
+    method: Option<hyper::Method>,
+    uri: Option<hyper::Uri>,
+    content_type: Option<ContentType>,
+    accept_type: Option<ContentType>,
+    call_id: String,
+    deadline: Option<SystemTime>,
+    extensions: Extensions,
+    #[cfg(feature = "oci-events")]
+    body: hyper::body::Bytes,
+}
+
+impl RuntimeContextBuilder {
+    /// Sets a config variable. The context sees no other config.
+    pub fn config(mut self, key: &str, value: &str) -> Self {
+        self.config.insert(key.to_owned(), value.to_owned());
+        self
+    }
+
+    /// Adds a request header.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` or `value` is not a valid header name or value.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        let name = HeaderName::from_str(name).expect("invalid header name");
+        let value = HeaderValue::from_str(value).expect("invalid header value");
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Adds a request trailer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` or `value` is not a valid header name or value.
+    pub fn trailer(mut self, name: &str, value: &str) -> Self {
+        let name = HeaderName::from_str(name).expect("invalid trailer name");
+        let value = HeaderValue::from_str(value).expect("invalid trailer value");
+        self.trailers.append(name, value);
+        self
+    }
+
+    /// Sets the content type of the request. Defaults to the one of the `Content-Type` header,
+    /// or JSON.
+    pub fn content_type(mut self, content_type: ContentType) -> Self {
+        self.content_type = Some(content_type);
+        self
+    }
+
+    /// Sets the content type accepted for the response. Defaults to the one of the `Accept`
+    /// header, or JSON.
+    pub fn accept_type(mut self, accept_type: ContentType) -> Self {
+        self.accept_type = Some(accept_type);
+        self
+    }
+
+    /// Adds an extension, e.g. the data a middleware would attach to the context.
+    pub fn extension<T: Clone + Send + Sync + 'static>(mut self, value: T) -> Self {
+        self.extensions.insert(value);
+        self
+    }
+
+    /// Sets the request body read by `RuntimeContext::as_oci_event`.
+    #[cfg(feature = "oci-events")]
+    pub fn body<B: Into<hyper::body::Bytes>>(mut self, body: B) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Sets the call ID.
+    pub fn call_id(mut self, call_id: &str) -> Self {
+        self.call_id = call_id.to_owned();
+        self
+    }
+
+    /// Sets the HTTP method of the request. Defaults to the one of the `Fn-Http-Method`
+    /// header.
+    pub fn method(mut self, method: hyper::Method) -> Self {
+        self.method = Some(method);
+        self
+    }
+
+    /// Sets the URL of the request. Defaults to the one of the `Fn-Http-Request-Url` header.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `url` is not a valid URI.
+    pub fn request_url(mut self, url: &str) -> Self {
+        self.uri = Some(hyper::Uri::try_from(url).expect("invalid request URL"));
+        self
+    }
+
+    /// Sets the deadline of the invocation.
+    pub fn deadline(mut self, deadline: SystemTime) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Builds the context.
+    pub fn build(self) -> RuntimeContext {
+        let headers = self.headers;
+        let config = Arc::new(self.config);
+        RuntimeContext {
+            content_type: self
+                .content_type
+                .unwrap_or_else(|| resolve_content_type(headers.get(CONTENT_TYPE))),
+            accept_type: self
+                .accept_type
+                .unwrap_or_else(|| resolve_content_type(get_accept_header_value(&headers))),
+            trailers: self.trailers,
+            http_intent: is_http_intent(&headers),
+            method: self.method.or_else(|| parse_method(&headers)),
+            uri: self.uri.or_else(|| parse_request_url(&headers)),
+            call_id: self.call_id,
+            frame_line: logging::frame_line(&headers),
+            response_headers: HeaderMap::new(),
+            response_content_type: None,
+            response_status_code: None,
+            redirected: false,
+            deadline: self.deadline,
+            cancellation_token: CancellationToken::new(),
+            oci: OciHeaders::from_headers(&headers),
+            trace: TraceContext::from_headers(&headers, &config),
+            completion: CompletionCallbacks::default(),
+            temp_dir: None,
+            extensions: self.extensions,
+            #[cfg(feature = "oci-events")]
+            body: self.body,
+            config,
+            headers,
+        }
     }
 }
 
 impl RuntimeContext {
+    /// Returns a builder of contexts for unit tests.
+    pub fn builder() -> RuntimeContextBuilder {
+        RuntimeContextBuilder::default()
+    }
+
     /// from_req creates a RuntimeContext from a hyper Request reference.
     pub fn from_req<T>(req: &hyper::Request<T>) -> Self {
+        let http_intent = is_http_intent(req.headers());
         let headers = {
-            let fn_intent = req
-                .headers()
-                .get("Fn-Intent")
-                .map(|value| value.to_str().unwrap())
-                .unwrap_or_else(|| "");
-
-            if fn_intent == "httprequest" {
+            if http_intent {
                 req.headers()
                     .iter()
-                    .filter(|(k, _v)| *k == CONTENT_TYPE || k.as_str().starts_with("Fn-Http-H-"))
+                    .filter(|(k, _v)| *k == CONTENT_TYPE || k.as_str().starts_with("fn-http-h-"))
                     .map(|(k, v)| (k, v.to_owned()))
                     .fold(HeaderMap::new(), |mut m, (k, v)| {
-                        m.insert(k, v);
+                        m.append(k, v);
                         m
                     })
             } else {
@@ -79,45 +444,60 @@ impl RuntimeContext {
             }
         };
 
+        let method = parse_method(req.headers());
+        let uri = parse_request_url(req.headers());
+        let call_id = req
+            .headers()
+            .get("Fn-Call-Id")
+            .map(|v| v.to_str().unwrap_or_default())
+            .unwrap_or_default()
+            .to_owned();
+
         Self {
             config: CONFIG_FROM_ENV.clone(),
-            headers: headers.clone(),
-            method: headers
-                .get("Fn-Http-Method")
-                .map(|value| hyper::Method::try_from(value.to_str().unwrap()).unwrap()),
+            headers,
+            trailers: HeaderMap::new(),
+            http_intent,
+            method,
             content_type: resolve_content_type(req.headers().get(CONTENT_TYPE)),
             accept_type: resolve_content_type(get_accept_header_value(req.headers())),
-            uri: headers
-                .get("Fn-Http-Request-Url")
-                .map(|value| hyper::Uri::try_from(value.to_str().unwrap()).unwrap()),
-            call_id: headers
-                .get("Fn-Call-Id")
-                .map(|v| v.to_str().unwrap_or_default())
-                .unwrap_or_default()
-                .to_owned(),
+            uri,
+            call_id,
+            frame_line: logging::frame_line(req.headers()),
             response_headers: HeaderMap::new(),
+            response_content_type: None,
             response_status_code: None,
+            redirected: false,
+            deadline: parse_deadline(req.headers().get("Fn-Deadline")),
+            cancellation_token: CancellationToken::new(),
+            oci: OciHeaders::from_headers(req.headers()),
+            trace: TraceContext::from_headers(req.headers(), &CONFIG_FROM_ENV),
+            completion: CompletionCallbacks::default(),
+            temp_dir: None,
+            extensions: Extensions::new(),
+            #[cfg(feature = "oci-events")]
+            body: hyper::body::Bytes::new(),
         }
     }
 
     /// Returns the app ID
     pub fn app_id(&self) -> String {
-        return (self.config.get("FN_APP_ID").unwrap_or(&String::default())).to_string();
+        (self.config.get("FN_APP_ID").unwrap_or(&String::default())).to_string()
     }
 
     /// Returns the function ID
     pub fn function_id(&self) -> String {
-        return (self.config.get("FN_FN_ID").unwrap_or(&String::default())).to_string();
+        (self.config.get("FN_FN_ID").unwrap_or(&String::default())).to_string()
     }
 
     /// Returns the app name
     pub fn app_name(&self) -> String {
-        return (self.config.get("FN_APP_NAME").unwrap_or(&String::default())).to_string();
+        (self.config.get("FN_APP_NAME").unwrap_or(&String::default())).to_string()
     }
 
     /// Returns the function name
     pub fn function_name(&self) -> String {
-        return (self.config.get("FN_FN_NAME").unwrap_or(&String::default())).to_string();
+        (self.config.get("FN_FN_NAME").unwrap_or(&String::default())).to_string()
     }
 
     /// Returns the `Content-Type` header from request. This header is used to choose a deserializer for request body.
@@ -135,11 +515,314 @@ impl RuntimeContext {
         self.call_id.clone()
     }
 
-    /// Returns request headers
+    /// Returns the call ID without copying it.
+    pub fn call_id_ref(&self) -> &str {
+        &self.call_id
+    }
+
+    /// Returns the method of HTTP invocations, from the `Fn-Http-Method` header.
+    pub fn method(&self) -> Option<&hyper::Method> {
+        self.method.as_ref()
+    }
+
+    /// Returns the URL of HTTP invocations as received by the trigger, from the
+    /// `Fn-Http-Request-Url` header.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// match (ctx.method(), ctx.request_url().map(|url| url.path())) {
+    ///     (Some(&Method::GET), Some("/health")) => Ok(health()),
+    ///     _ => Err(FunctionError::new_user_error("unknown route".into())),
+    /// }
+    /// ```
+    pub fn request_url(&self) -> Option<&hyper::Uri> {
+        self.uri.as_ref()
+    }
+
+    /// Returns the query parameters of the request URL, decoded. The last value wins when a
+    /// parameter is repeated.
+    pub fn query_params(&self) -> HashMap<String, String> {
+        url::form_urlencoded::parse(self.query_string().as_bytes())
+            .into_owned()
+            .collect()
+    }
+
+    /// Deserializes the query parameters of the request URL into `T`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// #[derive(Deserialize)]
+    /// struct Page {
+    ///     offset: usize,
+    ///     limit: Option<usize>,
+    /// }
+    ///
+    /// let page: Page = ctx.query()?;
+    /// ```
+    pub fn query<T: DeserializeOwned>(&self) -> Result<T, FunctionError> {
+        serde_urlencoded::from_str(self.query_string()).map_err(|e| FunctionError::InvalidInput {
+            inner: format!("Invalid query string: {}", e),
+            source: Some(Box::new(e)),
+        })
+    }
+
+    fn query_string(&self) -> &str {
+        self.uri.as_ref().and_then(hyper::Uri::query).unwrap_or("")
+    }
+
+    /// Returns the point in time after which the Fn agent will cut the invocation off, as sent
+    /// in the `Fn-Deadline` header.
+    pub fn deadline(&self) -> Option<SystemTime> {
+        self.deadline
+    }
+
+    /// Returns the time left until the deadline, or zero if it has already passed.
+    pub fn time_remaining(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| {
+            deadline
+                .duration_since(SystemTime::now())
+                .unwrap_or(Duration::ZERO)
+        })
+    }
+
+    /// Returns a budget of 80% of the time left until the deadline, split across at most
+    /// `attempts` attempts of an outbound call. None if the invocation has no deadline.
+    pub fn timeout_budget(&self, attempts: u32) -> Option<TimeoutBudget> {
+        self.time_remaining()
+            .map(|remaining| TimeoutBudget::new(remaining.mul_f64(DEFAULT_BUDGET_SHARE), attempts))
+    }
+
+    /// Returns a token that is cancelled once the deadline is reached, so handlers can abort
+    /// outstanding work before the Fn agent kills the invocation.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// tokio::select! {
+    ///     res = client.get(url).send() => res?,
+    ///     _ = ctx.cancellation_token().cancelled() => return Err(FunctionError::new_user_error("deadline reached".into())),
+    /// }
+    /// ```
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation_token.clone()
+    }
+
+    /// Replaces the negotiated request and response content types.
+    pub(crate) fn override_content_types(
+        &mut self,
+        content_type: Option<ContentType>,
+        accept_type: Option<ContentType>,
+    ) {
+        if let Some(content_type) = content_type {
+            self.content_type = content_type;
+        }
+        if let Some(accept_type) = accept_type {
+            self.accept_type = accept_type;
+        }
+    }
+
+    #[cfg(feature = "secrets")]
+    pub(crate) fn set_config(&mut self, config: Arc<HashMap<String, String>>) {
+        self.config = config;
+    }
+
+    /// Exposes the current call through the env vars of the legacy default contract.
+    pub(crate) fn shim_legacy_config(&mut self, request_headers: &HeaderMap) {
+        let mut config = (*self.config).clone();
+        crate::legacy::shim_config(&mut config, request_headers);
+        self.config = Arc::new(config);
+    }
+
+    /// Starts a timer cancelling the token at the deadline. Must be called within a tokio runtime.
+    pub(crate) fn arm_deadline(&self) -> Option<DeadlineTimer> {
+        let remaining = self.time_remaining()?;
+        let token = self.cancellation_token.clone();
+        Some(DeadlineTimer(tokio::spawn(async move {
+            tokio::time::sleep(remaining).await;
+            token.cancel();
+        })))
+    }
+
+    /// Registers `callback` to run once the response is written, with its final status and
+    /// timing. Callbacks run on the connection task and should hand slow work off, e.g. with
+    /// `tokio::spawn`. They also run if the connection drops before the response is written.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// ctx.on_complete(|outcome| audit_log(&outcome.call_id, outcome.status, outcome.duration));
+    /// ```
+    pub fn on_complete<C>(&mut self, callback: C)
+    where
+        C: FnOnce(&Outcome) + Send + 'static,
+    {
+        self.completion.push(Box::new(callback));
+    }
+
+    /// Returns the extensions of the invocation, a map of values by type attached to the
+    /// context, e.g. by an authentication layer for the handler to read.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// ctx.extensions_mut().insert(Caller { user_id });
+    /// // ...
+    /// let caller = ctx.extensions().get::<Caller>();
+    /// ```
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Returns a mutable reference to the extensions of the invocation.
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// Returns a temporary directory for the invocation, created on first use. It is removed
+    /// with its contents once the response is written, or when the invocation fails or
+    /// panics, so intermediate files don't pile up in warm containers.
+    pub fn temp_dir(&mut self) -> Result<PathBuf, FunctionError> {
+        if let Some(dir) = &self.temp_dir {
+            return Ok(dir.path().to_owned());
+        }
+        let dir = Arc::new(InvocationDir::create(&self.call_id)?);
+        // The callbacks hold the directory until the response is written.
+        let held = dir.clone();
+        self.completion.push(Box::new(move |_| drop(held)));
+        let path = dir.path().to_owned();
+        self.temp_dir = Some(dir);
+        Ok(path)
+    }
+
+    pub(crate) fn set_completion_callbacks(&mut self, callbacks: CompletionCallbacks) {
+        self.completion = callbacks;
+    }
+
+    /// Returns a writer for stderr that frames and flushes the output of the invocation as a
+    /// whole, so it doesn't interleave with the output of concurrent invocations.
+    pub fn log_writer(&self) -> LogWriter {
+        LogWriter::new(
+            LogOutput::Stderr,
+            self.frame_line.clone(),
+            self.call_id.clone(),
+        )
+    }
+
+    /// Returns a writer for stdout that frames and flushes the output of the invocation as a
+    /// whole, like `log_writer`.
+    pub fn stdout_writer(&self) -> LogWriter {
+        LogWriter::new(
+            LogOutput::Stdout,
+            self.frame_line.clone(),
+            self.call_id.clone(),
+        )
+    }
+
+    /// Returns the OCI specific headers of the invocation, such as `opc-request-id`.
+    pub fn oci_headers(&self) -> &OciHeaders {
+        &self.oci
+    }
+
+    /// Loads the resource principal of the function on OCI Functions from its
+    /// `OCI_RESOURCE_PRINCIPAL_*` config, to sign requests to OCI APIs. The token and key are
+    /// read again on every call, so that a refreshed token is picked up.
+    #[cfg(feature = "resource-principal")]
+    pub fn oci_resource_principal(
+        &self,
+    ) -> Result<crate::resource_principal::ResourcePrincipal, FunctionError> {
+        crate::resource_principal::ResourcePrincipal::from_config(&self.config)
+    }
+
+    /// Decodes the request body as an OCI Events envelope, whatever the input of the handler.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let event = ctx.as_oci_event::<ResourceEventData<ObjectStorageDetails>>()?;
+    /// ```
+    #[cfg(feature = "oci-events")]
+    pub fn as_oci_event<T: DeserializeOwned>(
+        &self,
+    ) -> Result<crate::oci_events::OciEvent<T>, FunctionError> {
+        serde_json::from_slice(&self.body).map_err(|e| FunctionError::Coercion {
+            inner: format!("Invalid OCI event: {}", e),
+            source: Some(Box::new(e)),
+        })
+    }
+
+    #[cfg(feature = "oci-events")]
+    pub(crate) fn set_body(&mut self, body: hyper::body::Bytes) {
+        self.body = body;
+    }
+
+    /// Returns the request of the client as seen through OCI API Gateway, with its client IP
+    /// and helpers to match API Gateway routes.
+    pub fn api_gateway(&self) -> crate::api_gateway::ApiGatewayRequest {
+        crate::api_gateway::ApiGatewayRequest::from_context(self)
+    }
+
+    /// Returns the tracing headers of the invocation and the OCI APM configuration, to
+    /// continue the trace of the caller.
+    pub fn trace_context(&self) -> &TraceContext {
+        &self.trace
+    }
+
+    /// Returns a copy of the request headers. Prefer `headers_ref` to read them.
     pub fn headers(&self) -> HeaderMap {
         self.headers.clone()
     }
 
+    /// Returns the request headers without copying them.
+    pub fn headers_ref(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Returns the trailers sent after the request body, e.g. a checksum of a streamed body.
+    /// Empty until the body has been read, so always read in handlers.
+    pub fn request_trailers(&self) -> &HeaderMap {
+        &self.trailers
+    }
+
+    pub(crate) fn set_request_trailers(&mut self, trailers: HeaderMap) {
+        self.trailers = trailers;
+    }
+
+    /// Returns the value of the request header `name`, or None if it is missing or not
+    /// visible ASCII.
+    pub fn header_str(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+
+    /// Returns the headers of the HTTP client, sent by the trigger with the `Fn-Http-H-` prefix,
+    /// under their original names.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let token = ctx.http_headers().get(AUTHORIZATION).cloned();
+    /// ```
+    pub fn http_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            if let Some(name) = name
+                .as_str()
+                .strip_prefix("fn-http-h-")
+                .and_then(|name| HeaderName::from_str(name).ok())
+            {
+                headers.append(name, value.clone());
+            }
+        }
+        headers
+    }
+
+    /// Returns the value of the HTTP client header `name`, looked up without the `Fn-Http-H-`
+    /// prefix, or None if it is missing or not visible ASCII.
+    pub fn http_header(&self, name: &str) -> Option<&str> {
+        self.header_str(&format!("Fn-Http-H-{}", name))
+    }
+
     /// Returns an `Option<String>` based on the value of header present in headers.
     /// `header` returns None if the header with key is not found.
     pub fn header(&self, key: String) -> Option<String> {
@@ -156,25 +839,276 @@ impl RuntimeContext {
         &self.config
     }
 
-    /// Adds a custom header to the response.
+    /// Returns the value of the config variable `key`.
+    pub fn config_value(&self, key: &str) -> Option<&str> {
+        self.config.get(key).map(String::as_str)
+    }
+
+    /// Parses the config variable `key` as a `T`, or returns None if it is not set.
     ///
     /// # Examples
     ///
     /// ```rust,ignore
-    /// ctx.add_response_header("X-COOLNESS-METER-SAYS", "OVER-9000")
+    /// let port: u16 = ctx.config_get("DB_PORT")?.unwrap_or(5432);
     /// ```
-    pub fn add_response_header(&mut self, key: String, value: String) {
-        self.response_headers.insert(
-            HeaderName::from_str(key.as_str()).unwrap(),
-            HeaderValue::from_str(value.as_str()).unwrap(),
-        );
+    pub fn config_get<T: FromStr>(&self, key: &str) -> Result<Option<T>, FunctionError>
+    where
+        T::Err: std::fmt::Display,
+    {
+        self.config_value(key)
+            .map(|v| {
+                v.parse::<T>().map_err(|e| FunctionError::Initialization {
+                    inner: format!("Invalid {} specified: {} ({})", key, v, e),
+                    source: None,
+                })
+            })
+            .transpose()
+    }
+
+    /// Returns the config variable `key` as a boolean, from `true`, `yes`, `on` or `1` and
+    /// `false`, `no`, `off` or `0`, ignoring case.
+    pub fn config_bool(&self, key: &str) -> Result<Option<bool>, FunctionError> {
+        self.config_value(key)
+            .map(|v| match v.trim().to_ascii_lowercase().as_str() {
+                "true" | "yes" | "on" | "1" => Ok(true),
+                "false" | "no" | "off" | "0" => Ok(false),
+                _ => Err(FunctionError::Initialization {
+                    inner: format!("Invalid {} specified: {}", key, v),
+                    source: None,
+                }),
+            })
+            .transpose()
+    }
+
+    /// Returns the config variable `key` as an integer.
+    pub fn config_int(&self, key: &str) -> Result<Option<i64>, FunctionError> {
+        self.config_get(key)
+    }
+
+    /// Returns the config variable `key` as a duration such as `500ms`, `30s`, `5m` or `1h`. A
+    /// bare number is in seconds.
+    pub fn config_duration(&self, key: &str) -> Result<Option<Duration>, FunctionError> {
+        self.config_value(key)
+            .map(|v| {
+                parse_duration(v).ok_or_else(|| FunctionError::Initialization {
+                    inner: format!("Invalid {} specified: {}", key, v),
+                    source: None,
+                })
+            })
+            .transpose()
+    }
+
+    /// Deserializes the config variables into a settings struct, matching field names to
+    /// variable names without case, and with their `FN_` prefix stripped. Register the struct
+    /// with `FunctionBuilder::validate_config` to check the config once at startup.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// #[derive(Deserialize)]
+    /// struct Settings {
+    ///     db_url: String,
+    ///     app_name: String,
+    ///     #[serde(default)]
+    ///     verbose: bool,
+    /// }
+    ///
+    /// let settings: Settings = ctx.config_as()?;
+    /// ```
+    pub fn config_as<T: DeserializeOwned>(&self) -> Result<T, FunctionError> {
+        deserialize_config(&self.config, "")
+    }
+
+    /// Like `config_as`, with only the variables starting with `prefix`, ignoring case, and
+    /// with the prefix stripped, e.g. `MYAPP_DB_URL` for the `db_url` field with `MYAPP_`.
+    pub fn config_as_prefixed<T: DeserializeOwned>(
+        &self,
+        prefix: &str,
+    ) -> Result<T, FunctionError> {
+        deserialize_config(&self.config, prefix)
+    }
+
+    /// Returns the config variables, in no particular order.
+    pub fn config_iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.config.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Adds a custom header to the response, replacing any previous value. For invocations
+    /// through an HTTP trigger, the name is prefixed with `Fn-Http-H-` so the header reaches the
+    /// client, unless it already is. Fails if `key` or `value` is not a valid header name or
+    /// value.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// ctx.add_response_header("X-COOLNESS-METER-SAYS", "OVER-9000")?;
+    /// ```
+    pub fn add_response_header<K, V>(&mut self, key: K, value: V) -> Result<(), FunctionError>
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let (name, value) = parse_response_header(key.as_ref(), value.as_ref())?;
+        self.insert_response_header(name, value);
+        Ok(())
+    }
+
+    /// Returns the cookies of the client request by name, from its `Cookie` header.
+    pub fn cookies(&self) -> HashMap<String, String> {
+        crate::cookie::parse(&self.headers)
     }
 
-    /// Helper to return the response headers
+    /// Adds a `Set-Cookie` header for `cookie` to the response, keeping the cookies set
+    /// before. Fails if the name, value, path or domain of the cookie cannot be sent unquoted.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// ctx.set_cookie(Cookie::new("theme", "dark").path("/").max_age(Duration::from_secs(86400)))?;
+    /// ```
+    pub fn set_cookie(&mut self, cookie: Cookie) -> Result<(), FunctionError> {
+        cookie.validate()?;
+        self.append_response_header("Set-Cookie", cookie.to_string())
+    }
+
+    /// Adds a value to a response header, keeping the previous ones, e.g. to set several
+    /// cookies. Names are handled like in `add_response_header`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// ctx.append_response_header("Set-Cookie", "session=abc; HttpOnly")?;
+    /// ctx.append_response_header("Set-Cookie", "theme=dark")?;
+    /// ```
+    pub fn append_response_header<K, V>(&mut self, key: K, value: V) -> Result<(), FunctionError>
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let (name, value) = parse_response_header(key.as_ref(), value.as_ref())?;
+        let name = self.response_header_name(name);
+        self.response_headers.append(name, value);
+        Ok(())
+    }
+
+    /// Replaces the values of a response header with `values`. Nothing is changed if one of
+    /// them is invalid.
+    pub fn set_response_header_values<K, I>(
+        &mut self,
+        key: K,
+        values: I,
+    ) -> Result<(), FunctionError>
+    where
+        K: AsRef<str>,
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let key = key.as_ref();
+        let values = values
+            .into_iter()
+            .map(|value| parse_response_header(key, value.as_ref()).map(|(_, value)| value))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (name, _) = parse_response_header(key, "")?;
+        let name = self.response_header_name(name);
+        self.response_headers.remove(&name);
+        for value in values {
+            self.response_headers.append(name.clone(), value);
+        }
+        Ok(())
+    }
+
+    /// Adds a custom header to the response, replacing any previous value, like
+    /// `add_response_header` with an already validated name and value.
+    pub fn insert_response_header(&mut self, name: HeaderName, value: HeaderValue) {
+        let name = self.response_header_name(name);
+        self.response_headers.insert(name, value);
+    }
+
+    /// Adds a value to a response header, keeping the previous ones, like
+    /// `append_response_header` with an already validated name and value.
+    #[cfg(feature = "compression")]
+    pub(crate) fn append_response_header_value(&mut self, name: HeaderName, value: HeaderValue) {
+        let name = self.response_header_name(name);
+        self.response_headers.append(name, value);
+    }
+
+    /// Returns true if the handler added the response header `name`.
+    #[cfg(feature = "compression")]
+    pub(crate) fn has_response_header(&self, name: HeaderName) -> bool {
+        self.response_headers
+            .contains_key(self.response_header_name(name))
+    }
+
+    /// Returns the name under which a header added by the handler is sent.
+    fn response_header_name(&self, name: HeaderName) -> HeaderName {
+        if !self.http_intent {
+            return name;
+        }
+        match http_response_header_name(name.as_str()) {
+            Cow::Borrowed(_) => name,
+            Cow::Owned(prefixed) => HeaderName::from_str(&prefixed).unwrap_or(name),
+        }
+    }
+
+    /// Returns a copy of the response headers. Prefer `response_headers_ref` to read them.
     pub fn response_headers(&self) -> HeaderMap {
         self.response_headers.clone()
     }
 
+    /// Returns the response headers added so far without copying them.
+    pub fn response_headers_ref(&self) -> &HeaderMap {
+        &self.response_headers
+    }
+
+    /// Moves the response headers out once the handler has returned.
+    pub(crate) fn take_response_headers(&mut self) -> HeaderMap {
+        std::mem::take(&mut self.response_headers)
+    }
+
+    /// Sets the status and adds the headers of an output setting its own response, named like
+    /// the headers added by the handler.
+    pub(crate) fn set_response_parts(&mut self, status: StatusCode, headers: HeaderMap) {
+        self.response_status_code = Some(status);
+        let mut name = None;
+        for (key, value) in headers {
+            name = key.or(name);
+            match &name {
+                Some(name) if *name == CONTENT_TYPE => self.response_content_type = Some(value),
+                Some(name) => {
+                    let name = self.response_header_name(name.clone());
+                    self.response_headers.append(name, value);
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Sets the Content-Type of the response, instead of negotiating it from the Accept header
+    /// of the request. The output is encoded as the named type when the FDK supports it, e.g.
+    /// `application/json`, and as plain text otherwise: return an `FnResponse` for binary
+    /// bodies such as `application/pdf`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// ctx.set_response_content_type("text/csv")?;
+    /// Ok(rows.join("\n"))
+    /// ```
+    pub fn set_response_content_type(&mut self, content_type: &str) -> Result<(), FunctionError> {
+        let value =
+            HeaderValue::from_str(content_type).map_err(|e| FunctionError::InvalidInput {
+                inner: format!("Invalid response content type {}: {}", content_type, e),
+                source: Some(Box::new(e)),
+            })?;
+        self.response_content_type = Some(value);
+        Ok(())
+    }
+
+    /// Returns the Content-Type set by the handler, if any.
+    pub fn response_content_type(&self) -> Option<&HeaderValue> {
+        self.response_content_type.as_ref()
+    }
+
     /// Sets the status code in the response headers under Fn-Http-Status key.
     /// Default value is 200.
     pub fn set_status_code(&mut self, status: u16) -> Result<(), FunctionError> {
@@ -183,14 +1117,134 @@ impl RuntimeContext {
             Err(_) => {
                 return Err(FunctionError::InvalidInput {
                     inner: "Invalid http code added".into(),
+                    source: None,
                 })
             }
         };
         Ok(())
     }
 
+    /// Redirects the caller to `url` with a 3xx `status`, for handlers returning another output
+    /// than `FnResponse::redirect`. The status is reported in Fn-Http-Status and `Location` is
+    /// prefixed with `Fn-Http-H-` for HTTP triggers. The output of the handler is discarded:
+    /// the response has no body and no Content-Type.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// ctx.redirect("/login", StatusCode::SEE_OTHER)?;
+    /// return Ok(String::new());
+    /// ```
+    pub fn redirect(&mut self, url: &str, status: StatusCode) -> Result<(), FunctionError> {
+        if !status.is_redirection() {
+            return Err(FunctionError::InvalidInput {
+                inner: format!("Invalid redirect status code {}", status.as_u16()),
+                source: None,
+            });
+        }
+        self.add_response_header(hyper::header::LOCATION.as_str(), url)?;
+        self.response_status_code = Some(status);
+        self.redirected = true;
+        Ok(())
+    }
+
+    /// Returns true if the handler redirected the caller with `redirect`.
+    pub(crate) fn is_redirect(&self) -> bool {
+        self.redirected
+    }
+
     /// Helper function to return status code set by user.
     pub fn get_status_code(&self) -> Option<StatusCode> {
         self.response_status_code
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http_request() -> hyper::http::request::Builder {
+        hyper::Request::builder()
+            .header("Fn-Intent", "httprequest")
+            .header("Fn-Call-Id", "01CALL")
+            .header("Fn-Http-Method", "GET")
+            .header("Fn-Http-Request-Url", "/hello")
+    }
+
+    fn header_value(bytes: &'static [u8]) -> HeaderValue {
+        HeaderValue::from_bytes(bytes).unwrap()
+    }
+
+    #[test]
+    fn non_utf8_method_is_invalid_input() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Fn-Http-Method", header_value(b"G\xc3\xa9T"));
+        assert!(matches!(
+            check_http_headers(&headers),
+            Err(FunctionError::InvalidInput { .. })
+        ));
+    }
+
+    #[test]
+    fn obs_text_request_url_is_invalid_input() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Fn-Http-Method", header_value(b"GET"));
+        headers.insert("Fn-Http-Request-Url", header_value(b"/caf\xe9"));
+        assert!(matches!(
+            check_http_headers(&headers),
+            Err(FunctionError::InvalidInput { .. })
+        ));
+    }
+
+    #[test]
+    fn unnamed_forwarded_header_is_invalid_input() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Fn-Http-H-", header_value(b"value"));
+        assert!(matches!(
+            check_http_headers(&headers),
+            Err(FunctionError::InvalidInput { .. })
+        ));
+    }
+
+    #[test]
+    fn bad_header_bytes_do_not_panic() {
+        let req = hyper::Request::builder()
+            .header("Fn-Intent", header_value(b"http\xffrequest"))
+            .header("Fn-Call-Id", header_value(b"\xff"))
+            .header("Fn-Http-Method", header_value(b"\xfe\xff"))
+            .header("Fn-Http-Request-Url", header_value(b"/\x80"))
+            .header("Fn-Http-H-Accept", header_value(b"\xff/\xff"))
+            .body(())
+            .unwrap();
+        let ctx = RuntimeContext::from_req(&req);
+        assert_eq!(ctx.call_id_ref(), "");
+        assert!(ctx.method().is_none());
+        assert!(ctx.request_url().is_none());
+    }
+
+    #[test]
+    fn response_parts_are_prefixed_for_http_triggers_only() {
+        let response = crate::FnResponse::temporary_redirect("/login").unwrap();
+        let mut http = RuntimeContext::builder()
+            .header("Fn-Intent", "httprequest")
+            .build();
+        http.set_response_parts(response.status(), response.headers().clone());
+        assert_eq!(http.response_headers_ref()["Fn-Http-H-Location"], "/login");
+
+        let mut cloud_event = RuntimeContext::builder().build();
+        cloud_event.set_response_parts(response.status(), response.headers().clone());
+        assert_eq!(cloud_event.response_headers_ref()["Location"], "/login");
+        assert!(cloud_event
+            .response_headers_ref()
+            .get("Fn-Http-H-Location")
+            .is_none());
+    }
+
+    #[test]
+    fn http_invocations_keep_the_call_id() {
+        let req = http_request().body(()).unwrap();
+        let ctx = RuntimeContext::from_req(&req);
+        assert_eq!(ctx.call_id_ref(), "01CALL");
+        assert!(ctx.headers_ref().get("Fn-Call-Id").is_none());
+    }
+}
@@ -0,0 +1,330 @@
+//! HTTP cookies of the client request and the response.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! let session = ctx.cookies().get("session").cloned();
+//! ctx.set_cookie(
+//!     Cookie::new("session", "abc123")
+//!         .path("/")
+//!         .max_age(Duration::from_secs(3600))
+//!         .secure(true)
+//!         .http_only(true)
+//!         .same_site(SameSite::Lax),
+//! )?;
+//! ```
+
+use crate::errors::FunctionError;
+use chrono::{DateTime, Utc};
+use hyper::HeaderMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+/// SameSite is the `SameSite` attribute of a cookie.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SameSite {
+    /// Only sends the cookie on requests from the same site.
+    Strict,
+    /// Also sends the cookie when navigating to the site from another one.
+    Lax,
+    /// Sends the cookie on cross-site requests. Browsers require `Secure` with it.
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        })
+    }
+}
+
+/// Cookie is a cookie to set on the client with `RuntimeContext::set_cookie`. It formats as
+/// the value of a `Set-Cookie` header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    expires: Option<SystemTime>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// Returns a session cookie, without attributes.
+    pub fn new<N: Into<String>, V: Into<String>>(name: N, value: V) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// Returns a cookie deleting the cookie `name` from the client. Its path and domain must
+    /// match the ones it was set with.
+    pub fn removal<N: Into<String>>(name: N) -> Self {
+        Self::new(name, "").max_age(Duration::ZERO)
+    }
+
+    /// Returns the name of the cookie.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the value of the cookie.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Restricts the cookie to the paths under `path`.
+    pub fn path<P: Into<String>>(mut self, path: P) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Sends the cookie to `domain` and its subdomains.
+    pub fn domain<D: Into<String>>(mut self, domain: D) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Expires the cookie after `max_age`, in whole seconds.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Expires the cookie at `expires`. `max_age` takes precedence in browsers.
+    pub fn expires(mut self, expires: SystemTime) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// Only sends the cookie over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Hides the cookie from scripts.
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Sets when the cookie is sent on cross-site requests.
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Checks the name is a token and the value and attributes can be sent unquoted.
+    pub(crate) fn validate(&self) -> Result<(), FunctionError> {
+        let invalid = |what: &str| FunctionError::InvalidInput {
+            inner: format!("Invalid cookie {}: invalid {}", self.name, what),
+            source: None,
+        };
+        let is_token = |c: char| c.is_ascii_graphic() && !"()<>@,;:\\\"/[]?={}".contains(c);
+        if self.name.is_empty() || !self.name.chars().all(is_token) {
+            return Err(invalid("name"));
+        }
+        if !self
+            .value
+            .chars()
+            .all(|c| c.is_ascii_graphic() && !"\",;\\".contains(c))
+        {
+            return Err(invalid("value"));
+        }
+        let is_attribute = |v: &String| v.chars().all(|c| c.is_ascii_graphic() && c != ';');
+        if !self.path.iter().all(is_attribute) {
+            return Err(invalid("path"));
+        }
+        if !self.domain.iter().all(is_attribute) {
+            return Err(invalid("domain"));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            let expires = DateTime::<Utc>::from(expires);
+            write!(
+                f,
+                "; Expires={}",
+                expires.format("%a, %d %b %Y %H:%M:%S GMT")
+            )?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the `Cookie` headers of the request, sent directly or by an HTTP trigger, into the
+/// values by name. The first value of a name wins, as browsers send the most specific first.
+pub(crate) fn parse(headers: &HeaderMap) -> HashMap<String, String> {
+    let mut cookies = HashMap::new();
+    let values = headers
+        .get_all("Cookie")
+        .iter()
+        .chain(headers.get_all("Fn-Http-H-Cookie").iter())
+        .filter_map(|v| v.to_str().ok());
+    for pair in values.flat_map(|v| v.split(';')) {
+        if let Some((name, value)) = pair.split_once('=') {
+            let name = name.trim();
+            if name.is_empty() {
+                continue;
+            }
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            cookies
+                .entry(name.to_owned())
+                .or_insert_with(|| value.to_owned());
+        }
+    }
+    cookies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(cookies: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for cookie in cookies {
+            headers.append("Fn-Http-H-Cookie", cookie.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn quoted_values_are_unquoted() {
+        let cookies = parse(&request(&[r#"session="abc123"; theme=dark"#]));
+        assert_eq!(cookies["session"], "abc123");
+        assert_eq!(cookies["theme"], "dark");
+    }
+
+    #[test]
+    fn empty_values_are_kept() {
+        let cookies = parse(&request(&["session=; quoted=\"\""]));
+        assert_eq!(cookies["session"], "");
+        assert_eq!(cookies["quoted"], "");
+    }
+
+    #[test]
+    fn first_value_of_a_name_wins() {
+        let mut headers = request(&["session=specific; session=generic", "session=other"]);
+        headers.append("Cookie", "session=direct".parse().unwrap());
+        assert_eq!(parse(&headers)["session"], "direct");
+
+        let cookies = parse(&request(&["session=specific; session=generic"]));
+        assert_eq!(cookies["session"], "specific");
+    }
+
+    #[test]
+    fn pairs_without_name_are_skipped() {
+        let cookies = parse(&request(&["=orphan; flag; id=7"]));
+        assert_eq!(cookies.len(), 1);
+        assert_eq!(cookies["id"], "7");
+    }
+
+    #[test]
+    fn attributes_are_rendered() {
+        let cookie = Cookie::new("session", "abc123")
+            .path("/")
+            .domain("example.com")
+            .max_age(Duration::from_millis(3_600_500))
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+        assert_eq!(
+            cookie.to_string(),
+            "session=abc123; Path=/; Domain=example.com; Max-Age=3600; Secure; HttpOnly; \
+             SameSite=Lax"
+        );
+        assert_eq!(Cookie::new("a", "b").to_string(), "a=b");
+        assert_eq!(
+            Cookie::new("a", "b")
+                .same_site(SameSite::None)
+                .secure(true)
+                .to_string(),
+            "a=b; Secure; SameSite=None"
+        );
+    }
+
+    #[test]
+    fn removal_expires_the_cookie() {
+        assert_eq!(
+            Cookie::removal("session").to_string(),
+            "session=; Max-Age=0"
+        );
+    }
+
+    #[test]
+    fn expires_is_rendered_as_an_http_date() {
+        let expires = SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(
+            Cookie::new("a", "b").expires(expires).to_string(),
+            "a=b; Expires=Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+    }
+
+    #[test]
+    fn invalid_names_are_rejected() {
+        for name in ["", "a b", "a;b", "a=b", "a\"b", "caf\u{e9}"] {
+            assert!(
+                matches!(
+                    Cookie::new(name, "v").validate(),
+                    Err(FunctionError::InvalidInput { .. })
+                ),
+                "{:?}",
+                name
+            );
+        }
+        assert!(Cookie::new("session_id-2", "v").validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_values_and_attributes_are_rejected() {
+        assert!(Cookie::new("a", "b;c").validate().is_err());
+        assert!(Cookie::new("a", "b c").validate().is_err());
+        assert!(Cookie::new("a", "b").path("/x;y").validate().is_err());
+        assert!(Cookie::new("a", "b").domain("a b").validate().is_err());
+        assert!(Cookie::new("a", "").validate().is_ok());
+    }
+}
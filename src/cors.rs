@@ -0,0 +1,360 @@
+//! Cross-origin resource sharing for functions called from browsers.
+//!
+//! With `FunctionBuilder::cors`, preflight requests, `OPTIONS` requests with an `Origin` and
+//! an `Access-Control-Request-Method` header, are answered with a 204 without calling the
+//! handler, and the responses to allowed origins get the `Access-Control-Allow-*` headers.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! Function::builder()
+//!     .cors(CorsConfig {
+//!         allowed_origins: vec!["https://app.example.com".into()],
+//!         allow_credentials: true,
+//!         ..CorsConfig::default()
+//!     })
+//!     .run(handler)
+//!     .await
+//! ```
+
+use crate::body::Body;
+use crate::oci::forwarded_header;
+use crate::utils::success_or_recoverable_error;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{HeaderMap, Method, Request, Response, StatusCode};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// CorsConfig sets which cross-origin requests browsers may make to the function.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorsConfig {
+    /// Origins allowed to call the function, e.g. `https://app.example.com`, or `*` for any.
+    pub allowed_origins: Vec<String>,
+    /// Methods allowed in cross-origin requests.
+    pub allowed_methods: Vec<String>,
+    /// Request headers allowed in cross-origin requests. When empty, the headers a preflight
+    /// asks for are allowed.
+    pub allowed_headers: Vec<String>,
+    /// Response headers scripts may read besides the CORS-safelisted ones.
+    pub exposed_headers: Vec<String>,
+    /// Lets browsers send cookies and credentials. The origin of the request is then echoed
+    /// instead of `*`.
+    pub allow_credentials: bool,
+    /// How long browsers may cache a preflight response.
+    pub max_age: Option<Duration>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec!["*".into()],
+            allowed_methods: ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE"]
+                .iter()
+                .map(|m| m.to_string())
+                .collect(),
+            allowed_headers: Vec::new(),
+            exposed_headers: Vec::new(),
+            allow_credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+/// CorsRequest holds what CORS needs of a request, kept while it is handled.
+pub(crate) struct CorsRequest {
+    origin: Option<HeaderValue>,
+    http_intent: bool,
+}
+
+impl CorsConfig {
+    /// Returns the CORS view of `req`, and the response to it if it is a preflight request.
+    pub(crate) fn inspect<B>(&self, req: &Request<B>) -> (CorsRequest, Option<Response<Body>>) {
+        let headers = req.headers();
+        let request = CorsRequest {
+            origin: forwarded_header(headers, "Origin").and_then(|o| HeaderValue::from_str(o).ok()),
+            http_intent: crate::context::is_http_intent(headers),
+        };
+        let method = headers
+            .get("Fn-Http-Method")
+            .and_then(|m| Method::from_bytes(m.as_bytes()).ok())
+            .unwrap_or_else(|| req.method().clone());
+        let requested_method = forwarded_header(headers, "Access-Control-Request-Method");
+        if method != Method::OPTIONS || request.origin.is_none() || requested_method.is_none() {
+            return (request, None);
+        }
+
+        let mut response_headers = HeaderMap::new();
+        if self.apply(&request, &mut response_headers) {
+            let allowed = self
+                .allowed_methods
+                .iter()
+                .any(|m| requested_method.is_some_and(|r| m.eq_ignore_ascii_case(r)));
+            if allowed {
+                let allowed_headers = if self.allowed_headers.is_empty() {
+                    forwarded_header(headers, "Access-Control-Request-Headers")
+                        .unwrap_or_default()
+                        .to_owned()
+                } else {
+                    self.allowed_headers.join(", ")
+                };
+                request.insert(
+                    &mut response_headers,
+                    "Access-Control-Allow-Methods",
+                    &self.allowed_methods.join(", "),
+                );
+                if !allowed_headers.is_empty() {
+                    request.insert(
+                        &mut response_headers,
+                        "Access-Control-Allow-Headers",
+                        &allowed_headers,
+                    );
+                }
+                if let Some(max_age) = self.max_age {
+                    request.insert(
+                        &mut response_headers,
+                        "Access-Control-Max-Age",
+                        &max_age.as_secs().to_string(),
+                    );
+                }
+            }
+        }
+        let response =
+            success_or_recoverable_error(StatusCode::NO_CONTENT, None, Some(response_headers));
+        (request, Some(response))
+    }
+
+    /// Adds the headers allowing the origin of `request` to read the response, if it is
+    /// allowed. Returns whether it is.
+    pub(crate) fn apply(&self, request: &CorsRequest, headers: &mut HeaderMap) -> bool {
+        let origin = match &request.origin {
+            Some(origin) => origin,
+            None => return false,
+        };
+        let any = self.allowed_origins.iter().any(|o| o == "*");
+        let listed = self
+            .allowed_origins
+            .iter()
+            .any(|o| o.as_bytes().eq_ignore_ascii_case(origin.as_bytes()));
+        if !any && !listed {
+            return false;
+        }
+
+        if any && !self.allow_credentials {
+            request.insert(headers, "Access-Control-Allow-Origin", "*");
+        } else {
+            request.insert_value(headers, "Access-Control-Allow-Origin", origin.clone());
+            request.vary_origin(headers);
+        }
+        if self.allow_credentials {
+            request.insert(headers, "Access-Control-Allow-Credentials", "true");
+        }
+        if !self.exposed_headers.is_empty() {
+            request.insert(
+                headers,
+                "Access-Control-Expose-Headers",
+                &self.exposed_headers.join(", "),
+            );
+        }
+        true
+    }
+}
+
+impl CorsRequest {
+    /// Inserts a response header, prefixed with `Fn-Http-H-` for HTTP triggers.
+    fn insert(&self, headers: &mut HeaderMap, name: &str, value: &str) {
+        if let Ok(value) = HeaderValue::from_str(value) {
+            self.insert_value(headers, name, value);
+        }
+    }
+
+    fn insert_value(&self, headers: &mut HeaderMap, name: &str, value: HeaderValue) {
+        if let Some(name) = self.header_name(name) {
+            headers.insert(name, value);
+        }
+    }
+
+    /// Adds `Origin` to the `Vary` header, keeping the other values.
+    fn vary_origin(&self, headers: &mut HeaderMap) {
+        if let Some(name) = self.header_name("Vary") {
+            headers.append(name, HeaderValue::from_static("Origin"));
+        }
+    }
+
+    fn header_name(&self, name: &str) -> Option<HeaderName> {
+        if self.http_intent {
+            HeaderName::from_str(&format!("Fn-Http-H-{}", name)).ok()
+        } else {
+            HeaderName::from_str(name).ok()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(origins: &[&str], allow_credentials: bool) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: origins.iter().map(|o| o.to_string()).collect(),
+            exposed_headers: vec!["X-Request-Id".into()],
+            allow_credentials,
+            max_age: Some(Duration::from_secs(600)),
+            ..CorsConfig::default()
+        }
+    }
+
+    fn http_request(method: &str, origin: &str) -> hyper::http::request::Builder {
+        Request::post("/")
+            .header("Fn-Intent", "httprequest")
+            .header("Fn-Http-Method", method)
+            .header("Fn-Http-H-Origin", origin)
+    }
+
+    fn preflight(config: &CorsConfig, origin: &str) -> Response<Body> {
+        let req = http_request("OPTIONS", origin)
+            .header("Fn-Http-H-Access-Control-Request-Method", "PUT")
+            .header("Fn-Http-H-Access-Control-Request-Headers", "Content-Type")
+            .body(())
+            .unwrap();
+        config.inspect(&req).1.expect("not a preflight request")
+    }
+
+    fn simple(config: &CorsConfig, origin: &str) -> HeaderMap {
+        let req = http_request("GET", origin).body(()).unwrap();
+        let (request, preflight) = config.inspect(&req);
+        assert!(preflight.is_none());
+        let mut headers = HeaderMap::new();
+        headers.append("Fn-Http-H-Vary", HeaderValue::from_static("Accept"));
+        config.apply(&request, &mut headers);
+        headers
+    }
+
+    fn values<'a>(headers: &'a HeaderMap, name: &str) -> Vec<&'a str> {
+        headers
+            .get_all(name)
+            .iter()
+            .map(|v| v.to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn preflight_of_an_allowed_origin_is_answered() {
+        let config = config(&["https://app.example.com"], false);
+        let response = preflight(&config, "https://APP.example.com");
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(headers["Fn-Http-Status"], "204");
+        assert_eq!(
+            headers["Fn-Http-H-Access-Control-Allow-Origin"],
+            "https://APP.example.com"
+        );
+        assert_eq!(values(headers, "Fn-Http-H-Vary"), ["Origin"]);
+        assert_eq!(
+            headers["Fn-Http-H-Access-Control-Allow-Methods"],
+            "GET, HEAD, POST, PUT, PATCH, DELETE"
+        );
+        assert_eq!(
+            headers["Fn-Http-H-Access-Control-Allow-Headers"],
+            "Content-Type"
+        );
+        assert_eq!(headers["Fn-Http-H-Access-Control-Max-Age"], "600");
+        assert_eq!(
+            headers["Fn-Http-H-Access-Control-Expose-Headers"],
+            "X-Request-Id"
+        );
+        assert!(!headers.contains_key("Fn-Http-H-Access-Control-Allow-Credentials"));
+    }
+
+    #[test]
+    fn preflight_of_a_denied_origin_gets_no_cors_headers() {
+        let config = config(&["https://app.example.com"], false);
+        let response = preflight(&config, "https://evil.example.com");
+        assert_eq!(response.headers()["Fn-Http-Status"], "204");
+        assert!(!response
+            .headers()
+            .keys()
+            .any(|name| name.as_str().starts_with("fn-http-h-access-control-")));
+    }
+
+    #[test]
+    fn preflight_of_a_denied_method_gets_no_allow_methods() {
+        let config = CorsConfig {
+            allowed_methods: vec!["GET".into()],
+            ..CorsConfig::default()
+        };
+        let response = preflight(&config, "https://app.example.com");
+        let headers = response.headers();
+        assert_eq!(headers["Fn-Http-H-Access-Control-Allow-Origin"], "*");
+        assert!(!headers.contains_key("Fn-Http-H-Access-Control-Allow-Methods"));
+    }
+
+    #[test]
+    fn wildcard_is_sent_without_vary() {
+        let headers = simple(&config(&["*"], false), "https://app.example.com");
+        assert_eq!(headers["Fn-Http-H-Access-Control-Allow-Origin"], "*");
+        assert_eq!(values(&headers, "Fn-Http-H-Vary"), ["Accept"]);
+    }
+
+    #[test]
+    fn wildcard_with_credentials_echoes_the_origin() {
+        let config = config(&["*"], true);
+        let headers = simple(&config, "https://app.example.com");
+        assert_eq!(
+            headers["Fn-Http-H-Access-Control-Allow-Origin"],
+            "https://app.example.com"
+        );
+        assert_eq!(
+            headers["Fn-Http-H-Access-Control-Allow-Credentials"],
+            "true"
+        );
+        assert_eq!(values(&headers, "Fn-Http-H-Vary"), ["Accept", "Origin"]);
+
+        let response = preflight(&config, "https://app.example.com");
+        assert_eq!(
+            response.headers()["Fn-Http-H-Access-Control-Allow-Origin"],
+            "https://app.example.com"
+        );
+        assert_eq!(values(response.headers(), "Fn-Http-H-Vary"), ["Origin"]);
+    }
+
+    #[test]
+    fn simple_request_of_a_listed_origin_gets_cors_headers() {
+        let headers = simple(
+            &config(&["https://app.example.com"], false),
+            "https://app.example.com",
+        );
+        assert_eq!(
+            headers["Fn-Http-H-Access-Control-Allow-Origin"],
+            "https://app.example.com"
+        );
+        assert_eq!(
+            headers["Fn-Http-H-Access-Control-Expose-Headers"],
+            "X-Request-Id"
+        );
+        assert_eq!(values(&headers, "Fn-Http-H-Vary"), ["Accept", "Origin"]);
+        assert!(!headers.contains_key("Fn-Http-H-Access-Control-Allow-Methods"));
+    }
+
+    #[test]
+    fn simple_request_of_a_denied_origin_is_left_alone() {
+        let headers = simple(
+            &config(&["https://app.example.com"], true),
+            "https://app.example.com.evil.net",
+        );
+        assert_eq!(headers.len(), 1);
+        assert_eq!(values(&headers, "Fn-Http-H-Vary"), ["Accept"]);
+    }
+
+    #[test]
+    fn requests_without_http_trigger_get_unprefixed_headers() {
+        let req = Request::get("/")
+            .header("Origin", "https://app.example.com")
+            .body(())
+            .unwrap();
+        let (request, preflight) = CorsConfig::default().inspect(&req);
+        assert!(preflight.is_none());
+        let mut headers = HeaderMap::new();
+        assert!(CorsConfig::default().apply(&request, &mut headers));
+        assert_eq!(headers["Access-Control-Allow-Origin"], "*");
+    }
+}
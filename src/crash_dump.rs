@@ -0,0 +1,177 @@
+use hyper::HeaderMap;
+use std::cell::RefCell;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static CONFIG: OnceLock<CrashDumpConfig> = OnceLock::new();
+
+thread_local! {
+    static CURRENT_REQUEST: RefCell<Option<Arc<RequestSnapshot>>> = const { RefCell::new(None) };
+}
+
+/// CrashDumpConfig controls where and how the request that caused a panic or an unrecoverable
+/// error is persisted for postmortem reproduction.
+#[derive(Clone, Debug)]
+pub struct CrashDumpConfig {
+    /// Directory the dumps are written to.
+    pub dir: PathBuf,
+    /// Request bodies longer than this are truncated in the dump.
+    pub max_body_bytes: usize,
+    /// Headers whose values are replaced by `<redacted>`, matched case-insensitively. The
+    /// `Fn-Http-H-` prefixed form of every name is redacted as well.
+    pub redacted_headers: Vec<String>,
+}
+
+impl Default for CrashDumpConfig {
+    fn default() -> Self {
+        Self {
+            dir: std::env::temp_dir(),
+            max_body_bytes: 64 * 1024,
+            redacted_headers: vec![
+                "authorization".into(),
+                "proxy-authorization".into(),
+                "cookie".into(),
+                "set-cookie".into(),
+                "x-api-key".into(),
+            ],
+        }
+    }
+}
+
+impl CrashDumpConfig {
+    fn is_redacted(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        let name = name.strip_prefix("fn-http-h-").unwrap_or(&name);
+        self.redacted_headers
+            .iter()
+            .any(|redacted| redacted.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Enables crash dumps for the lifetime of the process. Only the first call has an effect.
+pub(crate) fn enable(config: CrashDumpConfig) {
+    let _ = CONFIG.set(config);
+}
+
+pub(crate) fn enabled() -> bool {
+    CONFIG.get().is_some()
+}
+
+/// RequestSnapshot is the redacted, truncated copy of a request kept while it is processed.
+pub(crate) struct RequestSnapshot {
+    call_id: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    body_len: usize,
+}
+
+impl RequestSnapshot {
+    /// Captures the request if crash dumps are enabled.
+    pub(crate) fn capture(call_id: &str, headers: &HeaderMap, body: &[u8]) -> Option<Arc<Self>> {
+        Some(Self::capture_with(CONFIG.get()?, call_id, headers, body))
+    }
+
+    /// Captures the request, redacted and truncated as set by `config`.
+    pub(crate) fn capture_with(
+        config: &CrashDumpConfig,
+        call_id: &str,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Arc<Self> {
+        let headers = headers
+            .iter()
+            .map(|(k, v)| {
+                let value = if config.is_redacted(k.as_str()) {
+                    "<redacted>".to_owned()
+                } else {
+                    String::from_utf8_lossy(v.as_bytes()).into_owned()
+                };
+                (k.as_str().to_owned(), value)
+            })
+            .collect();
+        Arc::new(Self {
+            call_id: call_id.to_owned(),
+            headers,
+            body: body[..body.len().min(config.max_body_bytes)].to_vec(),
+            body_len: body.len(),
+        })
+    }
+
+    /// Writes the snapshot to the configured directory and logs where it went.
+    pub(crate) fn dump(&self, reason: &str) {
+        let config = match CONFIG.get() {
+            Some(config) => config,
+            None => return,
+        };
+        let path = config.dir.join(self.file_name("fdk-crash", "dump"));
+
+        match self.write_to(&path, reason) {
+            Ok(()) => eprintln!(
+                "Crash dump of call {} written to {}",
+                self.call_id,
+                path.display()
+            ),
+            Err(e) => eprintln!("Failed to write crash dump of call {}: {}", self.call_id, e),
+        }
+    }
+
+    /// Returns a file name unique to the call and time, `<prefix>-<call ID>-<millis>.<ext>`.
+    pub(crate) fn file_name(&self, prefix: &str, extension: &str) -> String {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let call_id: String = self
+            .call_id
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect();
+        format!("{}-{}-{}.{}", prefix, call_id, millis, extension)
+    }
+
+    /// Writes the reason, the call ID, the headers, a blank line, a line with the captured and
+    /// original sizes of the body, and the body. `replay` reads requests back from this format.
+    pub(crate) fn write_to(&self, path: &std::path::Path, reason: &str) -> std::io::Result<()> {
+        let mut file = fs::File::create(path)?;
+        writeln!(file, "Reason: {}", reason)?;
+        writeln!(file, "Call ID: {}", self.call_id)?;
+        for (k, v) in &self.headers {
+            writeln!(file, "{}: {}", k, v)?;
+        }
+        writeln!(
+            file,
+            "\n<body: {} of {} bytes>",
+            self.body.len(),
+            self.body_len
+        )?;
+        file.write_all(&self.body)
+    }
+}
+
+/// SnapshotGuard makes a snapshot the current request of this thread until dropped, so the
+/// panic hook can dump it.
+pub(crate) struct SnapshotGuard(());
+
+impl SnapshotGuard {
+    pub(crate) fn enter(snapshot: &Arc<RequestSnapshot>) -> Self {
+        CURRENT_REQUEST.with(|current| *current.borrow_mut() = Some(snapshot.clone()));
+        SnapshotGuard(())
+    }
+}
+
+impl Drop for SnapshotGuard {
+    fn drop(&mut self) {
+        CURRENT_REQUEST.with(|current| *current.borrow_mut() = None);
+    }
+}
+
+/// Dumps the request running on the current thread, if any. Called from the panic hook.
+pub(crate) fn dump_current(reason: &str) {
+    let snapshot = CURRENT_REQUEST.with(|current| current.borrow().clone());
+    if let Some(snapshot) = snapshot {
+        snapshot.dump(reason);
+    }
+}
@@ -0,0 +1,58 @@
+use crate::coercions::ContentType;
+use crate::logging::{self, Level};
+use hyper::{Request, Uri};
+
+/// Query parameter overriding the response content type in dev mode.
+pub const ACCEPT_OVERRIDE_PARAM: &str = "_accept";
+/// Query parameter overriding the request content type in dev mode.
+pub const CONTENT_TYPE_OVERRIDE_PARAM: &str = "_content_type";
+
+/// Returns true if `FN_DEV_MODE` is set to `1` or `true`.
+pub(crate) fn enabled_by_env() -> bool {
+    crate::context::CONFIG_FROM_ENV
+        .get("FN_DEV_MODE")
+        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+/// FormatOverrides are the content types requested through query parameters.
+#[derive(Default)]
+pub(crate) struct FormatOverrides {
+    pub content_type: Option<ContentType>,
+    pub accept_type: Option<ContentType>,
+}
+
+/// Reads `_content_type` and `_accept` from the query of the request URI, or of the
+/// `Fn-Http-Request-Url` header for HTTP triggered calls.
+pub(crate) fn format_overrides<B>(req: &Request<B>) -> FormatOverrides {
+    let gateway_uri = req
+        .headers()
+        .get("Fn-Http-Request-Url")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<Uri>().ok());
+    let query = gateway_uri
+        .as_ref()
+        .and_then(Uri::query)
+        .or_else(|| req.uri().query());
+
+    let mut overrides = FormatOverrides::default();
+    let query = match query {
+        Some(q) => q,
+        None => return overrides,
+    };
+    for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        let slot = match key.as_ref() {
+            ACCEPT_OVERRIDE_PARAM => &mut overrides.accept_type,
+            CONTENT_TYPE_OVERRIDE_PARAM => &mut overrides.content_type,
+            _ => continue,
+        };
+        match ContentType::parse(&value) {
+            Some(content_type) => *slot = Some(content_type),
+            None => logging::log(
+                Level::Warn,
+                format_args!("ignoring {}={}: unsupported content type", key, value),
+            ),
+        }
+    }
+    overrides
+}
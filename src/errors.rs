@@ -1,35 +1,144 @@
-use hyper::{Body, Response};
+use crate::body::Body;
+use hyper::body::Bytes;
+use hyper::header::HeaderValue;
+use hyper::{Response, StatusCode};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+#[cfg(feature = "debug-errors")]
+use std::{cell::Cell, panic::Location};
 
 use crate::utils::{
     make_header_map_with_single_value, success_or_recoverable_error, unrecoverable_error,
 };
 use thiserror::Error;
 
+/// ErrorSource is the error a `FunctionError` was caused by, returned by `Error::source` so that
+/// the root cause can be inspected or downcast.
+pub type ErrorSource = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// FunctionError is the error of the FDK and of handlers. The message of a variant is in
+/// `inner`, and the error it was caused by, if any, in `source`.
 #[derive(Error, Debug)]
 pub enum FunctionError {
     #[error("Invalid input: {inner:?}")]
-    InvalidInput { inner: String },
+    InvalidInput {
+        inner: String,
+        #[source]
+        source: Option<ErrorSource>,
+    },
 
     #[error("Bad request")]
     BadRequest,
 
     #[error("Initialization failed: {inner:?}")]
-    Initialization { inner: String },
+    Initialization {
+        inner: String,
+        #[source]
+        source: Option<ErrorSource>,
+    },
 
     #[error("Coercion failed: {inner:?}")]
-    Coercion { inner: String },
+    Coercion {
+        inner: String,
+        #[source]
+        source: Option<ErrorSource>,
+    },
 
     #[error("IO Error: {inner:?}")]
-    IO { inner: String },
+    IO {
+        inner: String,
+        #[source]
+        source: Option<ErrorSource>,
+    },
 
     #[error("Server error: {inner:?}")]
-    Server { inner: String },
+    Server {
+        inner: String,
+        #[source]
+        source: Option<ErrorSource>,
+    },
 
     #[error("Internal system error: {inner:?}")]
-    System { inner: String },
+    System {
+        inner: String,
+        #[source]
+        source: Option<ErrorSource>,
+    },
 
     #[error("User error: {inner:?}")]
-    User { inner: String },
+    User {
+        inner: String,
+        #[source]
+        source: Option<ErrorSource>,
+    },
+
+    #[error("Timeout: {inner:?}")]
+    Timeout {
+        inner: String,
+        #[source]
+        source: Option<ErrorSource>,
+    },
+
+    #[error("Busy: {inner:?}")]
+    Busy {
+        inner: String,
+        #[source]
+        source: Option<ErrorSource>,
+    },
+
+    #[error("User error {code}: {inner:?}")]
+    Coded {
+        code: String,
+        inner: String,
+        #[source]
+        source: Option<ErrorSource>,
+    },
+
+    #[error("Coercion failed at {path} (line {line}, column {column}): {inner:?}")]
+    InvalidField {
+        inner: String,
+        path: String,
+        line: usize,
+        column: usize,
+        #[source]
+        source: Option<ErrorSource>,
+    },
+}
+
+/// ErrorKind identifies a `FunctionError` variant, e.g. to map it to a status code with
+/// `FunctionBuilder::error_status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    InvalidInput,
+    BadRequest,
+    Initialization,
+    Coercion,
+    IO,
+    Server,
+    System,
+    User,
+    Timeout,
+    Busy,
+}
+
+/// ErrorCode is implemented by user error types to tell their variants apart once converted
+/// with `FunctionError::from_coded`, so each can get its own status code through
+/// `FunctionBuilder::error_code_status`.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// impl ErrorCode for ShopError {
+///     fn error_code(&self) -> &'static str {
+///         match self {
+///             ShopError::UnknownItem(_) => "unknown_item",
+///             ShopError::OutOfStock(_) => "out_of_stock",
+///         }
+///     }
+/// }
+/// ```
+pub trait ErrorCode: std::fmt::Display {
+    fn error_code(&self) -> &'static str;
 }
 
 impl FunctionError {
@@ -39,57 +148,367 @@ impl FunctionError {
             Self::InvalidInput { .. }
                 | Self::BadRequest
                 | Self::Coercion { .. }
+                | Self::InvalidField { .. }
                 | Self::User { .. }
+                | Self::Coded { .. }
         )
     }
 
+    /// Returns the kind of the error. Coded errors are of kind `User`.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::InvalidInput { .. } => ErrorKind::InvalidInput,
+            Self::BadRequest => ErrorKind::BadRequest,
+            Self::Initialization { .. } => ErrorKind::Initialization,
+            Self::Coercion { .. } | Self::InvalidField { .. } => ErrorKind::Coercion,
+            Self::IO { .. } => ErrorKind::IO,
+            Self::Server { .. } => ErrorKind::Server,
+            Self::System { .. } => ErrorKind::System,
+            Self::User { .. } | Self::Coded { .. } => ErrorKind::User,
+            Self::Timeout { .. } => ErrorKind::Timeout,
+            Self::Busy { .. } => ErrorKind::Busy,
+        }
+    }
+
+    /// Creates a user error carrying the code of `error`.
+    #[cfg_attr(feature = "debug-errors", track_caller)]
+    pub fn from_coded<E: ErrorCode>(error: E) -> Self {
+        record_origin();
+        Self::Coded {
+            code: error.error_code().to_owned(),
+            inner: error.to_string(),
+            source: None,
+        }
+    }
+
+    #[cfg_attr(feature = "debug-errors", track_caller)]
     pub fn new_user_error(error: String) -> Self {
-        Self::User { inner: error }
+        record_origin();
+        Self::User {
+            inner: error,
+            source: None,
+        }
+    }
+
+    /// Sets the error this one was caused by. `BadRequest` has no source and is returned as is.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let order = db.find_order(id).map_err(|e| {
+    ///     FunctionError::new_user_error(format!("Unknown order {}", id)).with_source(e)
+    /// })?;
+    /// ```
+    #[cfg_attr(feature = "debug-errors", track_caller)]
+    pub fn with_source<E: Into<ErrorSource>>(mut self, error: E) -> Self {
+        record_origin();
+        match &mut self {
+            Self::InvalidInput { source, .. }
+            | Self::Initialization { source, .. }
+            | Self::Coercion { source, .. }
+            | Self::IO { source, .. }
+            | Self::Server { source, .. }
+            | Self::System { source, .. }
+            | Self::User { source, .. }
+            | Self::Timeout { source, .. }
+            | Self::Busy { source, .. }
+            | Self::Coded { source, .. }
+            | Self::InvalidField { source, .. } => *source = Some(error.into()),
+            Self::BadRequest => {}
+        }
+        self
+    }
+
+    /// Returns the error at the end of the source chain, or this error if it has no source.
+    pub fn root_cause(&self) -> &(dyn std::error::Error + 'static) {
+        let mut error: &(dyn std::error::Error + 'static) = self;
+        while let Some(source) = error.source() {
+            error = source;
+        }
+        error
+    }
+}
+
+/// ErrorStatusMap holds the status codes registered for error kinds and error codes.
+#[derive(Default)]
+pub(crate) struct ErrorStatusMap {
+    kinds: HashMap<ErrorKind, u16>,
+    codes: HashMap<String, u16>,
+}
+
+impl ErrorStatusMap {
+    pub(crate) fn insert_kind(&mut self, kind: ErrorKind, status: u16) {
+        self.kinds.insert(kind, status);
+    }
+
+    pub(crate) fn insert_code(&mut self, code: String, status: u16) {
+        self.codes.insert(code, status);
+    }
+
+    /// Fails if any registered status is not a valid HTTP status code.
+    pub(crate) fn validate(&self) -> Result<(), FunctionError> {
+        match self
+            .kinds
+            .values()
+            .chain(self.codes.values())
+            .find(|status| StatusCode::from_u16(**status).is_err())
+        {
+            Some(status) => Err(FunctionError::Initialization {
+                inner: format!("Invalid status code mapped to an error: {}", status),
+                source: None,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the status registered for `kind`.
+    pub(crate) fn status_for_kind(&self, kind: ErrorKind) -> Option<StatusCode> {
+        self.kinds
+            .get(&kind)
+            .and_then(|status| StatusCode::from_u16(*status).ok())
+    }
+
+    /// Returns the status registered for the code of `e`, or else for its kind.
+    pub(crate) fn status_for(&self, e: &FunctionError) -> Option<StatusCode> {
+        let by_code = match e {
+            FunctionError::Coded { code, .. } => self.codes.get(code),
+            _ => None,
+        };
+        by_code
+            .or_else(|| self.kinds.get(&e.kind()))
+            .and_then(|status| StatusCode::from_u16(*status).ok())
+    }
+}
+
+lazy_static! {
+    /// Answers invocations rejected by the concurrency limit.
+    pub(crate) static ref BUSY: StaticError = StaticError::new(FunctionError::Busy {
+        inner: "Too many concurrent invocations".into(),
+        source: None,
+    });
+    /// Answers invocations whose request body could not be read.
+    pub(crate) static ref READ_FAILURE: StaticError = StaticError::new(FunctionError::IO {
+        inner: "Failed to read request body".into(),
+        source: None,
+    });
+}
+
+/// StaticError is an error response serialized once, for the failures that come in storms
+/// under load, so that answering them formats no strings.
+pub(crate) struct StaticError {
+    error: FunctionError,
+    kind: ErrorKind,
+    status: StatusCode,
+    recoverable: bool,
+    body: Bytes,
+    content_length: HeaderValue,
+}
+
+impl StaticError {
+    /// Serializes `e` like `error_response` does.
+    fn new(e: FunctionError) -> Self {
+        let (status, recoverable) = match &e {
+            FunctionError::Timeout { .. } => (StatusCode::GATEWAY_TIMEOUT, true),
+            FunctionError::Busy { .. } => (StatusCode::SERVICE_UNAVAILABLE, true),
+            e if e.is_user_error() => (StatusCode::BAD_GATEWAY, true),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, false),
+        };
+        let body = Bytes::from(e.to_string());
+        Self {
+            kind: e.kind(),
+            error: e,
+            status,
+            recoverable,
+            content_length: body.len().into(),
+            body,
+        }
+    }
+
+    /// Returns the error the response is serialized from.
+    pub(crate) fn error(&self) -> &FunctionError {
+        &self.error
+    }
+
+    pub(crate) fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Returns the response, reporting the status mapped to the kind of the error in
+    /// `statuses` if any.
+    pub(crate) fn response(&self, statuses: &ErrorStatusMap) -> Response<Body> {
+        let body = Some(Body::from(self.body.clone()));
+        let headers = Some(make_header_map_with_single_value(
+            hyper::header::CONTENT_LENGTH,
+            self.content_length.clone(),
+        ));
+        match statuses.status_for_kind(self.kind) {
+            Some(status) => success_or_recoverable_error(status, body, headers),
+            None if self.recoverable => success_or_recoverable_error(self.status, body, headers),
+            None => unrecoverable_error(self.status, body, headers),
+        }
+    }
+}
+
+/// Converts an error to a response, reporting `status` in Fn-Http-Status when set.
+pub(crate) fn error_response(status: Option<StatusCode>, e: FunctionError) -> Response<Body> {
+    match status {
+        Some(status) if matches!(e, FunctionError::InvalidField { .. }) => field_error(status, &e),
+        Some(status) => {
+            let bytes = e.to_string().into_bytes();
+            let content_length = bytes.len();
+            success_or_recoverable_error(
+                status,
+                Option::from(Body::from(bytes)),
+                Option::from(make_header_map_with_single_value(
+                    hyper::header::CONTENT_LENGTH,
+                    content_length.into(),
+                )),
+            )
+        }
+        None => e.into(),
     }
 }
 
 impl From<FunctionError> for hyper::Response<Body> {
     fn from(e: FunctionError) -> hyper::Response<Body> {
-        if e.is_user_error() {
+        if let FunctionError::InvalidField { .. } = e {
+            field_error(StatusCode::BAD_REQUEST, &e)
+        } else if e.is_user_error() {
             client_error(format!("{}", e))
+        } else if let FunctionError::Timeout { .. } = e {
+            timeout_error(format!("{}", e))
+        } else if let FunctionError::Busy { .. } = e {
+            busy_error(format!("{}", e))
         } else {
             server_error(format!("{}", e))
         }
     }
 }
 
+/// Produces a response listing the offending field of an `InvalidField` error as JSON.
+fn field_error(status: StatusCode, e: &FunctionError) -> Response<Body> {
+    let body = match e {
+        FunctionError::InvalidField {
+            inner,
+            path,
+            line,
+            column,
+            ..
+        } => serde_json::json!({
+            "error": inner,
+            "field": path,
+            "line": line,
+            "column": column,
+        }),
+        _ => serde_json::json!({ "error": e.to_string() }),
+    };
+    let bytes = body.to_string().into_bytes();
+    let mut headers =
+        make_header_map_with_single_value(hyper::header::CONTENT_LENGTH, bytes.len().into());
+    headers.insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/json"),
+    );
+    success_or_recoverable_error(status, Option::from(Body::from(bytes)), Some(headers))
+}
+
 impl From<std::io::Error> for FunctionError {
+    #[cfg_attr(feature = "debug-errors", track_caller)]
     fn from(e: std::io::Error) -> Self {
+        record_origin();
         Self::IO {
             inner: e.to_string(),
+            source: Some(Box::new(e)),
         }
     }
 }
 
 impl From<std::env::VarError> for FunctionError {
+    #[cfg_attr(feature = "debug-errors", track_caller)]
     fn from(e: std::env::VarError) -> Self {
+        record_origin();
         Self::Initialization {
             inner: e.to_string(),
+            source: Some(Box::new(e)),
         }
     }
 }
 
 impl From<url::ParseError> for FunctionError {
+    #[cfg_attr(feature = "debug-errors", track_caller)]
     fn from(e: url::ParseError) -> Self {
+        record_origin();
         Self::Initialization {
-            inner: format!("Could not parse the URL: {}", e.to_string()),
+            inner: format!("Could not parse the URL: {}", e),
+            source: Some(Box::new(e)),
         }
     }
 }
 
 impl From<hyper::Error> for FunctionError {
+    #[cfg_attr(feature = "debug-errors", track_caller)]
     fn from(e: hyper::Error) -> Self {
+        record_origin();
         Self::Server {
             inner: e.to_string(),
+            source: Some(Box::new(e)),
         }
     }
 }
 
+#[cfg(feature = "debug-errors")]
+thread_local! {
+    static ORIGIN: Cell<Option<&'static Location<'static>>> = const { Cell::new(None) };
+}
+
+/// Records the caller as the origin of the error being created on this thread, with the
+/// `debug-errors` feature. The constructors and conversions calling it are `#[track_caller]`,
+/// so the origin is the line of the handler or FDK stage creating the error.
+#[cfg_attr(feature = "debug-errors", track_caller)]
+#[inline]
+pub(crate) fn record_origin() {
+    #[cfg(feature = "debug-errors")]
+    {
+        let location = Location::caller();
+        ORIGIN.with(|origin| origin.set(Some(location)));
+    }
+}
+
+/// Returns and clears the origin of the last error created on this thread.
+#[cfg(feature = "debug-errors")]
+pub(crate) fn take_origin() -> Option<&'static Location<'static>> {
+    ORIGIN.with(Cell::take)
+}
+
+/// Implements `From` for the errors of the serde formats, as coercion errors keeping the
+/// original error as their source.
+macro_rules! coercion_error_from {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for FunctionError {
+                #[cfg_attr(feature = "debug-errors", track_caller)]
+                fn from(e: $error) -> Self {
+                    record_origin();
+                    Self::Coercion {
+                        inner: e.to_string(),
+                        source: Some(Box::new(e)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+coercion_error_from!(
+    serde_json::Error,
+    serde_yaml::Error,
+    serde_plain::Error,
+    serde_urlencoded::de::Error,
+    serde_urlencoded::ser::Error,
+    quick_xml::DeError,
+    quick_xml::SeError,
+);
+
 /// A utility function that produces a client error response from a type that
 /// can be converted to a vector of bytes.
 pub fn client_error<T>(data: T) -> Response<Body>
@@ -125,3 +544,39 @@ where
         )),
     )
 }
+
+/// A utility function that produces a gateway timeout response from a type that
+/// can be converted to a vector of bytes.
+pub fn timeout_error<T>(data: T) -> Response<Body>
+where
+    T: Into<Vec<u8>>,
+{
+    let bytes: Vec<u8> = data.into();
+    let content_length = bytes.len();
+    success_or_recoverable_error(
+        hyper::StatusCode::GATEWAY_TIMEOUT,
+        Option::from(Body::from(bytes)),
+        Option::from(make_header_map_with_single_value(
+            hyper::header::CONTENT_LENGTH,
+            content_length.into(),
+        )),
+    )
+}
+
+/// A utility function that produces a service unavailable response from a type that
+/// can be converted to a vector of bytes.
+pub fn busy_error<T>(data: T) -> Response<Body>
+where
+    T: Into<Vec<u8>>,
+{
+    let bytes: Vec<u8> = data.into();
+    let content_length = bytes.len();
+    success_or_recoverable_error(
+        hyper::StatusCode::SERVICE_UNAVAILABLE,
+        Option::from(Body::from(bytes)),
+        Option::from(make_header_map_with_single_value(
+            hyper::header::CONTENT_LENGTH,
+            content_length.into(),
+        )),
+    )
+}
@@ -1,20 +1,43 @@
-use hyper::{Body, Request};
-use lazy_static::lazy_static;
-use object_pool::Pool;
-use std::io::Write;
+use crate::body::Body;
+use http_body_util::BodyExt;
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::service::service_fn;
+use hyper::{Request, Response};
+use hyper_util::rt::TokioIo;
+use hyper_util::server::graceful::GracefulShutdown;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::coercions::{ContentType, InputCoercible, OutputCoercible};
-use crate::context::RuntimeContext;
-use crate::errors::FunctionError;
-use crate::socket::UDS;
-use crate::utils::success_or_recoverable_error;
+use crate::coercions::{ContentType, InputCoercible, JsonStrictness, OutputCoercible};
+use crate::completion::{self, CompletionBody, CompletionCallbacks, PhaseTimings};
+#[cfg(feature = "compression")]
+use crate::compression::CompressionConfig;
+use crate::context::{check_http_headers, describe_coercion_choices, RuntimeContext};
+use crate::cors::CorsConfig;
+use crate::crash_dump::{self, CrashDumpConfig, RequestSnapshot, SnapshotGuard};
+use crate::dev::{self, FormatOverrides};
+use crate::errors::{self, error_response, ErrorKind, ErrorStatusMap, FunctionError, StaticError};
+use crate::limits::{self, ConcurrencyLimit, SaturationPolicy};
+use crate::logging::{self, Level, LogFormat};
+use crate::metrics::{self, MetricsExporter};
+use crate::probe::{probe_response, ProbeConfig};
+use crate::record;
+use crate::runtime::RuntimeConfig;
+use crate::server::{HttpProtocol, ServerConfig, ServerOptions};
+use crate::shutdown::{
+    self, ExitCodePolicy, ExitReason, Shutdown, ShutdownHook, ShutdownPhase, ShutdownTimeouts,
+};
+use crate::socket::{SocketPermissions, UDS};
+use crate::utils::{self, success_or_recoverable_error, ResponseEnvelope};
 
 pub type Result<OutputCoercible> = core::result::Result<OutputCoercible, FunctionError>;
 
-lazy_static! {
-    static ref POOL: Pool<Vec<u8>> = Pool::new(1024, || Vec::with_capacity(4096));
-}
-
 /// Function is the first class primitive provided by FDK to run functions on Oracle Cloud Functions and FnProject.
 pub struct Function;
 
@@ -34,127 +57,1439 @@ impl Function {
     /// ```
     pub async fn run<T, S, F>(function: F) -> Result<()>
     where
-        T: InputCoercible + 'static,
-        S: OutputCoercible + 'static,
+        T: InputCoercible + Send + 'static,
+        S: OutputCoercible + Send + 'static,
         F: Fn(&mut RuntimeContext, T) -> Result<S> + Send + Sync + 'static,
     {
-        Self::run_inner(std::sync::Arc::new(function)).await
+        Self::builder().run(function).await
+    }
+
+    /// Returns a `FunctionBuilder` to configure the FDK before running a function.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// Function::builder()
+    ///     .drain_timeout(Duration::from_secs(2))
+    ///     .on_shutdown(|| async { eprintln!("bye") })
+    ///     .run(|_: &mut RuntimeContext, i: String| Ok(i))
+    ///     .await
+    /// ```
+    pub fn builder() -> FunctionBuilder {
+        FunctionBuilder::default()
     }
 
-    async fn run_inner<T, S, F>(function: std::sync::Arc<F>) -> Result<()>
+    /// Runs `function` on the invocation recorded at `path` with `FN_RECORD_DIR`, or on the
+    /// request of a crash dump, and returns the response as it would be written to the Fn
+    /// agent. Redacted headers are replayed as `<redacted>`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let response = Function::replay("/tmp/fdk-record-01ABC-1700000000000.req", handler).await?;
+    /// println!("{} {:?}", response.status(), response.body());
+    /// ```
+    pub async fn replay<T, S, F>(
+        path: impl AsRef<std::path::Path>,
+        function: F,
+    ) -> Result<Response<Bytes>>
     where
-        T: InputCoercible + 'static,
-        S: OutputCoercible + 'static,
+        T: InputCoercible + Send + 'static,
+        S: OutputCoercible + Send + 'static,
         F: Fn(&mut RuntimeContext, T) -> Result<S> + Send + Sync + 'static,
     {
-        let socket = match UDS::new() {
-            Ok(s) => s,
-            Err(e) => return Err(e),
+        let req = record::read(path.as_ref())?;
+        let mut options = InvocationOptions::default();
+        options.prepare()?;
+        options.recorder = None;
+        invoke_once(Arc::new(function), Arc::new(options), req).await
+    }
+}
+
+/// FunctionBuilder holds the FDK configuration used by `FunctionBuilder::run`.
+#[derive(Default)]
+pub struct FunctionBuilder {
+    shutdown_timeouts: ShutdownTimeouts,
+    start_hooks: Vec<StartHook>,
+    shutdown_hooks: Vec<ShutdownHook>,
+    exit_code_policy: Option<ExitCodePolicy>,
+    crash_dumps: Option<CrashDumpConfig>,
+    log_format: Option<LogFormat>,
+    xml_root: Option<String>,
+    metrics_exporters: Vec<(Arc<dyn MetricsExporter>, Duration)>,
+    #[cfg(feature = "prometheus")]
+    metrics_listener: Option<String>,
+    #[cfg(feature = "statsd")]
+    statsd: Option<String>,
+    socket_permissions: SocketPermissions,
+    server: ServerOptions,
+    config_checks: Vec<ConfigCheck>,
+    #[cfg(feature = "secrets")]
+    resolve_secrets: bool,
+    runtime: Option<RuntimeConfig>,
+    options: InvocationOptions,
+}
+
+/// Initializes a resource before the function is served.
+type StartHook = Box<dyn FnOnce() -> futures::future::BoxFuture<'static, Result<()>> + Send>;
+
+/// Checks the config variables deserialize into a settings struct.
+type ConfigCheck = Box<dyn Fn(&HashMap<String, String>) -> Result<()> + Send>;
+
+/// Receives the errors of failed invocations.
+type ErrorReporter = Arc<dyn Fn(&RuntimeContext, &FunctionError) + Send + Sync>;
+
+impl FunctionBuilder {
+    /// Sets how long in-flight invocations may take to complete once shutdown starts.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeouts.drain = timeout;
+        self
+    }
+
+    /// Sets the total time budget of the `on_shutdown` hooks.
+    pub fn shutdown_hooks_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeouts.hooks = timeout;
+        self
+    }
+
+    /// Sets how long flushing logs may take during shutdown.
+    pub fn flush_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeouts.flush = timeout;
+        self
+    }
+
+    /// Registers a hook run at startup, in registration order, before the listener is created
+    /// so the Fn agent only routes invocations once every hook has completed, e.g. to open a
+    /// database pool. The function fails to start if a hook fails.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// Function::builder()
+    ///     .on_start(|| async { POOL.connect().await })
+    ///     .on_shutdown(|| async { POOL.close().await })
+    ///     .run(handler)
+    ///     .await
+    /// ```
+    pub fn on_start<H, Fut>(mut self, hook: H) -> Self
+    where
+        H: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.start_hooks.push(Box::new(move || {
+            Box::pin(hook()) as futures::future::BoxFuture<_>
+        }));
+        self
+    }
+
+    /// Registers a hook run after in-flight invocations have drained, in registration order.
+    pub fn on_shutdown<H, Fut>(mut self, hook: H) -> Self
+    where
+        H: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown_hooks.push(shutdown::boxed_hook(hook));
+        self
+    }
+
+    /// Makes `run` exit the process with the code `policy` assigns to the way the function
+    /// stopped, instead of returning. Errors are printed to stderr before exiting.
+    pub fn exit_code_policy(mut self, policy: ExitCodePolicy) -> Self {
+        self.exit_code_policy = Some(policy);
+        self
+    }
+
+    /// Persists the request being processed when it panics or fails with an unrecoverable
+    /// error, so the exact payload can be reproduced later.
+    pub fn crash_dumps(mut self, config: CrashDumpConfig) -> Self {
+        self.crash_dumps = Some(config);
+        self
+    }
+
+    /// Runs the user function under a timeout ending `margin` before the `Fn-Deadline`, and
+    /// answers with a 504 timeout error instead of letting the Fn agent cut the call off.
+    ///
+    /// The function then runs on tokio's blocking thread pool. It cannot be interrupted, so
+    /// it should watch `RuntimeContext::cancellation_token` to stop working after a timeout.
+    pub fn enforce_deadline(mut self, margin: Duration) -> Self {
+        self.options.deadline_margin = Some(margin);
+        self
+    }
+
+    /// Runs the user function under `timeout`, whatever the `Fn-Deadline`, and answers with a
+    /// 504 timeout error and logs the call when it takes longer, e.g. because a downstream
+    /// service hangs. With `enforce_deadline`, the shorter of both applies.
+    ///
+    /// The function then runs on tokio's blocking thread pool, like with `enforce_deadline`.
+    pub fn handler_timeout(mut self, timeout: Duration) -> Self {
+        self.options.handler_timeout = Some(timeout);
+        self
+    }
+
+    /// Adds `FN_METHOD`, `FN_REQUEST_URL`, `FN_CALL_ID`, `FN_DEADLINE`, `FN_PATH` and
+    /// `FN_HEADER_*` entries describing the current call to `RuntimeContext::config`, easing
+    /// the migration of functions written against the legacy default contract.
+    pub fn legacy_env_shim(mut self) -> Self {
+        self.options.legacy_env_shim = true;
+        self
+    }
+
+    /// Enables dev mode, also enabled by setting `FN_DEV_MODE=true`. In dev mode the
+    /// `_content_type` and `_accept` query parameters override the request and response
+    /// content types, e.g. `?_accept=text/yaml`, which eases manual testing with curl.
+    pub fn dev_mode(mut self) -> Self {
+        self.options.dev_mode = true;
+        self
+    }
+
+    /// Caps the number of invocations processed at the same time. Defaults to the value of
+    /// `FN_MAX_CONCURRENCY`, or no limit.
+    pub fn max_concurrency(mut self, max: usize) -> Self {
+        self.options.max_concurrency = Some(max);
+        self
+    }
+
+    /// Sets whether invocations beyond the concurrency limit wait or are rejected.
+    pub fn saturation_policy(mut self, policy: SaturationPolicy) -> Self {
+        self.options.saturation_policy = policy;
+        self
+    }
+
+    /// Sets the `Retry-After` of the 503 busy errors answering invocations rejected by the
+    /// saturation policy, telling the Fn agent when to try again. Defaults to one second.
+    pub fn busy_retry_after(mut self, delay: Duration) -> Self {
+        self.options.busy_retry_after = Some(delay);
+        self
+    }
+
+    /// Rejects request bodies larger than `bytes` with a 413 coercion error before buffering
+    /// them. Defaults to the value of `FN_MAX_BODY_SIZE`, or no limit.
+    pub fn max_body_size(mut self, bytes: usize) -> Self {
+        self.options.max_body_size = Some(bytes);
+        self
+    }
+
+    /// Sets how JSON request bodies with data after the first value are decoded. Defaults to
+    /// `JsonStrictness::Strict`, rejecting them with a coercion error.
+    pub fn json_strictness(mut self, strictness: JsonStrictness) -> Self {
+        self.options.json_strictness = strictness;
+        self
+    }
+
+    /// Compresses response bodies for clients listing an encoding enabled by the `zstd`,
+    /// `gzip` or `brotli` features in `Accept-Encoding`, preferring zstd, then brotli, gzip and
+    /// deflate among those of equal quality. zstd request bodies are decompressed with the
+    /// dictionary of `config` either way.
+    #[cfg(feature = "compression")]
+    pub fn compression(mut self, config: CompressionConfig) -> Self {
+        self.options.compression = config;
+        self.options.compress_responses = true;
+        self
+    }
+
+    /// Answers health probes designated by `config` with a fast 200, skipping coercion and
+    /// the user function. Readiness probes get a 503 while shutting down or saturated.
+    pub fn probes(mut self, config: ProbeConfig) -> Self {
+        self.options.probes = Some(config);
+        self
+    }
+
+    /// Answers CORS preflight requests and adds the CORS headers of `config` to the responses
+    /// to allowed origins, for functions called from browsers.
+    pub fn cors(mut self, config: CorsConfig) -> Self {
+        self.options.cors = Some(Arc::new(config));
+        self
+    }
+
+    /// Names the root element of XML responses `root`, instead of the name of the type of the
+    /// output, or `response` for outputs without one, such as strings and maps.
+    pub fn xml_root(mut self, root: &str) -> Self {
+        self.xml_root = Some(root.to_owned());
+        self
+    }
+
+    /// Sets the formats tried in order when the output cannot be encoded as the negotiated
+    /// content type, e.g. a nested struct as `application/x-www-form-urlencoded`. The response
+    /// then has the Content-Type of the format used. Defaults to JSON; an empty chain fails
+    /// such invocations with a coercion error. Outputs whose content type the handler set are
+    /// never encoded as another one.
+    pub fn output_fallback(mut self, formats: &[ContentType]) -> Self {
+        self.options.output_fallback = Some(formats.to_vec());
+        self
+    }
+
+    /// Sets the format of the lines the FDK writes to stderr. Defaults to the value of
+    /// `FN_LOG_FORMAT`, or `LogFormat::Text`.
+    pub fn log_format(mut self, format: LogFormat) -> Self {
+        self.log_format = Some(format);
+        self
+    }
+
+    /// Hands a snapshot of the FDK metrics to `exporter` every `interval`, and a last one
+    /// during the flush phase of the shutdown.
+    pub fn metrics_exporter<E: MetricsExporter + 'static>(
+        mut self,
+        exporter: E,
+        interval: Duration,
+    ) -> Self {
+        self.metrics_exporters.push((Arc::new(exporter), interval));
+        self
+    }
+
+    /// Serves the FDK metrics in the Prometheus text format on `GET /metrics` of a secondary
+    /// listener, `tcp://<host>:<port>` or `unix:///<path>`. Defaults to the value of
+    /// `FN_METRICS_LISTENER`, or no listener.
+    #[cfg(feature = "prometheus")]
+    pub fn metrics_listener(mut self, address: &str) -> Self {
+        self.metrics_listener = Some(address.to_owned());
+        self
+    }
+
+    /// Pushes the FDK metrics over UDP to the StatsD or DogStatsD server at `address`,
+    /// `<host>:<port>`: the counts every 10 seconds and the latency of every invocation as a
+    /// timing. The metric names are prefixed with `FN_STATSD_PREFIX`, `fdk` by default, and
+    /// carry the DogStatsD tags of `FN_STATSD_TAGS`, e.g. `env:prod,team:shop`. Defaults to
+    /// the value of `FN_STATSD_ADDRESS`, or no StatsD.
+    #[cfg(feature = "statsd")]
+    pub fn statsd(mut self, address: &str) -> Self {
+        self.statsd = Some(address.to_owned());
+        self
+    }
+
+    /// Selects between the Fn http-stream envelope, reporting the status in `Fn-Http-Status`
+    /// over a 200, and plain HTTP with the status on the wire, to run the same binary behind
+    /// the Fn agent or a reverse proxy. Defaults to the value of `FN_ENVELOPE`, `fn` or
+    /// `plain`, or `ResponseEnvelope::Fn`.
+    pub fn envelope(mut self, envelope: ResponseEnvelope) -> Self {
+        self.options.envelope = Some(envelope);
+        self
+    }
+
+    /// Sets the mode of the socket file, e.g. `0o660`. Defaults to the octal value of
+    /// `FN_SOCKET_MODE`, or `0o666`.
+    pub fn socket_mode(mut self, mode: u32) -> Self {
+        self.socket_permissions.mode = Some(mode);
+        self
+    }
+
+    /// Sets the owner of the socket file when running as root. Defaults to the values of
+    /// `FN_SOCKET_UID` and `FN_SOCKET_GID`, or the user running the function.
+    pub fn socket_owner(mut self, uid: u32, gid: u32) -> Self {
+        self.socket_permissions.uid = Some(uid);
+        self.socket_permissions.gid = Some(gid);
+        self
+    }
+
+    /// Sets the HTTP versions spoken with the Fn agent. Defaults to the value of
+    /// `FN_HTTP_PROTOCOL`, `auto`, `http1` or `http2`, or `HttpProtocol::Auto`.
+    pub fn http_protocol(mut self, protocol: HttpProtocol) -> Self {
+        self.server.protocol = Some(protocol);
+        self
+    }
+
+    /// Tunes the connections of the Fn agent, e.g. their keep-alive.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// Function::builder()
+    ///     .server(ServerConfig {
+    ///         header_read_timeout: Some(Duration::from_secs(300)),
+    ///         pipeline_flush: true,
+    ///         ..ServerConfig::default()
+    ///     })
+    ///     .run(handler)
+    ///     .await
+    /// ```
+    pub fn server(mut self, config: ServerConfig) -> Self {
+        self.server.config = config;
+        self
+    }
+
+    /// Reports `status` in Fn-Http-Status for errors of `kind`, instead of the default status
+    /// of the kind.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// Function::builder()
+    ///     .error_status(ErrorKind::Coercion, 422)
+    ///     .error_code_status("unknown_item", 404)
+    /// ```
+    pub fn error_status(mut self, kind: ErrorKind, status: u16) -> Self {
+        self.options.error_statuses.insert_kind(kind, status);
+        self
+    }
+
+    /// Reports `status` in Fn-Http-Status for errors created with `FunctionError::from_coded`
+    /// whose `ErrorCode::error_code` is `code`. Takes precedence over `error_status`.
+    pub fn error_code_status(mut self, code: &str, status: u16) -> Self {
+        self.options
+            .error_statuses
+            .insert_code(code.to_owned(), status);
+        self
+    }
+
+    /// Calls `reporter` with the context and error of every invocation failing with an error,
+    /// e.g. to forward it to an error tracking service. It runs before the error response is
+    /// sent, so slow work is best handed off to another task.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// Function::builder()
+    ///     .error_reporter(|ctx, e| {
+    ///         if !e.is_user_error() {
+    ///             tracker.capture(ctx.call_id_ref(), e.to_string());
+    ///         }
+    ///     })
+    ///     .run(handler)
+    ///     .await
+    /// ```
+    pub fn error_reporter<R>(mut self, reporter: R) -> Self
+    where
+        R: Fn(&RuntimeContext, &FunctionError) + Send + Sync + 'static,
+    {
+        self.options.error_reporter = Some(Arc::new(reporter));
+        self
+    }
+
+    /// Checks at startup that the config variables deserialize into `T` with
+    /// `RuntimeContext::config_as`, or with `RuntimeContext::config_as_prefixed` when `prefix`
+    /// is not empty. `run` fails with the error otherwise.
+    pub fn validate_config<T: DeserializeOwned + 'static>(mut self, prefix: &str) -> Self {
+        let prefix = prefix.to_owned();
+        self.config_checks.push(Box::new(move |config| {
+            crate::context::deserialize_config::<T>(config, &prefix).map(drop)
+        }));
+        self
+    }
+
+    /// Replaces the config values naming OCI Vault secrets, `vault:` followed by the OCID of a
+    /// secret, with the secrets at startup, fetched with the resource principal of the
+    /// function. `run` fails if a secret cannot be fetched.
+    #[cfg(feature = "secrets")]
+    pub fn resolve_secrets(mut self) -> Self {
+        self.resolve_secrets = true;
+        self
+    }
+
+    /// Sets the tokio runtime built by `run_blocking`. Defaults to the values of
+    /// `FN_RUNTIME_FLAVOR`, `FN_WORKER_THREADS` and `FN_MAX_BLOCKING_THREADS`, or a
+    /// multi-thread runtime with a worker thread per CPU.
+    pub fn runtime(mut self, config: RuntimeConfig) -> Self {
+        self.runtime = Some(config);
+        self
+    }
+
+    /// Builds the runtime set with `runtime` and runs the function on it like `run`, blocking
+    /// the calling thread. Call it from a plain `fn main` instead of `#[tokio::main]`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// fn main() -> Result<()> {
+    ///     Function::builder()
+    ///         .runtime(RuntimeConfig {
+    ///             flavor: RuntimeFlavor::CurrentThread,
+    ///             ..Default::default()
+    ///         })
+    ///         .run_blocking(|_: &mut RuntimeContext, i: String| Ok(i))
+    /// }
+    /// ```
+    pub fn run_blocking<T, S, F>(mut self, function: F) -> Result<()>
+    where
+        T: InputCoercible + Send + 'static,
+        S: OutputCoercible + Send + 'static,
+        F: Fn(&mut RuntimeContext, T) -> Result<S> + Send + Sync + 'static,
+    {
+        let config = match self.runtime.take() {
+            Some(config) => config,
+            None => RuntimeConfig::from_env()?,
         };
+        config.build()?.block_on(self.run(function))
+    }
 
-        let svc = hyper::service::make_service_fn(|_| {
-            let function = function.clone();
-            async move {
-                Ok::<_, FunctionError>(hyper::service::service_fn(move |req: Request<Body>| {
-                    let function = function.clone();
-                    async move {
-                        crate::logging::start_logging(req.headers());
-
-                        let mut ctx = RuntimeContext::from_req(&req);
-
-                        // We don't need buffer to live outside of the block we decode the request body
-                        let arg = {
-                            let mut buffer = match POOL.try_pull() {
-                                Some(buf) => buf,
-                                None => {
-                                    return Ok(FunctionError::System {
-                                        inner: "Failed to allocate memory".into(),
-                                    }
-                                    .into());
-                                }
-                            };
-                            let _ = buffer.write(
-                                match hyper::body::to_bytes(req.into_body()).await {
-                                    Ok(data) => data.to_vec(),
-                                    Err(e) => {
-                                        return Ok(FunctionError::IO {
-                                            inner: format!("Failed to read request body: {}", e),
-                                        }
-                                        .into());
-                                    }
-                                }
-                                .as_ref(),
-                            );
-
-                            let decoded_arg_result = decode_body(ctx.content_type(), &buffer);
-
-                            buffer.clear();
-
-                            let decoded_arg = match decoded_arg_result {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    return Ok(FunctionError::Coercion {
-                                        inner: format!(
-                                            "Error while deserializing request body: {}",
-                                            e
-                                        ),
-                                    }
-                                    .into())
-                                }
-                            };
-
-                            decoded_arg
-                        };
-
-                        let output_format = ctx.accept_type();
-
-                        let output = match function(&mut ctx, arg) {
-                            Ok(out) => out,
-                            Err(e) => match e {
-                                FunctionError::User { .. } => return Ok(e.into()),
-                                _ => {
-                                    return Ok(FunctionError::InvalidInput {
-                                        inner: format!("Error executing user function: {}", e),
-                                    }
-                                    .into())
-                                }
-                            },
-                        };
-
-                        let response_body = match encode_body(&output_format, output) {
-                            Ok(body) => body,
-                            Err(e) => {
-                                return Ok(FunctionError::Coercion {
-                                    inner: format!("Error while serializing response body: {}", e),
-                                }
-                                .into())
-                            }
-                        };
-
-                        let response_content_type = output_format.as_header_value();
-
-                        ctx.add_response_header(
-                            hyper::header::CONTENT_TYPE.as_str().to_owned(),
-                            response_content_type,
-                        );
+    /// Runs the function like `run` on the runtime of `handle`, blocking the calling thread
+    /// until it stops.
+    ///
+    /// The runtime must be multi-threaded: blocking on the handle of a current-thread runtime
+    /// does not drive its I/O, so the function fails to start with such a handle. Use
+    /// `run_blocking` to serve on a current-thread runtime.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from within an asynchronous execution context.
+    pub fn run_on<T, S, F>(self, handle: &tokio::runtime::Handle, function: F) -> Result<()>
+    where
+        T: InputCoercible + Send + 'static,
+        S: OutputCoercible + Send + 'static,
+        F: Fn(&mut RuntimeContext, T) -> Result<S> + Send + Sync + 'static,
+    {
+        if handle.runtime_flavor() != tokio::runtime::RuntimeFlavor::MultiThread {
+            return Err(FunctionError::Initialization {
+                inner: "run_on requires the handle of a multi-threaded runtime".to_owned(),
+                source: None,
+            });
+        }
+        handle.block_on(self.run(function))
+    }
+
+    /// Runs the function until the process receives SIGTERM or SIGINT, then shuts down in
+    /// order: stop accepting, drain in-flight invocations, run the shutdown hooks, flush logs
+    /// and remove the socket files.
+    pub async fn run<T, S, F>(self, function: F) -> Result<()>
+    where
+        T: InputCoercible + Send + 'static,
+        S: OutputCoercible + Send + 'static,
+        F: Fn(&mut RuntimeContext, T) -> Result<S> + Send + Sync + 'static,
+    {
+        let exit_code_policy = self.exit_code_policy.clone();
+        let (reason, result) = self.serve(function).await;
 
-                        Ok::<_, FunctionError>(success_or_recoverable_error(
-                            ctx.get_status_code().unwrap_or(hyper::StatusCode::OK),
-                            Option::from(Body::from(response_body)),
-                            Option::from(ctx.response_headers()),
-                        ))
+        match exit_code_policy {
+            Some(policy) => {
+                if let Err(e) = &result {
+                    eprintln!("{}", e);
+                }
+                std::process::exit(policy.code(reason))
+            }
+            None => result,
+        }
+    }
+
+    async fn serve<T, S, F>(#[allow(unused_mut)] mut self, function: F) -> (ExitReason, Result<()>)
+    where
+        T: InputCoercible + Send + 'static,
+        S: OutputCoercible + Send + 'static,
+        F: Fn(&mut RuntimeContext, T) -> Result<S> + Send + Sync + 'static,
+    {
+        let started = Instant::now();
+        let function = Arc::new(function);
+        if let Some(format) = self.log_format {
+            logging::set_format(format);
+        }
+        if let Some(root) = self.xml_root.take() {
+            crate::coercions::set_xml_root(root);
+        }
+        logging::install_panic_hook();
+        #[cfg(feature = "log")]
+        crate::log_bridge::install();
+        crate::legacy::warn_legacy_env(&crate::context::CONFIG_FROM_ENV);
+        crate::migration::warn_renamed_env(&crate::context::CONFIG_FROM_ENV);
+        if let Some(config) = self.crash_dumps {
+            crash_dump::enable(config);
+        }
+        #[cfg(feature = "secrets")]
+        if self.resolve_secrets {
+            match crate::secrets::resolve(&crate::context::CONFIG_FROM_ENV).await {
+                Ok(config) => self.options.config = Some(Arc::new(config)),
+                Err(e) => return (ExitReason::InitFailure, Err(e)),
+            }
+        }
+        for check in &self.config_checks {
+            if let Err(e) = check(self.options.config()) {
+                return (ExitReason::InitFailure, Err(e));
+            }
+        }
+
+        #[cfg(feature = "opentelemetry")]
+        if let Err(e) = crate::otel::init() {
+            return (ExitReason::InitFailure, Err(e));
+        }
+        #[cfg(feature = "statsd")]
+        if let Some(address) = self.statsd.take().or_else(crate::statsd::address_from_env) {
+            match crate::statsd::init(&address) {
+                Ok(statsd) => self
+                    .metrics_exporters
+                    .push((statsd, crate::statsd::PUSH_INTERVAL)),
+                Err(e) => return (ExitReason::InitFailure, Err(e)),
+            }
+        }
+
+        // Everything the first invocation would otherwise pay for happens before the listener
+        // is created, as the Fn agent routes invocations as soon as it appears.
+        let mut options = self.options;
+        if let Err(e) = options.prepare() {
+            return (ExitReason::InitFailure, Err(e));
+        }
+        warm_up();
+        for hook in self.start_hooks {
+            if let Err(e) = hook().await {
+                return (ExitReason::InitFailure, Err(e));
+            }
+        }
+
+        if let Some(req) = crate::invoke::request_from_args() {
+            let options = Arc::new(options);
+            let result = match req {
+                Ok(req) => invoke_once(function, options, req).await,
+                Err(e) => Err(e),
+            };
+            let result = result.and_then(|response| crate::invoke::print_response(&response));
+            for hook in self.shutdown_hooks {
+                hook().await;
+            }
+            return match result {
+                Ok(()) => (ExitReason::CleanShutdown, Ok(())),
+                Err(e) => (ExitReason::InitFailure, Err(e)),
+            };
+        }
+
+        // From here on the start hooks have run, so every exit goes through the shutdown
+        // phases for the shutdown hooks to release what they acquired.
+        #[allow(unused_mut)]
+        let mut shutdown = Shutdown {
+            timeouts: self.shutdown_timeouts,
+            hooks: self.shutdown_hooks,
+            exporters: self
+                .metrics_exporters
+                .iter()
+                .map(|(exporter, _)| exporter.clone())
+                .collect(),
+            // The Fn socket files are unlinked when the listener is dropped, which checks that
+            // the symlink still points to this instance first.
+            socket_files: Vec::new(),
+        };
+        let server = match self.server.connection_builder() {
+            Ok(server) => server,
+            Err(e) => {
+                shutdown.finish().await;
+                return (ExitReason::InitFailure, Err(e));
+            }
+        };
+        let mut socket = match UDS::new(&self.socket_permissions) {
+            Ok(s) => s,
+            Err(e) => {
+                shutdown.finish().await;
+                return (ExitReason::InitFailure, Err(e));
+            }
+        };
+        #[cfg(feature = "prometheus")]
+        let metrics_listener = match self
+            .metrics_listener
+            .or_else(crate::prometheus::listener_from_env)
+        {
+            Some(address) => match crate::prometheus::MetricsListener::bind(&address).await {
+                Ok(listener) => {
+                    shutdown.socket_files.extend(listener.socket_file());
+                    Some(tokio::spawn(listener.serve()))
+                }
+                Err(e) => {
+                    drop(socket);
+                    shutdown.finish().await;
+                    return (ExitReason::InitFailure, Err(e));
+                }
+            },
+            None => None,
+        };
+
+        let startup = started.elapsed();
+        metrics::global().record_startup(startup);
+        logging::log(
+            Level::Debug,
+            format_args!("ready in {} ms", startup.as_millis()),
+        );
+        options.startup = Some(startup);
+        let options = Arc::new(options);
+        let exporter_tasks: Vec<_> = self
+            .metrics_exporters
+            .into_iter()
+            .map(|(exporter, interval)| metrics::spawn_exporter(exporter, interval))
+            .collect();
+        let connections = GracefulShutdown::new();
+        let signal = shutdown::wait_for_signal();
+        tokio::pin!(signal);
+        let mut failure = None;
+        loop {
+            tokio::select! {
+                accepted = socket.accept() => match accepted {
+                    Ok(conn) => {
+                        let function = function.clone();
+                        let options = options.clone();
+                        let service = service_fn(move |req: Request<Incoming>| {
+                            let function = function.clone();
+                            let options = options.clone();
+                            async move { Ok::<_, Infallible>(serve_request(function, options, req).await) }
+                        });
+                        let conn = server
+                            .serve_connection(TokioIo::new(conn), service)
+                            .into_owned();
+                        let conn = connections.watch(conn);
+                        tokio::spawn(async move {
+                            let _ = conn.await;
+                        });
+                    }
+                    Err(e) => {
+                        failure = Some((ExitReason::ServerError, e));
+                        break;
+                    }
+                },
+                signal = &mut signal => {
+                    if let Err(e) = signal {
+                        failure = Some((ExitReason::InitFailure, e.into()));
                     }
-                }))
+                    break;
+                }
             }
-        });
+        }
+
+        options.shutting_down.store(true, Ordering::Relaxed);
+        Shutdown::log_phase(ShutdownPhase::StopAccepting);
+        drop(socket);
+
+        Shutdown::log_phase(ShutdownPhase::Drain);
+        let drain_timeout = shutdown.timeouts.drain;
+        let drained = tokio::time::timeout(drain_timeout, connections.shutdown()).await;
+        if drained.is_err() {
+            Shutdown::log_timeout(ShutdownPhase::Drain, drain_timeout);
+        }
+
+        for task in exporter_tasks {
+            task.abort();
+        }
+        #[cfg(feature = "prometheus")]
+        if let Some(task) = metrics_listener {
+            task.abort();
+        }
+        shutdown.finish().await;
+
+        match (failure, drained) {
+            (Some((reason, e)), _) => (reason, Err(e)),
+            (None, Ok(())) => (ExitReason::CleanShutdown, Ok(())),
+            (None, Err(_)) => (ExitReason::DrainTimeout, Ok(())),
+        }
+    }
+}
 
-        let _ = hyper::server::Server::builder(socket).serve(svc).await?;
+/// Formats tried when the output cannot be encoded as the negotiated content type, unless
+/// `FunctionBuilder::output_fallback` sets others.
+const DEFAULT_OUTPUT_FALLBACK: &[ContentType] = &[ContentType::JSON];
 
+/// InvocationOptions holds the builder settings applied to every invocation.
+#[derive(Default)]
+pub(crate) struct InvocationOptions {
+    deadline_margin: Option<Duration>,
+    handler_timeout: Option<Duration>,
+    legacy_env_shim: bool,
+    dev_mode: bool,
+    max_concurrency: Option<usize>,
+    saturation_policy: SaturationPolicy,
+    busy_retry_after: Option<Duration>,
+    concurrency_limit: Option<ConcurrencyLimit>,
+    error_statuses: ErrorStatusMap,
+    error_reporter: Option<ErrorReporter>,
+    max_body_size: Option<usize>,
+    json_strictness: JsonStrictness,
+    output_fallback: Option<Vec<ContentType>>,
+    #[cfg(feature = "compression")]
+    compression: CompressionConfig,
+    #[cfg(feature = "compression")]
+    compress_responses: bool,
+    probes: Option<ProbeConfig>,
+    cors: Option<Arc<CorsConfig>>,
+    envelope: Option<ResponseEnvelope>,
+    shutting_down: AtomicBool,
+    startup: Option<Duration>,
+    startup_reported: AtomicBool,
+    #[cfg(feature = "secrets")]
+    config: Option<Arc<HashMap<String, String>>>,
+    recorder: Option<CrashDumpConfig>,
+}
+
+impl InvocationOptions {
+    /// Returns the startup time to report on the first invocation, once.
+    fn startup_to_report(&self) -> Option<Duration> {
+        self.startup
+            .filter(|_| !self.startup_reported.swap(true, Ordering::Relaxed))
+    }
+
+    /// Returns the config of the function, with its secrets once resolved.
+    fn config(&self) -> &HashMap<String, String> {
+        #[cfg(feature = "secrets")]
+        if let Some(config) = &self.config {
+            return config;
+        }
+        &crate::context::CONFIG_FROM_ENV
+    }
+
+    /// Validates the builder settings and fills unset ones from the environment.
+    pub(crate) fn prepare(&mut self) -> Result<()> {
+        self.error_statuses.validate()?;
+        self.dev_mode |= dev::enabled_by_env();
+        if self.max_concurrency.is_none() {
+            self.max_concurrency = limits::max_concurrency_from_env()?;
+        }
+        if self.max_body_size.is_none() {
+            self.max_body_size = limits::max_body_size_from_env()?;
+        }
+        let policy = self.saturation_policy;
+        self.concurrency_limit = self
+            .max_concurrency
+            .map(|max| ConcurrencyLimit::new(max, policy));
+        #[cfg(feature = "compression")]
+        self.compression.load_dictionary_from_env()?;
+        if self.envelope.is_none() {
+            self.envelope = utils::envelope_from_env()?;
+        }
+        self.recorder = record::recorder_from_env();
         Ok(())
     }
 }
 
-fn encode_body<S: OutputCoercible>(content_type: &ContentType, s: S) -> Result<Vec<u8>> {
+/// Initializes the lazily built statics invocations use: the log level, the metrics, the
+/// precomputed error responses and the JSON serializer they are encoded with.
+fn warm_up() {
+    logging::enabled(Level::Debug);
+    metrics::global();
+    crate::socket::accept_stats();
+    lazy_static::initialize(&errors::BUSY);
+    lazy_static::initialize(&errors::READ_FAILURE);
+}
+
+/// Runs a single invocation outside of the listener and collects its response.
+async fn invoke_once<T, S, F>(
+    function: Arc<F>,
+    options: Arc<InvocationOptions>,
+    req: Request<Body>,
+) -> Result<Response<Bytes>>
+where
+    T: InputCoercible + Send + 'static,
+    S: OutputCoercible + Send + 'static,
+    F: Fn(&mut RuntimeContext, T) -> Result<S> + Send + Sync + 'static,
+{
+    let (parts, body) = serve_request(function, options, req).await.into_parts();
+    let body = body
+        .collect()
+        .await
+        .map_err(|e| FunctionError::IO {
+            inner: format!("Failed to read the response: {}", e),
+            source: Some(Box::new(e)),
+        })?
+        .to_bytes();
+    Ok(Response::from_parts(parts, body))
+}
+
+/// Answers health probes, and hands invocations to `handle_request`, recording their latency
+/// and running their completion callbacks once the response is written.
+pub(crate) async fn serve_request<T, S, F, B>(
+    function: Arc<F>,
+    options: Arc<InvocationOptions>,
+    req: Request<B>,
+) -> Response<CompletionBody>
+where
+    T: InputCoercible + Send + 'static,
+    S: OutputCoercible + Send + 'static,
+    F: Fn(&mut RuntimeContext, T) -> Result<S> + Send + Sync + 'static,
+    B: hyper::body::Body<Data = Bytes> + Send + Unpin + 'static,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    if let Some(kind) = options.probes.as_ref().and_then(|p| p.classify(&req)) {
+        let ready = !options.shutting_down.load(Ordering::Relaxed)
+            && options
+                .concurrency_limit
+                .as_ref()
+                .is_none_or(ConcurrencyLimit::has_capacity);
+        return completion::on_completion(
+            probe_response(kind, ready),
+            CompletionCallbacks::default(),
+            String::new(),
+            Instant::now(),
+            None,
+        );
+    }
+
+    let cors = match &options.cors {
+        Some(config) => match config.inspect(&req) {
+            (_, Some(preflight)) => {
+                return completion::on_completion(
+                    preflight,
+                    CompletionCallbacks::default(),
+                    String::new(),
+                    Instant::now(),
+                    None,
+                )
+            }
+            (request, None) => Some((config.clone(), request)),
+        },
+        None => None,
+    };
+
+    #[cfg(feature = "opentelemetry")]
+    let trace = crate::otel::tracing().map(|tracing| tracing.start(req.headers()));
+    #[cfg(feature = "tracing")]
+    let span = crate::spans::invocation_span(req.headers());
+    let start = Instant::now();
+    let call_id = crate::oci::forwarded_header(req.headers(), "Fn-Call-Id")
+        .unwrap_or_default()
+        .to_owned();
+    let callbacks = CompletionCallbacks::default();
+    let timings = PhaseTimings::default();
+    let envelope = options.envelope.unwrap_or_default();
+    let startup = options.startup_to_report();
+    let handled = logging::with_call_id(
+        call_id.clone(),
+        handle_request(function, options, req, callbacks.clone(), timings.clone()),
+    );
+    #[cfg(feature = "tracing")]
+    let handled = tracing::Instrument::instrument(handled, span.clone());
+    #[cfg(feature = "opentelemetry")]
+    let handled = crate::otel::instrument(handled, trace.as_ref());
+    let mut response = handled.await;
+    if let Some((config, request)) = &cors {
+        config.apply(request, response.headers_mut());
+    }
+    if let Some(startup) = startup {
+        response.headers_mut().insert(
+            HeaderName::from_static("fn-fdk-startup-ms"),
+            HeaderValue::from(startup.as_millis() as u64),
+        );
+    }
+    if envelope == ResponseEnvelope::Plain {
+        response = utils::unwrap_envelope(response);
+    }
+    metrics::global().record_invocation(start.elapsed());
+    #[cfg(feature = "opentelemetry")]
+    if let Some(cx) = &trace {
+        crate::otel::end(cx, &response);
+    }
+    #[cfg(feature = "tracing")]
+    crate::spans::record_status(&span, &response);
+    completion::on_completion(response, callbacks, call_id, start, Some(timings))
+}
+
+async fn handle_request<T, S, F, B>(
+    function: Arc<F>,
+    options: Arc<InvocationOptions>,
+    req: Request<B>,
+    callbacks: CompletionCallbacks,
+    timings: PhaseTimings,
+) -> Response<Body>
+where
+    T: InputCoercible + Send + 'static,
+    S: OutputCoercible + Send + 'static,
+    F: Fn(&mut RuntimeContext, T) -> Result<S> + Send + Sync + 'static,
+    B: hyper::body::Body<Data = Bytes> + Send + Unpin + 'static,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    let _permit = match &options.concurrency_limit {
+        Some(limit) => match limit.acquire().await {
+            Some(permit) => Some(permit),
+            None => {
+                if let Some(reporter) = &options.error_reporter {
+                    reporter(&RuntimeContext::from_req(&req), errors::BUSY.error());
+                }
+                return busy(&options);
+            }
+        },
+        None => None,
+    };
+
+    logging::start_logging(req.headers());
+
+    let mut ctx = RuntimeContext::from_req(&req);
+    if let Err(e) = check_http_headers(req.headers()) {
+        let status = options
+            .error_statuses
+            .status_for(&e)
+            .unwrap_or(hyper::StatusCode::BAD_REQUEST);
+        report(&options, &ctx, &e);
+        metrics::global().record_error(e.kind());
+        return error_response(Some(status), e);
+    }
+    #[cfg(feature = "secrets")]
+    if let Some(config) = &options.config {
+        ctx.set_config(config.clone());
+    }
+    ctx.set_completion_callbacks(callbacks);
+    let _deadline_timer = ctx.arm_deadline();
+    if options.legacy_env_shim {
+        ctx.shim_legacy_config(req.headers());
+    }
+    let overrides = if options.dev_mode {
+        dev::format_overrides(&req)
+    } else {
+        FormatOverrides::default()
+    };
+    let coercion_choices = if logging::enabled(Level::Debug) {
+        let (mut decode_choice, mut encode_choice) =
+            describe_coercion_choices(req.headers(), &ctx.content_type(), &ctx.accept_type());
+        if overrides.content_type.is_some() {
+            decode_choice = format!("overridden by {}", dev::CONTENT_TYPE_OVERRIDE_PARAM);
+        }
+        if overrides.accept_type.is_some() {
+            encode_choice = format!("overridden by {}", dev::ACCEPT_OVERRIDE_PARAM);
+        }
+        Some((decode_choice, encode_choice))
+    } else {
+        None
+    };
+    ctx.override_content_types(overrides.content_type, overrides.accept_type);
+    let crash_headers = if crash_dump::enabled() || options.recorder.is_some() {
+        Some(req.headers().clone())
+    } else {
+        None
+    };
+
+    // Over HTTP/1.1 hyper only writes trailers declared upfront in a `Trailer` header, which
+    // streamed responses cannot know, so HTTP trailers need HTTP/2 and `TE: trailers`.
+    let http_trailers = req.version() == hyper::Version::HTTP_2
+        && req
+            .headers()
+            .get_all(hyper::header::TE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .any(|v| {
+                v.split(',')
+                    .any(|t| t.trim().eq_ignore_ascii_case("trailers"))
+            });
+
+    #[cfg(feature = "compression")]
+    let (content_encoding, accept_encoding) = {
+        let header = |name| crate::oci::forwarded_header(req.headers(), name).map(str::to_owned);
+        (header("Content-Encoding"), header("Accept-Encoding"))
+    };
+
+    let read_start = Instant::now();
+    let body = limits::read_body(req.into_body(), options.max_body_size)
+        .await
+        .map(|(body, trailers)| {
+            ctx.set_request_trailers(trailers);
+            body
+        });
+    timings.set_body_read(read_start.elapsed());
+    #[cfg(feature = "compression")]
+    let body = body.and_then(|body| {
+        options
+            .compression
+            .decode_request(content_encoding.as_deref(), body, options.max_body_size)
+    });
+    // Oversized, badly encoded or interrupted bodies are the client's doing: they are
+    // answered with a 4xx and leave no crash dump.
+    let reject = |err: FunctionError, status: hyper::StatusCode| {
+        let status = options.error_statuses.status_for(&err).unwrap_or(status);
+        report(&options, &ctx, &err);
+        metrics::global().record_error(err.kind());
+        error_response(Some(status), err)
+    };
+    let body = match body {
+        Ok(data) => data,
+        Err(err) if limits::is_body_too_large(&err) => {
+            return reject(err, hyper::StatusCode::PAYLOAD_TOO_LARGE)
+        }
+        Err(err @ FunctionError::InvalidInput { .. }) => {
+            let status = if is_unsupported_encoding(&err) {
+                hyper::StatusCode::UNSUPPORTED_MEDIA_TYPE
+            } else {
+                hyper::StatusCode::BAD_REQUEST
+            };
+            return reject(err, status);
+        }
+        Err(err @ FunctionError::IO { .. }) => {
+            if logging::enabled(Level::Debug) {
+                logging::log(
+                    Level::Debug,
+                    format_args!("call {}: {}", ctx.call_id_ref(), err),
+                );
+            }
+            report(&options, &ctx, errors::READ_FAILURE.error());
+            return fail_static(&options, &errors::READ_FAILURE);
+        }
+        Err(err) => {
+            if let Some(headers) = crash_headers {
+                if let Some(snapshot) = RequestSnapshot::capture(ctx.call_id_ref(), &headers, &[]) {
+                    snapshot.dump(&err.to_string());
+                }
+            }
+            return fail(&options, &ctx, err);
+        }
+    };
+    metrics::global().record_request_size(body.len());
+    #[cfg(feature = "oci-events")]
+    ctx.set_body(body.clone());
+    if let (Some(recorder), Some(headers)) = (&options.recorder, &crash_headers) {
+        record::record(recorder, ctx.call_id_ref(), headers, &body);
+    }
+    let snapshot = crash_headers
+        .and_then(|headers| RequestSnapshot::capture(ctx.call_id_ref(), &headers, &body));
+
+    let arg = {
+        if let Some((decode_choice, _)) = &coercion_choices {
+            logging::log(
+                Level::Debug,
+                format_args!(
+                    "call {}: decoding {} bytes as {} ({})",
+                    ctx.call_id_ref(),
+                    body.len(),
+                    ctx.content_type().as_header_value(),
+                    decode_choice
+                ),
+            );
+        }
+        #[cfg(feature = "tracing")]
+        let _decode = tracing::debug_span!("decode", bytes = body.len()).entered();
+        let decoded_arg_result = match T::try_decode_request(&ctx, &body) {
+            Some(Ok(arg)) => Ok(arg),
+            Some(Err(e)) => return fail(&options, &ctx, e),
+            None => decode_body(ctx.content_type(), options.json_strictness, &body),
+        };
+
+        let decoded_arg = match decoded_arg_result {
+            Ok(v) => v,
+            Err(e @ FunctionError::InvalidField { .. }) => return fail(&options, &ctx, e),
+            Err(e) => {
+                return fail(
+                    &options,
+                    &ctx,
+                    FunctionError::Coercion {
+                        inner: format!("Error while deserializing request body: {}", e),
+                        source: Some(Box::new(e)),
+                    },
+                )
+            }
+        };
+
+        decoded_arg
+    };
+
+    let budget = options
+        .deadline_margin
+        .and_then(|margin| ctx.time_remaining().map(|r| r.saturating_sub(margin)));
+    let budget = match (budget, options.handler_timeout) {
+        (Some(budget), Some(timeout)) => Some(budget.min(timeout)),
+        (budget, timeout) => budget.or(timeout),
+    };
+    let handler_start = Instant::now();
+    let result = match budget {
+        Some(budget) => {
+            let call_id = ctx.call_id();
+            let reported_ctx = options.error_reporter.as_ref().map(|_| ctx.clone());
+            match call_with_timeout(function, ctx, arg, snapshot, budget).await {
+                Ok((returned_ctx, result)) => {
+                    ctx = returned_ctx;
+                    result
+                }
+                Err(e) => {
+                    timings.set_handler(handler_start.elapsed());
+                    if let FunctionError::Timeout { .. } = e {
+                        logging::log(
+                            Level::Warn,
+                            format_args!(
+                                "call {}: the user function timed out after {} ms",
+                                call_id,
+                                handler_start.elapsed().as_millis()
+                            ),
+                        );
+                    }
+                    if let Some(ctx) = &reported_ctx {
+                        report(&options, ctx, &e);
+                    }
+                    return fail_response(&options, e);
+                }
+            }
+        }
+        None => call(&*function, &mut ctx, arg, snapshot.as_ref()),
+    };
+    timings.set_handler(handler_start.elapsed());
+
+    let mut output = match result {
+        Ok(out) => out,
+        Err(e) => {
+            let status = options.error_statuses.status_for(&e);
+            report(&options, &ctx, &e);
+            metrics::global().record_error(e.kind());
+            return match e {
+                FunctionError::User { .. }
+                | FunctionError::Coded { .. }
+                | FunctionError::InvalidField { .. } => error_response(status, e),
+                _ => error_response(
+                    status,
+                    FunctionError::InvalidInput {
+                        inner: format!("Error executing user function: {}", e),
+                        source: Some(Box::new(e)),
+                    },
+                ),
+            };
+        }
+    };
+
+    let parts = output.take_response_parts();
+    let own_response = parts.is_some();
+    if let Some((status, headers)) = parts {
+        ctx.set_response_parts(status, headers);
+    }
+    if ctx.is_redirect() {
+        metrics::global().record_response_size(0);
+        return success_or_recoverable_error(
+            ctx.get_status_code().unwrap_or(hyper::StatusCode::FOUND),
+            None,
+            Some(ctx.take_response_headers()),
+        );
+    }
+    let content_type = ctx.response_content_type().cloned();
+    if let Some(stream) = output.take_stream() {
+        if let Some(content_type) = content_type {
+            ctx.insert_response_header(hyper::header::CONTENT_TYPE, content_type);
+        }
+        return success_or_recoverable_error(
+            ctx.get_status_code().unwrap_or(hyper::StatusCode::OK),
+            Some(stream.into_body(http_trailers)),
+            Some(ctx.take_response_headers()),
+        );
+    }
+
+    let negotiated_format = response_format(&ctx);
+    let mut formats = vec![negotiated_format.clone()];
+    if content_type.is_none() {
+        let fallback = options
+            .output_fallback
+            .as_deref()
+            .unwrap_or(DEFAULT_OUTPUT_FALLBACK);
+        formats.extend(
+            fallback
+                .iter()
+                .filter(|f| **f != negotiated_format)
+                .cloned(),
+        );
+    }
+    let encoded = {
+        #[cfg(feature = "tracing")]
+        let _encode = tracing::debug_span!("encode").entered();
+        output.try_encode_first_of(&formats)
+    };
+    let (output_format, response_body) = match encoded {
+        Ok(encoded) => encoded,
+        Err(e) => {
+            return fail(
+                &options,
+                &ctx,
+                FunctionError::Coercion {
+                    inner: format!("Error while serializing response body: {}", e),
+                    source: Some(Box::new(e)),
+                },
+            )
+        }
+    };
+
+    if let Some((_, encode_choice)) = &coercion_choices {
+        let encode_choice = match &content_type {
+            Some(_) => "set by the handler",
+            None if output_format != negotiated_format => "fallback",
+            None => encode_choice.as_str(),
+        };
+        logging::log(
+            Level::Debug,
+            format_args!(
+                "call {}: encoded {} bytes as {} ({})",
+                ctx.call_id_ref(),
+                response_body.len(),
+                output_format.as_header_value(),
+                encode_choice
+            ),
+        );
+    }
+
+    // Bodies the handler already encoded itself are sent as they are.
+    #[cfg(feature = "compression")]
+    let response_body = if options.compress_responses
+        && !ctx.has_response_header(hyper::header::CONTENT_ENCODING)
+    {
+        let (body, encoding) = options
+            .compression
+            .encode_response(accept_encoding.as_deref(), response_body);
+        if let Some(encoding) = encoding {
+            ctx.insert_response_header(
+                hyper::header::CONTENT_ENCODING,
+                HeaderValue::from_static(encoding),
+            );
+        }
+        ctx.append_response_header_value(
+            hyper::header::VARY,
+            HeaderValue::from_static("accept-encoding"),
+        );
+        body
+    } else {
+        response_body
+    };
+    metrics::global().record_response_size(response_body.len());
+
+    // Outputs setting their own response without a body, such as `FnResponse::redirect`, get
+    // no Content-Type.
+    let content_type = content_type.or_else(|| {
+        if own_response && response_body.is_empty() {
+            None
+        } else {
+            HeaderValue::from_str(&output_format.as_header_value()).ok()
+        }
+    });
+    if let Some(content_type) = content_type {
+        ctx.insert_response_header(hyper::header::CONTENT_TYPE, content_type);
+    }
+
+    success_or_recoverable_error(
+        ctx.get_status_code().unwrap_or(hyper::StatusCode::OK),
+        Option::from(Body::from(response_body)),
+        Option::from(ctx.take_response_headers()),
+    )
+}
+
+/// Reports the error of an invocation and converts it to a response.
+#[cfg_attr(feature = "debug-errors", track_caller)]
+fn fail(options: &InvocationOptions, ctx: &RuntimeContext, e: FunctionError) -> Response<Body> {
+    report(options, ctx, &e);
+    fail_response(options, e)
+}
+
+/// Converts an error to a response, applying the status registered for it.
+#[cfg_attr(feature = "debug-errors", track_caller)]
+fn fail_response(options: &InvocationOptions, e: FunctionError) -> Response<Body> {
+    #[cfg(feature = "debug-errors")]
+    logging::log(
+        Level::Warn,
+        format_args!(
+            "call {}: the invocation failed at {}: {}",
+            logging::current_call_id().unwrap_or_default(),
+            std::panic::Location::caller(),
+            e
+        ),
+    );
+    metrics::global().record_error(e.kind());
+    error_response(options.error_statuses.status_for(&e), e)
+}
+
+/// Passes the error of a failed invocation to the error reporter, if one is set.
+fn report(options: &InvocationOptions, ctx: &RuntimeContext, e: &FunctionError) {
+    if let Some(reporter) = &options.error_reporter {
+        reporter(ctx, e);
+    }
+}
+
+fn fail_static(options: &InvocationOptions, e: &StaticError) -> Response<Body> {
+    metrics::global().record_error(e.kind());
+    e.response(&options.error_statuses)
+}
+
+/// Answers an invocation rejected by the saturation policy, with a `Retry-After` in seconds.
+fn busy(options: &InvocationOptions) -> Response<Body> {
+    let mut response = fail_static(options, &errors::BUSY);
+    let delay = options
+        .busy_retry_after
+        .unwrap_or(limits::DEFAULT_BUSY_RETRY_AFTER);
+    let seconds = delay.as_secs() + u64::from(delay.subsec_nanos() > 0);
+    response
+        .headers_mut()
+        .insert(hyper::header::RETRY_AFTER, HeaderValue::from(seconds));
+    response
+}
+
+/// Calls the user function with the call ID and crash snapshot of the invocation attached to
+/// the current thread.
+fn call<T, S, F>(
+    function: &F,
+    ctx: &mut RuntimeContext,
+    arg: T,
+    snapshot: Option<&Arc<RequestSnapshot>>,
+) -> Result<S>
+where
+    F: Fn(&mut RuntimeContext, T) -> Result<S>,
+{
+    let _call_id = logging::CallIdGuard::enter(ctx.call_id_ref());
+    let _snapshot = snapshot.map(SnapshotGuard::enter);
+    #[cfg(feature = "tracing")]
+    let _span = tracing::info_span!("handler").entered();
+    #[cfg(feature = "debug-errors")]
+    errors::take_origin();
+    let result = function(ctx, arg);
+    #[cfg(feature = "debug-errors")]
+    if let Err(e) = &result {
+        match errors::take_origin() {
+            Some(origin) => logging::log(
+                Level::Warn,
+                format_args!(
+                    "call {}: the user function failed at {}: {}",
+                    ctx.call_id_ref(),
+                    origin,
+                    e
+                ),
+            ),
+            None => logging::log(
+                Level::Warn,
+                format_args!(
+                    "call {}: the user function failed: {}",
+                    ctx.call_id_ref(),
+                    e
+                ),
+            ),
+        }
+    }
+    result
+}
+
+/// Calls the user function on the blocking thread pool and gives up once `budget` has elapsed.
+/// The context's cancellation token is cancelled on timeout so the function can stop early.
+async fn call_with_timeout<T, S, F>(
+    function: Arc<F>,
+    mut ctx: RuntimeContext,
+    arg: T,
+    snapshot: Option<Arc<RequestSnapshot>>,
+    budget: Duration,
+) -> core::result::Result<(RuntimeContext, Result<S>), FunctionError>
+where
+    T: Send + 'static,
+    S: Send + 'static,
+    F: Fn(&mut RuntimeContext, T) -> Result<S> + Send + Sync + 'static,
+{
+    let token = ctx.cancellation_token();
+    #[cfg(feature = "tracing")]
+    let span = tracing::Span::current();
+    let handle = tokio::task::spawn_blocking(move || {
+        #[cfg(feature = "tracing")]
+        let _span = span.entered();
+        let result = call(&*function, &mut ctx, arg, snapshot.as_ref());
+        (ctx, result)
+    });
+
+    match tokio::time::timeout(budget, handle).await {
+        Ok(Ok(completed)) => Ok(completed),
+        Ok(Err(e)) => Err(FunctionError::System {
+            inner: format!("User function failed: {}", e),
+            source: Some(Box::new(e)),
+        }),
+        Err(_) => {
+            token.cancel();
+            Err(FunctionError::Timeout {
+                inner: format!("User function did not complete within {:?}", budget),
+                source: None,
+            })
+        }
+    }
+}
+
+/// Returns the content type the output is encoded as: the one set by the handler if the FDK
+/// supports it, plain text if not, or else the one negotiated from the Accept header.
+pub(crate) fn response_format(ctx: &RuntimeContext) -> ContentType {
+    match ctx.response_content_type() {
+        Some(content_type) => content_type
+            .to_str()
+            .ok()
+            .and_then(|v| ContentType::parse(v.split(';').next().unwrap_or_default().trim()))
+            .unwrap_or(ContentType::Plain),
+        None => ctx.accept_type(),
+    }
+}
+
+/// Returns true if `e` was returned because the request Content-Encoding is not supported.
+#[cfg(feature = "compression")]
+fn is_unsupported_encoding(e: &FunctionError) -> bool {
+    crate::compression::is_unsupported_encoding(e)
+}
+
+#[cfg(not(feature = "compression"))]
+fn is_unsupported_encoding(_: &FunctionError) -> bool {
+    false
+}
+
+pub(crate) fn encode_body<S: OutputCoercible>(content_type: &ContentType, s: S) -> Result<Vec<u8>> {
     match content_type {
         ContentType::JSON => S::try_encode_json(s),
         ContentType::YAML => S::try_encode_yaml(s),
@@ -164,15 +1499,240 @@ fn encode_body<S: OutputCoercible>(content_type: &ContentType, s: S) -> Result<V
     }
 }
 
-fn decode_body<T: InputCoercible>(
+pub(crate) fn decode_body<T: InputCoercible>(
     content_type: ContentType,
-    buffer: &object_pool::Reusable<Vec<u8>>,
+    json_strictness: JsonStrictness,
+    buffer: &[u8],
 ) -> Result<T> {
     match content_type {
-        ContentType::JSON => T::try_decode_json(buffer.to_vec()),
-        ContentType::YAML => T::try_decode_yaml(buffer.to_vec()),
-        ContentType::XML => T::try_decode_xml(buffer.to_vec()),
-        ContentType::Plain => T::try_decode_plain(buffer.to_vec()),
-        ContentType::URLEncoded => T::try_decode_urlencoded(buffer.to_vec()),
+        ContentType::JSON => match json_strictness {
+            JsonStrictness::Strict => T::try_decode_json(buffer),
+            JsonStrictness::FirstValue => T::try_decode_json_first_value(buffer),
+        },
+        ContentType::YAML => T::try_decode_yaml(buffer),
+        ContentType::XML => T::try_decode_xml(buffer),
+        ContentType::Plain => T::try_decode_plain(buffer),
+        ContentType::URLEncoded => T::try_decode_urlencoded(buffer),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Body;
+    use hyper::StatusCode;
+
+    async fn reported_status(headers: &[(&'static str, &'static [u8])]) -> StatusCode {
+        let function = Arc::new(|_: &mut RuntimeContext, input: String| Ok(input));
+        let mut req = Request::builder()
+            .header("Fn-Intent", "httprequest")
+            .header("Fn-Call-Id", "01CALL")
+            .header("Content-Type", "text/plain");
+        for (name, value) in headers {
+            req = req.header(*name, HeaderValue::from_bytes(value).unwrap());
+        }
+        let req = req.body(Body::from("hello")).unwrap();
+        let response = serve_request(function, Arc::default(), req).await;
+        completion::reported_status(response.status(), response.headers())
+    }
+
+    #[tokio::test]
+    async fn bad_header_bytes_are_rejected_with_400() {
+        for headers in [
+            &[("Fn-Http-Method", &b"G\xffT"[..])][..],
+            &[("Fn-Http-Request-Url", &b"/caf\xe9"[..])][..],
+            &[("Fn-Http-H-", &b"value"[..])][..],
+        ] {
+            assert_eq!(reported_status(headers).await, StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn multibyte_text_split_across_chunks_is_decoded() {
+        let function = Arc::new(|_: &mut RuntimeContext, input: String| Ok(input));
+        let text = "héllo 🦀";
+        // Splits the "é" after its first byte and the crab after its second one.
+        let bytes = text.as_bytes();
+        let chunks = vec![&bytes[..2], &bytes[2..9], &bytes[9..]]
+            .into_iter()
+            .map(|chunk| Ok::<_, Infallible>(hyper::body::Frame::data(Bytes::from(chunk))));
+        let req = Request::builder()
+            .header("Fn-Call-Id", "01CALL")
+            .header("Content-Type", "text/plain")
+            .header("Accept", "text/plain")
+            .body(http_body_util::StreamBody::new(futures::stream::iter(
+                chunks,
+            )))
+            .unwrap();
+        let response = serve_request(function, Arc::default(), req).await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], text.as_bytes());
+    }
+
+    async fn body_status(
+        options: InvocationOptions,
+        encoding: &str,
+        body: &'static [u8],
+    ) -> StatusCode {
+        let function = Arc::new(|_: &mut RuntimeContext, input: String| Ok(input));
+        let req = Request::builder()
+            .header("Fn-Call-Id", "01CALL")
+            .header("Content-Type", "text/plain")
+            .header("Content-Encoding", encoding)
+            .body(Body::from(Bytes::from_static(body)))
+            .unwrap();
+        let response = serve_request(function, Arc::new(options), req).await;
+        completion::reported_status(response.status(), response.headers())
+    }
+
+    #[tokio::test]
+    async fn oversized_bodies_are_rejected_with_413() {
+        let options = InvocationOptions {
+            max_body_size: Some(4),
+            ..Default::default()
+        };
+        let status = body_status(options, "identity", b"too large").await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[tokio::test]
+    async fn badly_encoded_bodies_are_rejected_with_4xx() {
+        let status = body_status(InvocationOptions::default(), "x-unknown", b"hello").await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let status = body_status(InvocationOptions::default(), "gzip", b"not gzip").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    async fn redirect_response<S, F>(function: F) -> Response<CompletionBody>
+    where
+        S: OutputCoercible + Send + 'static,
+        F: Fn(&mut RuntimeContext, String) -> Result<S> + Send + Sync + 'static,
+    {
+        let req = Request::builder()
+            .header("Fn-Intent", "httprequest")
+            .header("Fn-Call-Id", "01CALL")
+            .header("Fn-Http-Method", "GET")
+            .header("Fn-Http-Request-Url", "/account")
+            .header("Fn-Http-H-Accept", "application/json")
+            .header("Content-Type", "text/plain")
+            .body(Body::from(""))
+            .unwrap();
+        serve_request(Arc::new(function), Arc::default(), req).await
+    }
+
+    #[tokio::test]
+    async fn context_redirects_have_no_body_or_content_type() {
+        let response = redirect_response(|ctx: &mut RuntimeContext, _: String| {
+            ctx.redirect("/login", StatusCode::SEE_OTHER)?;
+            Ok(String::new())
+        })
+        .await;
+        let headers = response.headers().clone();
+        assert_eq!(headers["Fn-Http-Status"], "303");
+        assert_eq!(headers["Fn-Http-H-Location"], "/login");
+        assert!(headers.get("Content-Type").is_none());
+        assert!(headers.get("Fn-Http-H-Content-Type").is_none());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn response_redirects_have_no_body_or_content_type() {
+        let response = redirect_response(|_: &mut RuntimeContext, _: String| {
+            crate::FnResponse::temporary_redirect("/login")
+        })
+        .await;
+        let headers = response.headers().clone();
+        assert_eq!(headers["Fn-Http-Status"], "307");
+        assert_eq!(headers["Fn-Http-H-Location"], "/login");
+        assert!(headers.get("Content-Type").is_none());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(body.is_empty());
+    }
+
+    #[cfg(feature = "gzip")]
+    async fn compressed_response<F>(accept_encoding: &str, function: F) -> (hyper::HeaderMap, Bytes)
+    where
+        F: Fn(&mut RuntimeContext, String) -> Result<String> + Send + Sync + 'static,
+    {
+        let options = InvocationOptions {
+            compress_responses: true,
+            ..Default::default()
+        };
+        let req = Request::builder()
+            .header("Fn-Call-Id", "01CALL")
+            .header("Content-Type", "text/plain")
+            .header("Accept", "text/plain")
+            .header("Accept-Encoding", accept_encoding)
+            .body(Body::from(""))
+            .unwrap();
+        let response = serve_request(Arc::new(function), Arc::new(options), req).await;
+        let headers = response.headers().clone();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (headers, body)
+    }
+
+    #[cfg(feature = "gzip")]
+    #[tokio::test]
+    async fn compression_keeps_the_vary_values_of_the_handler() {
+        let (headers, _) = compressed_response("gzip", |ctx: &mut RuntimeContext, _: String| {
+            ctx.add_response_header("Vary", "Origin")?;
+            Ok("compressible ".repeat(1000))
+        })
+        .await;
+        let vary: Vec<_> = headers.get_all("Vary").iter().collect();
+        assert_eq!(vary, ["Origin", "accept-encoding"]);
+        assert_eq!(headers["Content-Encoding"], "gzip");
+    }
+
+    #[cfg(feature = "gzip")]
+    #[tokio::test]
+    async fn bodies_encoded_by_the_handler_are_not_compressed_again() {
+        let body = "already encoded ".repeat(1000);
+        let expected = body.clone();
+        let (headers, sent) =
+            compressed_response("gzip", move |ctx: &mut RuntimeContext, _: String| {
+                ctx.add_response_header("Content-Encoding", "br")?;
+                Ok(body.clone())
+            })
+            .await;
+        assert_eq!(headers["Content-Encoding"], "br");
+        assert_eq!(&sent[..], expected.as_bytes());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[tokio::test]
+    async fn identity_only_clients_get_uncompressed_bodies() {
+        let body = "compressible ".repeat(1000);
+        let expected = body.clone();
+        let (headers, sent) = compressed_response(
+            "identity, gzip;q=0",
+            move |_: &mut RuntimeContext, _: String| Ok(body.clone()),
+        )
+        .await;
+        assert!(!headers.contains_key("Content-Encoding"));
+        assert_eq!(headers["Vary"], "accept-encoding");
+        assert_eq!(&sent[..], expected.as_bytes());
+    }
+
+    #[test]
+    fn run_on_rejects_current_thread_runtimes() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let result =
+            Function::builder().run_on(runtime.handle(), |_: &mut RuntimeContext, i: String| Ok(i));
+        assert!(matches!(result, Err(FunctionError::Initialization { .. })));
+    }
+
+    #[tokio::test]
+    async fn obs_text_forwarded_values_are_served() {
+        let headers = [
+            ("Fn-Http-Method", &b"GET"[..]),
+            ("Fn-Http-Request-Url", &b"/hello"[..]),
+            ("Fn-Http-H-X-Name", &b"caf\xe9"[..]),
+        ];
+        assert_eq!(reported_status(&headers).await, StatusCode::OK);
     }
 }
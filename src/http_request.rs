@@ -0,0 +1,171 @@
+//! The HTTP request of the client of an HTTP trigger, as a handler input.
+//!
+//! For invocations with the `httprequest` intent, the Fn agent sends the method and URL of the
+//! client request in `Fn-Http-Method` and `Fn-Http-Request-Url` and its headers with the
+//! `Fn-Http-H-` prefix. A handler taking `HttpRequestInfo` gets them put back together with the
+//! body, undecoded, instead of reading them off the context one by one.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! Function::run(|_: &mut RuntimeContext, req: HttpRequestInfo| {
+//!     let params = req
+//!         .path_params("/t/shop/orders/{id}")
+//!         .ok_or_else(|| FunctionError::new_user_error("not found".into()))?;
+//!     let signature = req.header("X-Signature").unwrap_or_default();
+//!     verify(signature, req.body())?;
+//!     Ok(load_order(&params["id"])?)
+//! })
+//! .await
+//! ```
+
+use crate::coercions::InputCoercible;
+use crate::context::RuntimeContext;
+use crate::errors::FunctionError;
+use hyper::header::CONTENT_TYPE;
+use hyper::{HeaderMap, Method, Uri};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+
+/// HttpRequestInfo is a handler input holding the HTTP request of the client of an HTTP
+/// trigger: its method, URL, headers under their original names, and body as is with its
+/// trailers. Invocations without `Fn-Http-Method` and `Fn-Http-Request-Url` fail with an
+/// invalid input error.
+#[derive(Clone, Debug)]
+pub struct HttpRequestInfo {
+    method: Method,
+    url: Uri,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    trailers: HeaderMap,
+}
+
+impl HttpRequestInfo {
+    /// Returns the request of the invocation of `ctx` with `body`.
+    pub fn from_context(ctx: &RuntimeContext, body: &[u8]) -> Result<Self, FunctionError> {
+        let missing = |header: &str| FunctionError::InvalidInput {
+            inner: format!(
+                "HttpRequestInfo needs an HTTP trigger invocation: missing or invalid {}",
+                header
+            ),
+            source: None,
+        };
+        let method = ctx.method().ok_or_else(|| missing("Fn-Http-Method"))?;
+        let url = ctx
+            .request_url()
+            .ok_or_else(|| missing("Fn-Http-Request-Url"))?;
+        let mut headers = ctx.http_headers();
+        if let Some(content_type) = ctx.headers_ref().get(CONTENT_TYPE) {
+            headers
+                .entry(CONTENT_TYPE)
+                .or_insert_with(|| content_type.clone());
+        }
+        Ok(Self {
+            method: method.clone(),
+            url: url.clone(),
+            headers,
+            body: body.to_vec(),
+            trailers: ctx.request_trailers().clone(),
+        })
+    }
+
+    /// Returns the method of the request.
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// Returns the full URL of the request, as received by the trigger.
+    pub fn url(&self) -> &Uri {
+        &self.url
+    }
+
+    /// Returns the path of the request URL.
+    pub fn path(&self) -> &str {
+        self.url.path()
+    }
+
+    /// Matches the path of the request against `route` and returns the values of its `{name}`
+    /// parameters, or None if it does not match. A trailing `{name*}` parameter matches the
+    /// rest of the path.
+    pub fn path_params(&self, route: &str) -> Option<HashMap<String, String>> {
+        crate::api_gateway::match_route(self.path(), route)
+    }
+
+    /// Returns the query string of the request URL, undecoded.
+    pub fn query_string(&self) -> &str {
+        self.url.query().unwrap_or("")
+    }
+
+    /// Returns the query parameters of the request URL, decoded. The last value wins when a
+    /// parameter is repeated.
+    pub fn query_params(&self) -> HashMap<String, String> {
+        url::form_urlencoded::parse(self.query_string().as_bytes())
+            .into_owned()
+            .collect()
+    }
+
+    /// Deserializes the query parameters of the request URL into `T`.
+    pub fn query<T: DeserializeOwned>(&self) -> Result<T, FunctionError> {
+        serde_urlencoded::from_str(self.query_string()).map_err(|e| FunctionError::InvalidInput {
+            inner: format!("Invalid query string: {}", e),
+            source: Some(Box::new(e)),
+        })
+    }
+
+    /// Returns the headers of the client, under their original names.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Returns the value of the client header `name`, or None if it is missing or not visible
+    /// ASCII.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+
+    /// Returns the body of the request.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Returns the trailers sent after the body of the request.
+    pub fn trailers(&self) -> &HeaderMap {
+        &self.trailers
+    }
+
+    /// Returns the body of the request, consuming the request.
+    pub fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+}
+
+impl InputCoercible for HttpRequestInfo {
+    fn try_decode_request(
+        ctx: &RuntimeContext,
+        input: &[u8],
+    ) -> Option<Result<Self, FunctionError>> {
+        Some(Self::from_context(ctx, input))
+    }
+    fn try_decode_plain(_: &[u8]) -> Result<Self, FunctionError> {
+        Err(without_context())
+    }
+    fn try_decode_json(_: &[u8]) -> Result<Self, FunctionError> {
+        Err(without_context())
+    }
+    fn try_decode_xml(_: &[u8]) -> Result<Self, FunctionError> {
+        Err(without_context())
+    }
+    fn try_decode_yaml(_: &[u8]) -> Result<Self, FunctionError> {
+        Err(without_context())
+    }
+    fn try_decode_urlencoded(_: &[u8]) -> Result<Self, FunctionError> {
+        Err(without_context())
+    }
+}
+
+fn without_context() -> FunctionError {
+    FunctionError::Coercion {
+        inner: "HttpRequestInfo is read from the whole request, not from a body alone".into(),
+        source: None,
+    }
+}
@@ -0,0 +1,194 @@
+//! Local invocation from the command line.
+//!
+//! A binary running a function with `FunctionBuilder::run` invokes the handler once and exits
+//! when started with `--invoke`, without a listener or the Fn agent:
+//!
+//! ```text
+//! ./func --invoke payload.json --content-type application/json --header 'Fn-Http-H-X-User: me'
+//! ```
+//!
+//! The body of the response is printed to stdout, and its status and headers to stderr.
+//! `--invoke -` reads the payload from stdin.
+
+use crate::body::Body;
+use crate::completion;
+use crate::errors::FunctionError;
+use hyper::body::Bytes;
+use hyper::{Request, Response};
+use std::ffi::OsString;
+use std::io::{Read, Write};
+
+const INVOKE_ARG: &str = "--invoke";
+
+/// Options of the invoke mode, all taking a value.
+const INVOKE_OPTIONS: &[&str] = &[INVOKE_ARG, "--content-type", "--accept", "--header", "-H"];
+
+/// Returns the request given on the command line if the binary was started with `--invoke`.
+/// Exits with a usage message if the arguments are invalid. Arguments other than the invoke
+/// options, such as `--check-config`, are left to their own commands.
+pub(crate) fn request_from_args() -> Option<Result<Request<Body>, FunctionError>> {
+    let args: Vec<_> = std::env::args_os().collect();
+    if !args.iter().skip(1).any(|arg| {
+        arg.to_str()
+            .is_some_and(|arg| arg == INVOKE_ARG || arg.starts_with("--invoke="))
+    }) {
+        return None;
+    }
+
+    let matches = clap::App::new("fdk")
+        .about("Invokes the function once, without the Fn agent")
+        .arg(
+            clap::Arg::with_name("invoke")
+                .long("invoke")
+                .value_name("FILE")
+                .takes_value(true)
+                .required(true)
+                .help("The payload to invoke the function with, or - for stdin"),
+        )
+        .arg(
+            clap::Arg::with_name("content-type")
+                .long("content-type")
+                .value_name("TYPE")
+                .takes_value(true)
+                .default_value("application/json")
+                .help("The content type of the payload"),
+        )
+        .arg(
+            clap::Arg::with_name("accept")
+                .long("accept")
+                .value_name("TYPE")
+                .takes_value(true)
+                .help("The content type of the response"),
+        )
+        .arg(
+            clap::Arg::with_name("header")
+                .long("header")
+                .short("H")
+                .value_name("NAME: VALUE")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("A request header"),
+        )
+        .get_matches_from(invoke_args(args));
+
+    Some(build_request(&matches))
+}
+
+/// Keeps the program name and the invoke options with their values out of `args`.
+fn invoke_args(args: Vec<OsString>) -> Vec<OsString> {
+    let mut kept = Vec::new();
+    let mut args = args.into_iter();
+    kept.extend(args.next());
+    while let Some(arg) = args.next() {
+        let option = arg.to_str().and_then(|arg| {
+            INVOKE_OPTIONS.iter().find(|option| {
+                arg == **option
+                    || arg
+                        .strip_prefix(**option)
+                        .is_some_and(|rest| rest.starts_with('=') || **option == "-H")
+            })
+        });
+        match option {
+            Some(option) if arg == **option => {
+                kept.push(arg);
+                kept.extend(args.next());
+            }
+            Some(_) => kept.push(arg),
+            None => {}
+        }
+    }
+    kept
+}
+
+fn build_request(matches: &clap::ArgMatches) -> Result<Request<Body>, FunctionError> {
+    let invalid = |inner: String| FunctionError::InvalidInput {
+        inner,
+        source: None,
+    };
+    let path = matches.value_of("invoke").unwrap_or("-");
+    let mut payload = Vec::new();
+    let read = if path == "-" {
+        std::io::stdin().read_to_end(&mut payload).map(|_| ())
+    } else {
+        std::fs::read(path).map(|data| payload = data)
+    };
+    read.map_err(|e| FunctionError::IO {
+        inner: format!("Failed to read payload {}: {}", path, e),
+        source: Some(Box::new(e)),
+    })?;
+
+    let mut builder = Request::post("/")
+        .header("Fn-Call-Id", "local-invoke")
+        .header(
+            hyper::header::CONTENT_TYPE,
+            matches
+                .value_of("content-type")
+                .unwrap_or("application/json"),
+        );
+    if let Some(accept) = matches.value_of("accept") {
+        builder = builder.header(hyper::header::ACCEPT, accept);
+    }
+    for header in matches.values_of("header").into_iter().flatten() {
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| invalid(format!("Invalid header {}: expected NAME: VALUE", header)))?;
+        builder = builder.header(name.trim(), value.trim());
+    }
+    builder
+        .body(Body::from(payload))
+        .map_err(|e| invalid(format!("Invalid request: {}", e)))
+}
+
+/// Prints the response of a local invocation, the body to stdout and the rest to stderr.
+pub(crate) fn print_response(response: &Response<Bytes>) -> Result<(), FunctionError> {
+    let status = completion::reported_status(response.status(), response.headers());
+    eprintln!("{}", status);
+    for (name, value) in response.headers() {
+        eprintln!("{}: {}", name, String::from_utf8_lossy(value.as_bytes()));
+    }
+    let mut stdout = std::io::stdout().lock();
+    stdout
+        .write_all(response.body())
+        .and_then(|()| stdout.flush())
+        .map_err(|e| FunctionError::IO {
+            inner: format!("Failed to print the response: {}", e),
+            source: Some(Box::new(e)),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn other_fdk_flags_are_left_out() {
+        let kept = invoke_args(args(&[
+            "func",
+            "--check-config",
+            "--invoke",
+            "-",
+            "selftest",
+            "--content-type=text/plain",
+            "-H",
+            "X-User: me",
+            "-HX-Team: fn",
+        ]));
+        assert_eq!(
+            kept,
+            args(&[
+                "func",
+                "--invoke",
+                "-",
+                "--content-type=text/plain",
+                "-H",
+                "X-User: me",
+                "-HX-Team: fn",
+            ])
+        );
+    }
+}
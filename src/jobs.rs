@@ -0,0 +1,164 @@
+//! Asynchronous jobs acknowledged with `202 Accepted`.
+//!
+//! `accept` answers an invocation right away with the ID of a job, and runs the job once the
+//! response is written. The state of recent jobs is kept in the container, to be queried by a
+//! follow-up invocation with `status_response`.
+//!
+//! Jobs run in the function container, which Fn may pause or recycle when idle: keep them
+//! short, and persist results that must survive the container elsewhere.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! fn handler(ctx: &mut RuntimeContext, req: Request) -> Result<FnResponse> {
+//!     match req {
+//!         Request::Start(report) => jobs::accept(ctx, move || build_report(report)),
+//!         Request::Status { job_id } => jobs::status_response(&job_id),
+//!     }
+//! }
+//! ```
+
+use crate::context::RuntimeContext;
+use crate::errors::FunctionError;
+use crate::function::Result;
+use crate::response::FnResponse;
+use hyper::StatusCode;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Number of jobs whose state is kept. The oldest are forgotten first.
+const MAX_JOBS: usize = 1024;
+
+lazy_static! {
+    static ref JOBS: Mutex<Jobs> = Mutex::new(Jobs::default());
+}
+
+static NEXT_JOB: AtomicU64 = AtomicU64::new(0);
+
+/// JobStatus is the state of an accepted job.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JobStatus {
+    /// The job waits for the response acknowledging it to be written.
+    Pending,
+    /// The job is running.
+    Running,
+    /// The job completed with `result`.
+    Succeeded { result: serde_json::Value },
+    /// The job failed with `error`.
+    Failed { error: String },
+}
+
+impl JobStatus {
+    /// Returns the JSON state of job `job_id`, such as `{"job_id":"...","status":"running"}`.
+    fn to_json(&self, job_id: &str) -> serde_json::Value {
+        match self {
+            Self::Pending => serde_json::json!({ "job_id": job_id, "status": "pending" }),
+            Self::Running => serde_json::json!({ "job_id": job_id, "status": "running" }),
+            Self::Succeeded { result } => {
+                serde_json::json!({ "job_id": job_id, "status": "succeeded", "result": result })
+            }
+            Self::Failed { error } => {
+                serde_json::json!({ "job_id": job_id, "status": "failed", "error": error })
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Jobs {
+    states: HashMap<String, JobStatus>,
+    order: VecDeque<String>,
+}
+
+impl Jobs {
+    fn insert(&mut self, job_id: String) {
+        while self.order.len() >= MAX_JOBS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.states.remove(&oldest);
+            }
+        }
+        self.order.push_back(job_id.clone());
+        self.states.insert(job_id, JobStatus::Pending);
+    }
+}
+
+fn set_status(job_id: &str, status: JobStatus) {
+    if let Ok(mut jobs) = JOBS.lock() {
+        if let Some(state) = jobs.states.get_mut(job_id) {
+            *state = status;
+        }
+    }
+}
+
+/// Returns the state of the job `job_id`, if it is known to this container.
+pub fn status(job_id: &str) -> Option<JobStatus> {
+    JOBS.lock().ok()?.states.get(job_id).cloned()
+}
+
+/// Returns `200 OK` with the JSON state of the job `job_id`, such as
+/// `{"job_id":"...","status":"succeeded","result":...}`, or `404 Not Found`.
+pub fn status_response(job_id: &str) -> Result<FnResponse> {
+    let (status, body) = match status(job_id) {
+        Some(job) => (StatusCode::OK, job.to_json(job_id)),
+        None => (
+            StatusCode::NOT_FOUND,
+            serde_json::json!({ "job_id": job_id, "error": "unknown job" }),
+        ),
+    };
+    Ok(FnResponse::new(status, body.to_string()))
+}
+
+/// Accepts `work` as a job run once the response is written, and returns a `202 Accepted`
+/// response with its ID in the `{"job_id":"..."}` body and the `X-Job-Id` header. The job
+/// ID is the call ID of the invocation.
+pub fn accept<S, W>(ctx: &mut RuntimeContext, work: W) -> Result<FnResponse>
+where
+    S: Serialize,
+    W: FnOnce() -> Result<S> + Send + 'static,
+{
+    let call_id = ctx.call_id();
+    let job_id = if call_id.is_empty() {
+        format!(
+            "job-{}-{}",
+            std::process::id(),
+            NEXT_JOB.fetch_add(1, Ordering::Relaxed)
+        )
+    } else {
+        call_id
+    };
+    if let Ok(mut jobs) = JOBS.lock() {
+        jobs.insert(job_id.clone());
+    }
+
+    let id = job_id.clone();
+    ctx.on_complete(move |_| {
+        let run = move || {
+            set_status(&id, JobStatus::Running);
+            let status = match work().and_then(|result| {
+                serde_json::to_value(result).map_err(|e| FunctionError::Coercion {
+                    inner: format!("Failed to serialize the job result: {}", e),
+                    source: Some(Box::new(e)),
+                })
+            }) {
+                Ok(result) => JobStatus::Succeeded { result },
+                Err(e) => JobStatus::Failed {
+                    error: e.to_string(),
+                },
+            };
+            set_status(&id, status);
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(run)),
+            Err(_) => drop(std::thread::spawn(run)),
+        }
+    });
+
+    FnResponse::new(
+        StatusCode::ACCEPTED,
+        serde_json::json!({ "job_id": job_id }).to_string(),
+    )
+    .with_header("X-Job-Id", &job_id)
+}
@@ -0,0 +1,118 @@
+use crate::logging::{self, Level};
+use crate::migration::MigrationNotice;
+use hyper::HeaderMap;
+use std::collections::HashMap;
+
+/// Env vars of the legacy `default` contract, which passed per-call metadata through the
+/// environment, paired with where the information lives in the http-stream contract.
+const LEGACY_VARS: [(&str, &str); 5] = [
+    ("FN_METHOD", "the Fn-Http-Method header"),
+    ("FN_REQUEST_URL", "the Fn-Http-Request-Url header"),
+    ("FN_CALL_ID", "RuntimeContext::call_id"),
+    ("FN_DEADLINE", "RuntimeContext::deadline"),
+    ("FN_PATH", "the path of the Fn-Http-Request-Url header"),
+];
+
+const LEGACY_HEADER_PREFIX: &str = "FN_HEADER_";
+
+/// Returns an actionable notice for each legacy contract variable found in `config`.
+pub(crate) fn env_notices(config: &HashMap<String, String>) -> Vec<MigrationNotice> {
+    let mut notices: Vec<MigrationNotice> = LEGACY_VARS
+        .iter()
+        .filter(|(var, _)| config.contains_key(*var))
+        .map(|(var, replacement)| MigrationNotice {
+            name: (*var).to_owned(),
+            replacement: (*replacement).to_owned(),
+            message: format!(
+                "{} is set but belongs to the legacy default contract and is not updated per call; read {} instead",
+                var, replacement
+            ),
+        })
+        .collect();
+
+    let mut legacy_headers: Vec<&str> = config
+        .keys()
+        .filter(|k| k.starts_with(LEGACY_HEADER_PREFIX))
+        .map(String::as_str)
+        .collect();
+    if !legacy_headers.is_empty() {
+        legacy_headers.sort_unstable();
+        notices.push(MigrationNotice {
+            name: legacy_headers.join(", "),
+            replacement: "RuntimeContext::header".to_owned(),
+            message: format!(
+                "{} belong to the legacy default contract; read request headers with RuntimeContext::header instead",
+                legacy_headers.join(", ")
+            ),
+        });
+    }
+
+    if let Some(format) = config.get("FN_FORMAT") {
+        if format != "http-stream" {
+            notices.push(MigrationNotice {
+                name: "FN_FORMAT".to_owned(),
+                replacement: "format: http-stream".to_owned(),
+                message: format!(
+                    "FN_FORMAT {} is no longer supported; set `format: http-stream` in func.yaml",
+                    format
+                ),
+            });
+        }
+    }
+    notices
+}
+
+/// Prints the legacy contract warnings for the process environment.
+pub(crate) fn warn_legacy_env(config: &HashMap<String, String>) {
+    for notice in env_notices(config) {
+        logging::log(Level::Warn, notice);
+    }
+}
+
+/// Adds the legacy contract variables describing the current call to `config`, so functions
+/// written against the default contract can keep reading them from `RuntimeContext::config`.
+pub(crate) fn shim_config(config: &mut HashMap<String, String>, headers: &HeaderMap) {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned)
+    };
+
+    let url = header("Fn-Http-Request-Url");
+    let values = [
+        ("FN_METHOD", header("Fn-Http-Method")),
+        ("FN_CALL_ID", header("Fn-Call-Id")),
+        ("FN_DEADLINE", header("Fn-Deadline")),
+        (
+            "FN_PATH",
+            url.as_deref()
+                .and_then(|u| u.parse::<hyper::Uri>().ok())
+                .map(|u| u.path().to_owned()),
+        ),
+        ("FN_REQUEST_URL", url),
+    ];
+    for (key, value) in values {
+        if let Some(value) = value {
+            config.insert(key.to_owned(), value);
+        }
+    }
+
+    for (name, value) in headers {
+        let name = name.as_str();
+        let name = match name.get(..10) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("fn-http-h-") => &name[10..],
+            _ => continue,
+        };
+        if let Ok(value) = value.to_str() {
+            config.insert(
+                format!(
+                    "{}{}",
+                    LEGACY_HEADER_PREFIX,
+                    name.to_ascii_uppercase().replace('-', "_")
+                ),
+                value.to_owned(),
+            );
+        }
+    }
+}
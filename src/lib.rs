@@ -27,7 +27,7 @@
 //! This is a simple function which greets the name provided as input.
 //!
 //! ```rust,ignore
-//! use fdk::{Function, FunctionError, RuntimeContext, Result};
+//! use fdk::prelude::*;
 //! use tokio; // Tokio for handling future.
 //!
 //! #[tokio::main]
@@ -49,31 +49,165 @@
 //!     Ok(())
 //! }
 //! ```
+//!
+//! Started with `--invoke payload.json`, the binary invokes the function once and prints the
+//! response instead of serving the Fn agent. `--content-type`, `--accept` and `--header` set
+//! the request, and `--invoke -` reads the payload from stdin.
+//!
+//! # Features
+//!
+//! - `no-net-extras`: guarantees the FDK performs no network activity besides serving the Fn
+//!   listener, with no exporters and no clients. The build fails if another enabled feature
+//!   needs the network.
+//! - `prometheus`: serves the built-in metrics on `GET /metrics` of a secondary listener set
+//!   with `FN_METRICS_LISTENER` or `FunctionBuilder::metrics_listener`.
+//! - `statsd`: pushes the invocation and error counts and the invocation latencies over UDP
+//!   to the StatsD or DogStatsD server set with `FN_STATSD_ADDRESS` or
+//!   `FunctionBuilder::statsd`.
+//! - `log`: installs a `log` backend writing records to stderr, prefixed with the call ID of
+//!   the invocation that emitted them, unless the function installs its own logger first.
+//! - `opentelemetry`: starts a span per invocation, child of the B3 or W3C trace context of
+//!   the request, and exports it over OTLP/HTTP when `OTEL_EXPORTER_OTLP_ENDPOINT` or
+//!   `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` is set. The invocation and error counts and the
+//!   invocation latencies are exported likewise when `OTEL_EXPORTER_OTLP_ENDPOINT` or
+//!   `OTEL_EXPORTER_OTLP_METRICS_ENDPOINT` is set.
+//! - `tracing`: runs every invocation in a `tracing` span carrying the call ID, app and
+//!   function names and content type, with child spans for decoding, the user function and
+//!   encoding.
+//! - `zstd`: decompresses zstd request bodies and, with `FunctionBuilder::compression`,
+//!   compresses responses, optionally with a pre-trained dictionary.
+//! - `gzip`: decompresses gzip and deflate request bodies and, with
+//!   `FunctionBuilder::compression`, compresses responses with gzip or deflate.
+//! - `brotli`: decompresses brotli request bodies and, with `FunctionBuilder::compression`,
+//!   compresses responses with brotli.
+//! - `oci-events`: adds typed payloads of OCI Events, such as `ObjectStorageEvent`, to take
+//!   as handler input or read with `RuntimeContext::as_oci_event`.
+//! - `resource-principal`: adds `RuntimeContext::oci_resource_principal`, loading the
+//!   resource principal OCI Functions injects into the container to sign requests to OCI APIs.
+//! - `secrets`: adds `FunctionBuilder::resolve_secrets`, replacing the config values naming
+//!   an OCI Vault secret, `vault:<secret OCID>`, with the secret fetched at startup with the
+//!   resource principal of the function.
+//! - `debug-errors`: logs where failed invocations failed, the line of the handler that
+//!   created or converted its error with `?`, or else the stage of the FDK that rejected the
+//!   request.
+//! - `soak`: adds the `soak` module to soak test a handler across many warm invocations.
+//! - `testing`: adds the `testing` module to invoke a handler in-process from tests, through
+//!   the same coercions and error handling as the Fn listener.
 
 #![allow(clippy::upper_case_acronyms)]
+#[cfg(all(feature = "no-net-extras", feature = "net-extras"))]
+compile_error!("the `no-net-extras` feature forbids features performing network activity");
+extern crate chrono;
 extern crate clap;
 extern crate futures;
 extern crate hyper;
 extern crate lazy_static;
-extern crate object_pool;
+extern crate libc;
 extern crate serde_json;
 extern crate serde_plain;
 extern crate serde_urlencoded;
-extern crate serde_xml_rs;
 extern crate serde_yaml;
 extern crate thiserror;
 extern crate tokio;
+extern crate tokio_util;
 extern crate url;
 
+mod api_gateway;
+mod body;
+mod budget;
+pub mod bytes_base64;
 mod coercions;
+mod completion;
+#[cfg(feature = "compression")]
+mod compression;
 mod context;
+mod cookie;
+mod cors;
+mod crash_dump;
+mod dev;
 mod errors;
 mod function;
+mod http_request;
+mod invoke;
+pub mod jobs;
+mod legacy;
+mod limits;
+#[cfg(feature = "log")]
+mod log_bridge;
 mod logging;
+pub mod metrics;
+mod migration;
+mod oci;
+#[cfg(feature = "oci-events")]
+mod oci_events;
+#[cfg(feature = "opentelemetry")]
+mod otel;
+pub mod prelude;
+mod probe;
+#[cfg(feature = "prometheus")]
+mod prometheus;
+mod record;
+#[cfg(feature = "resource-principal")]
+mod resource_principal;
+mod response;
+pub mod routing;
+mod runtime;
+#[cfg(feature = "secrets")]
+pub mod secrets;
+mod selftest;
+mod server;
+mod shutdown;
+#[cfg(feature = "soak")]
+pub mod soak;
 mod socket;
+#[cfg(feature = "tracing")]
+mod spans;
+#[cfg(feature = "statsd")]
+mod statsd;
+mod temp_dir;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod trace;
 mod utils;
+#[cfg(target_os = "linux")]
+mod vsock;
+mod warm_state;
 
-pub use coercions::{InputCoercible, OutputCoercible};
-pub use context::RuntimeContext;
-pub use errors::FunctionError;
-pub use function::{Function, Result};
+pub use api_gateway::ApiGatewayRequest;
+pub use budget::{AttemptTimeouts, TimeoutBudget, DEFAULT_BUDGET_SHARE};
+pub use bytes_base64::Base64Bytes;
+pub use coercions::{ContentType, InputCoercible, JsonStrictness, OutputCoercible, RawBody};
+pub use completion::{Outcome, Phases};
+#[cfg(feature = "compression")]
+pub use compression::CompressionConfig;
+pub use context::{RuntimeContext, RuntimeContextBuilder};
+pub use cookie::{Cookie, SameSite};
+pub use cors::CorsConfig;
+pub use crash_dump::CrashDumpConfig;
+pub use errors::{ErrorCode, ErrorKind, ErrorSource, FunctionError};
+pub use function::{Function, FunctionBuilder, Result};
+pub use http_request::HttpRequestInfo;
+pub use hyper::http::Extensions;
+pub use limits::SaturationPolicy;
+pub use logging::{LogFormat, LogOutput, LogWriter};
+pub use migration::{check_config, run_check_config_command, MigrationNotice};
+pub use oci::OciHeaders;
+#[cfg(feature = "oci-events")]
+pub use oci_events::{
+    AutonomousDatabaseDetails, AutonomousDatabaseEvent, EventExtensions, ObjectStorageDetails,
+    ObjectStorageEvent, OciEvent, ResourceEventData,
+};
+pub use probe::{ProbeConfig, ProbeKind};
+#[cfg(feature = "resource-principal")]
+pub use resource_principal::ResourcePrincipal;
+pub use response::{FnResponse, StreamBody, StreamSender};
+pub use routing::{ContentTypeRouter, Router};
+pub use runtime::{RuntimeConfig, RuntimeFlavor};
+pub use selftest::{run_selftest_command, selftest, selftest_with, FormatSupport, SelfTestReport};
+pub use server::{HttpProtocol, ServerConfig};
+pub use shutdown::{ExitCodePolicy, ExitReason, ShutdownPhase, ShutdownTimeouts};
+pub use socket::{accept_stats, AcceptStats};
+pub use tokio_util::sync::CancellationToken;
+pub use trace::TraceContext;
+pub use utils::ResponseEnvelope;
+pub use warm_state::WarmState;
@@ -0,0 +1,221 @@
+use crate::errors::{ErrorSource, FunctionError};
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes};
+use hyper::HeaderMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// How long the Fn agent is told to wait before retrying a rejected invocation by default.
+pub(crate) const DEFAULT_BUSY_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// SaturationPolicy decides what happens to an invocation arriving while the concurrency
+/// limit is reached.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SaturationPolicy {
+    /// Wait until a running invocation completes, however many are waiting.
+    #[default]
+    Queue,
+    /// Wait until a running invocation completes while fewer than this many invocations are
+    /// waiting, and answer with a 503 busy error otherwise.
+    QueueUpTo(usize),
+    /// Answer immediately with a 503 busy error.
+    Reject,
+}
+
+/// Returns the limit set in `FN_MAX_CONCURRENCY`, if any.
+pub(crate) fn max_concurrency_from_env() -> Result<Option<usize>, FunctionError> {
+    match crate::context::CONFIG_FROM_ENV.get("FN_MAX_CONCURRENCY") {
+        Some(v) => match v.parse::<usize>() {
+            Ok(max) if max > 0 => Ok(Some(max)),
+            parsed => Err(FunctionError::Initialization {
+                inner: format!("Invalid FN_MAX_CONCURRENCY specified: {}", v),
+                source: parsed.err().map(ErrorSource::from),
+            }),
+        },
+        None => Ok(None),
+    }
+}
+
+/// ConcurrencyLimit caps the number of invocations processed at the same time.
+pub(crate) struct ConcurrencyLimit {
+    semaphore: Arc<Semaphore>,
+    policy: SaturationPolicy,
+    waiting: AtomicUsize,
+}
+
+impl ConcurrencyLimit {
+    pub(crate) fn new(max: usize, policy: SaturationPolicy) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max)),
+            policy,
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns true if an invocation could start right away.
+    pub(crate) fn has_capacity(&self) -> bool {
+        self.semaphore.available_permits() > 0
+    }
+
+    /// Waits for a slot, or returns None right away if the policy rejects excess invocations.
+    /// The slot is released when the permit is dropped.
+    pub(crate) async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        match self.policy {
+            SaturationPolicy::Queue => self.semaphore.clone().acquire_owned().await.ok(),
+            SaturationPolicy::QueueUpTo(max_waiting) => {
+                if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+                    return Some(permit);
+                }
+                let _waiting = Waiting::enter(&self.waiting, max_waiting)?;
+                self.semaphore.clone().acquire_owned().await.ok()
+            }
+            SaturationPolicy::Reject => self.semaphore.clone().try_acquire_owned().ok(),
+        }
+    }
+}
+
+/// Waiting counts an invocation waiting for a slot until dropped, including when the
+/// connection is closed while it waits.
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    /// Counts an invocation as waiting, or returns None if `max` already are.
+    fn enter(waiting: &'a AtomicUsize, max: usize) -> Option<Self> {
+        waiting
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| Waiting(waiting))
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Returns the body size limit set in `FN_MAX_BODY_SIZE`, if any.
+pub(crate) fn max_body_size_from_env() -> Result<Option<usize>, FunctionError> {
+    match crate::context::CONFIG_FROM_ENV.get("FN_MAX_BODY_SIZE") {
+        Some(v) => v
+            .parse::<usize>()
+            .map(Some)
+            .map_err(|e| FunctionError::Initialization {
+                inner: format!("Invalid FN_MAX_BODY_SIZE specified: {}", v),
+                source: Some(ErrorSource::from(e)),
+            }),
+        None => Ok(None),
+    }
+}
+
+/// BodyTooLarge is the source of the error failing invocations whose request body exceeds the
+/// limit, telling it apart from other coercion errors.
+#[derive(Debug)]
+pub(crate) struct BodyTooLarge(usize);
+
+impl std::fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "request body exceeds {} bytes", self.0)
+    }
+}
+
+impl std::error::Error for BodyTooLarge {}
+
+pub(crate) fn body_too_large(limit: usize) -> FunctionError {
+    FunctionError::Coercion {
+        inner: format!("Request body exceeds the limit of {} bytes", limit),
+        source: Some(Box::new(BodyTooLarge(limit))),
+    }
+}
+
+/// Returns true if `e` was returned because the request body exceeds the limit.
+pub(crate) fn is_body_too_large(e: &FunctionError) -> bool {
+    std::error::Error::source(e).is_some_and(|source| source.is::<BodyTooLarge>())
+}
+
+/// Reads the whole request body and its trailers. With a limit, fails with a coercion error as
+/// soon as the announced or received size exceeds it, without buffering the rest.
+pub(crate) async fn read_body<B>(
+    mut body: B,
+    limit: Option<usize>,
+) -> Result<(Bytes, HeaderMap), FunctionError>
+where
+    B: Body<Data = Bytes> + Unpin,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    let read_error = |e: B::Error| FunctionError::IO {
+        inner: format!("Failed to read request body: {}", e),
+        source: Some(ErrorSource::from(e)),
+    };
+    let limit = match limit {
+        Some(limit) => limit,
+        None => {
+            return body
+                .collect()
+                .await
+                .map(|collected| {
+                    let trailers = collected.trailers().cloned().unwrap_or_default();
+                    (collected.to_bytes(), trailers)
+                })
+                .map_err(read_error)
+        }
+    };
+
+    if body.size_hint().lower() > limit as u64 {
+        return Err(body_too_large(limit));
+    }
+    let mut buffer = Vec::new();
+    let mut trailers = HeaderMap::new();
+    while let Some(frame) = body.frame().await {
+        let chunk = match frame.map_err(read_error)?.into_data() {
+            Ok(chunk) => chunk,
+            Err(frame) => {
+                if let Ok(frame_trailers) = frame.into_trailers() {
+                    trailers.extend(frame_trailers);
+                }
+                continue;
+            }
+        };
+        if buffer.len() + chunk.len() > limit {
+            return Err(body_too_large(limit));
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+    Ok((Bytes::from(buffer), trailers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::StreamBody;
+    use hyper::body::Frame;
+
+    #[tokio::test]
+    async fn read_errors_keep_their_source() {
+        let frames = vec![
+            Ok(Frame::data(Bytes::from_static(b"partial"))),
+            Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionReset,
+                "reset",
+            )),
+        ];
+        let body = StreamBody::new(futures::stream::iter(frames));
+        let err = read_body(body, Some(1024)).await.unwrap_err();
+        let source = std::error::Error::source(&err).expect("the read error is kept");
+        let io = source.downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(io.kind(), std::io::ErrorKind::ConnectionReset);
+    }
+
+    #[test]
+    fn body_too_large_is_recognized() {
+        assert!(is_body_too_large(&body_too_large(4)));
+        assert!(!is_body_too_large(&FunctionError::Coercion {
+            inner: "Request body exceeds the limit of 4 bytes".into(),
+            source: None,
+        }));
+    }
+}
@@ -0,0 +1,42 @@
+use crate::logging::{self, Level};
+use std::io::Write;
+
+/// CallIdLogger is a `log` backend writing records to stderr, prefixed with the call ID of the
+/// invocation that emitted them.
+struct CallIdLogger;
+
+static LOGGER: CallIdLogger = CallIdLogger;
+
+fn level_filter(level: Level) -> log::LevelFilter {
+    match level {
+        Level::Error => log::LevelFilter::Error,
+        Level::Warn => log::LevelFilter::Warn,
+        Level::Info => log::LevelFilter::Info,
+        Level::Debug => log::LevelFilter::Debug,
+    }
+}
+
+impl log::Log for CallIdLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        logging::emit(record.target(), record.level().as_str(), record.args());
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// Installs the call ID logger at the level set in `FN_LOG_LEVEL`, unless the function already
+/// installed a logger of its own.
+pub(crate) fn install() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level_filter(logging::max_level()));
+    }
+}
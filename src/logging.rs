@@ -1,24 +1,353 @@
 use crate::context;
 use hyper::HeaderMap;
+use lazy_static::lazy_static;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::future::Future;
+use std::io::Write;
+use std::panic::{self, PanicHookInfo};
+use std::sync::{Once, OnceLock};
+
+/// Level is the severity of an FDK log line. Lines above the level set in `FN_LOG_LEVEL`
+/// (`error`, `warn`, `info` or `debug`, default `info`) are discarded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Some(Self::Error),
+            "warn" => Some(Self::Warn),
+            "info" => Some(Self::Info),
+            "debug" => Some(Self::Debug),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+        }
+    }
+}
+
+/// LogFormat is the format of the lines written by the FDK. It defaults to the value of
+/// `FN_LOG_FORMAT`, `text` or `json`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `fdk info: message` lines.
+    #[default]
+    Text,
+    /// JSON lines such as `{"ts":"...","call_id":"...","level":"info","msg":"message"}`.
+    Json,
+}
+
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+
+/// Sets the log format, unless lines were already written with the format of the environment.
+pub(crate) fn set_format(format: LogFormat) {
+    let _ = FORMAT.set(format);
+}
+
+fn format() -> LogFormat {
+    *FORMAT.get_or_init(|| {
+        match context::CONFIG_FROM_ENV
+            .get("FN_LOG_FORMAT")
+            .map(|v| v.to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("json") => LogFormat::Json,
+            _ => LogFormat::Text,
+        }
+    })
+}
+
+/// Writes a line to stderr in the configured format. `source` is `fdk` for lines of the FDK
+/// itself, or the target of `log` records.
+pub(crate) fn emit(source: &str, level: &str, msg: &dyn std::fmt::Display) {
+    match format() {
+        LogFormat::Text if source == "fdk" => eprintln!("fdk {}: {}", level, msg),
+        LogFormat::Text => match current_call_id().filter(|id| !id.is_empty()) {
+            Some(call_id) => eprintln!("[{}] {} {}: {}", call_id, level, source, msg),
+            None => eprintln!("{} {}: {}", level, source, msg),
+        },
+        LogFormat::Json => {
+            let line = serde_json::json!({
+                "ts": chrono::DateTime::<chrono::Utc>::from(std::time::SystemTime::now())
+                    .to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                "call_id": current_call_id().filter(|id| !id.is_empty()),
+                "level": level.to_ascii_lowercase(),
+                "source": source,
+                "msg": msg.to_string(),
+            });
+            eprintln!("{}", line);
+        }
+    }
+}
+
+lazy_static! {
+    static ref LEVEL: Level = context::CONFIG_FROM_ENV
+        .get("FN_LOG_LEVEL")
+        .and_then(|v| Level::parse(v))
+        .unwrap_or(Level::Info);
+}
+
+/// Returns the most verbose level logged.
+#[cfg_attr(not(feature = "log"), allow(dead_code))]
+pub(crate) fn max_level() -> Level {
+    *LEVEL
+}
+
+/// Returns true if lines of `level` are logged.
+pub fn enabled(level: Level) -> bool {
+    level <= *LEVEL
+}
+
+/// Writes `msg` to stderr if `level` is enabled.
+pub fn log<M: std::fmt::Display>(level: Level, msg: M) {
+    if enabled(level) {
+        emit("fdk", level.as_str(), &msg);
+    }
+}
+
+/// Returns the `name=value` line framing the output of the invocation with `headers`, if
+/// `FN_LOGFRAME_NAME` and `FN_LOGFRAME_HDR` are set and the request carries the header.
+pub(crate) fn frame_line(headers: &HeaderMap) -> Option<String> {
+    let config = &context::CONFIG_FROM_ENV;
+    let framer = config.get("FN_LOGFRAME_NAME")?;
+    let value_src = config.get("FN_LOGFRAME_HDR")?;
+    let value = headers.get(value_src.as_str())?;
+    if value.is_empty() {
+        return None;
+    }
+    Some(format!("{}={}", framer, value.to_str().ok()?))
+}
 
 /// start_logging enables logging for a user request.
 pub fn start_logging(headers: &HeaderMap) {
-    let config = context::CONFIG_FROM_ENV.clone();
+    if let Some(frame) = frame_line(headers) {
+        println!("\n{}", frame);
+        eprintln!("\n{}", frame);
+    }
+}
+
+/// LogOutput is the standard stream a `LogWriter` writes to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogOutput {
+    Stdout,
+    Stderr,
+}
+
+/// Buffered output past which a `LogWriter` writes its complete lines without waiting for a
+/// flush.
+const LOG_WRITER_CAPACITY: usize = 8 * 1024;
+
+/// LogWriter buffers the output of an invocation and writes its complete lines to stdout or
+/// stderr in one piece on flush, so multi-line output of concurrent invocations doesn't
+/// interleave. Each piece is preceded by the `FN_LOGFRAME_NAME` frame line if log framing is
+/// configured, and its lines are prefixed with the call ID otherwise. A trailing partial line
+/// is written when the writer is dropped.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut log = ctx.log_writer();
+/// writeln!(log, "resizing {}", name)?;
+/// writeln!(log, "  from {}x{} to {}x{}", w, h, new_w, new_h)?;
+/// log.flush()?;
+/// ```
+pub struct LogWriter {
+    output: LogOutput,
+    frame: Option<String>,
+    call_id: String,
+    buffer: Vec<u8>,
+}
+
+impl LogWriter {
+    pub(crate) fn new(output: LogOutput, frame: Option<String>, call_id: String) -> Self {
+        Self {
+            output,
+            frame,
+            call_id,
+            buffer: Vec::new(),
+        }
+    }
 
-    let framer = match config.get("FN_LOGFRAME_NAME") {
-        Some(v) => v,
-        None => return,
-    };
+    /// Writes the first `len` buffered bytes as one framed piece.
+    fn write_buffered(&mut self, len: usize) -> std::io::Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        let lines: Vec<u8> = self.buffer.drain(..len).collect();
+        let mut piece = Vec::with_capacity(lines.len() + 64);
+        match &self.frame {
+            Some(frame) => {
+                piece.extend_from_slice(format!("\n{}\n", frame).as_bytes());
+                piece.extend_from_slice(&lines);
+            }
+            None => {
+                for line in lines.split_inclusive(|b| *b == b'\n') {
+                    if !self.call_id.is_empty() {
+                        piece.extend_from_slice(format!("[{}] ", self.call_id).as_bytes());
+                    }
+                    piece.extend_from_slice(line);
+                }
+            }
+        }
+        if piece.last() != Some(&b'\n') {
+            piece.push(b'\n');
+        }
+        match self.output {
+            LogOutput::Stdout => {
+                let mut out = std::io::stdout().lock();
+                out.write_all(&piece)?;
+                out.flush()
+            }
+            LogOutput::Stderr => {
+                let mut out = std::io::stderr().lock();
+                out.write_all(&piece)?;
+                out.flush()
+            }
+        }
+    }
 
-    let value_src = match config.get("FN_LOGFRAME_HDR") {
-        Some(v) => v,
-        None => return,
-    };
+    /// Returns the length of the buffered complete lines.
+    fn complete_lines(&self) -> usize {
+        self.buffer
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(0, |i| i + 1)
+    }
+}
 
-    if let Some(v) = headers.get(value_src) {
-        if !v.is_empty() {
-            println!("\n{}={}", framer, v.to_str().unwrap());
-            eprintln!("\n{}={}", framer, v.to_str().unwrap());
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() > LOG_WRITER_CAPACITY {
+            self.write_buffered(self.complete_lines())?;
         }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.write_buffered(self.complete_lines())
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        let _ = self.write_buffered(self.buffer.len());
+    }
+}
+
+thread_local! {
+    static CURRENT_CALL_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+tokio::task_local! {
+    static TASK_CALL_ID: String;
+}
+
+/// Runs `future` with `call_id` as the call ID of the current task, so FDK code handling the
+/// invocation outside of the user function can be attributed to it too.
+pub(crate) async fn with_call_id<F: Future>(call_id: String, future: F) -> F::Output {
+    TASK_CALL_ID.scope(call_id, future).await
+}
+
+/// CallIdGuard records the call ID of the invocation running on the current thread and
+/// clears it when dropped. An empty call ID is not recorded, so it doesn't shadow the one of
+/// the current task.
+pub struct CallIdGuard(bool);
+
+impl CallIdGuard {
+    pub fn enter(call_id: &str) -> Self {
+        if call_id.is_empty() {
+            return CallIdGuard(false);
+        }
+        CURRENT_CALL_ID.with(|id| *id.borrow_mut() = Some(call_id.to_owned()));
+        CallIdGuard(true)
+    }
+}
+
+impl Drop for CallIdGuard {
+    fn drop(&mut self) {
+        if self.0 {
+            CURRENT_CALL_ID.with(|id| *id.borrow_mut() = None);
+        }
+    }
+}
+
+/// Returns the call ID of the invocation running on the current thread or task, if any.
+pub fn current_call_id() -> Option<String> {
+    CURRENT_CALL_ID
+        .with(|id| id.borrow().clone())
+        .or_else(|| TASK_CALL_ID.try_with(String::clone).ok())
+}
+
+fn panic_payload(info: &PanicHookInfo<'_>) -> String {
+    if let Some(s) = info.payload().downcast_ref::<&str>() {
+        (*s).to_owned()
+    } else if let Some(s) = info.payload().downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "Box<dyn Any>".to_owned()
+    }
+}
+
+/// install_panic_hook logs the call ID, payload and backtrace of panics before handing them to
+/// the previously installed hook. It is installed once per process.
+pub fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let location = info
+                .location()
+                .map(|l| format!("{}:{}", l.file(), l.line()))
+                .unwrap_or_default();
+            let payload = panic_payload(info);
+            match format() {
+                LogFormat::Text => eprintln!(
+                    "Panic in call {} at {}: {}\n{}",
+                    current_call_id().unwrap_or_else(|| "<none>".to_owned()),
+                    location,
+                    payload,
+                    Backtrace::force_capture()
+                ),
+                LogFormat::Json => emit(
+                    "fdk",
+                    Level::Error.as_str(),
+                    &format_args!(
+                        "panic at {}: {}\n{}",
+                        location,
+                        payload,
+                        Backtrace::force_capture()
+                    ),
+                ),
+            }
+            crate::crash_dump::dump_current(&format!("panic at {}: {}", location, payload));
+            previous(info);
+        }));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_call_ids_are_not_recorded() {
+        let _outer = CallIdGuard::enter("01CALL");
+        drop(CallIdGuard::enter(""));
+        assert_eq!(current_call_id().as_deref(), Some("01CALL"));
     }
 }
@@ -0,0 +1,297 @@
+//! Built-in metrics recorded by the FDK for every invocation.
+//!
+//! The FDK counts invocations, errors by `ErrorKind`, and records latencies and payload sizes
+//! into fixed bucket histograms. Plug a sink in by implementing `MetricsExporter` and
+//! registering it with `FunctionBuilder::metrics_exporter`.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! struct StderrExporter;
+//!
+//! impl MetricsExporter for StderrExporter {
+//!     fn export(&self, snapshot: &MetricsSnapshot) {
+//!         eprintln!("{} invocations, {} errors", snapshot.invocations, snapshot.error_total());
+//!     }
+//! }
+//!
+//! Function::builder()
+//!     .metrics_exporter(StderrExporter, Duration::from_secs(60))
+//!     .run(handler)
+//!     .await
+//! ```
+
+use crate::completion::Phases;
+use crate::errors::ErrorKind;
+use lazy_static::lazy_static;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Every error kind, in the order used by `MetricsSnapshot::errors`.
+const ERROR_KINDS: [ErrorKind; 10] = [
+    ErrorKind::InvalidInput,
+    ErrorKind::BadRequest,
+    ErrorKind::Initialization,
+    ErrorKind::Coercion,
+    ErrorKind::IO,
+    ErrorKind::Server,
+    ErrorKind::System,
+    ErrorKind::User,
+    ErrorKind::Timeout,
+    ErrorKind::Busy,
+];
+
+/// Upper bounds of the latency buckets, in microseconds.
+pub const LATENCY_BUCKETS_MICROS: [u64; 11] = [
+    1_000, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000, 2_500_000,
+    10_000_000,
+];
+
+/// Upper bounds of the payload size buckets, in bytes.
+pub const SIZE_BUCKETS_BYTES: [u64; 9] = [
+    256, 1_024, 4_096, 16_384, 65_536, 262_144, 1_048_576, 4_194_304, 16_777_216,
+];
+
+lazy_static! {
+    static ref METRICS: Metrics = Metrics::new();
+}
+
+/// Returns the metrics of the process.
+pub fn global() -> &'static Metrics {
+    &METRICS
+}
+
+/// Histogram counts observations into buckets with fixed upper bounds, plus an overflow bucket.
+pub struct Histogram {
+    bounds: &'static [u64],
+    counts: Vec<AtomicU64>,
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [u64]) -> Self {
+        Self {
+            bounds,
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    /// Records one observation.
+    pub fn observe(&self, value: u64) {
+        let bucket = self.bounds.partition_point(|bound| *bound < value);
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// Returns the current bucket counts.
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let counts: Vec<u64> = self
+            .counts
+            .iter()
+            .map(|c| c.load(Ordering::Relaxed))
+            .collect();
+        HistogramSnapshot {
+            buckets: self
+                .bounds
+                .iter()
+                .copied()
+                .zip(counts.iter().copied())
+                .collect(),
+            count: counts.iter().sum(),
+            sum: self.sum.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// HistogramSnapshot is a point in time copy of a `Histogram`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    /// Upper bound and number of observations of each bucket, not cumulative. Observations
+    /// above the last bound only appear in `count`.
+    pub buckets: Vec<(u64, u64)>,
+    /// Total number of observations.
+    pub count: u64,
+    /// Sum of all observed values.
+    pub sum: u64,
+}
+
+impl HistogramSnapshot {
+    /// Returns the buckets with cumulative counts, as expected by Prometheus style sinks.
+    pub fn cumulative(&self) -> Vec<(u64, u64)> {
+        let mut total = 0;
+        self.buckets
+            .iter()
+            .map(|(bound, count)| {
+                total += count;
+                (*bound, total)
+            })
+            .collect()
+    }
+}
+
+/// Metrics holds the counters and histograms recorded by the FDK.
+pub struct Metrics {
+    invocations: AtomicU64,
+    errors: [AtomicU64; ERROR_KINDS.len()],
+    latency: Histogram,
+    body_read: Histogram,
+    handler: Histogram,
+    response_write: Histogram,
+    request_bytes: Histogram,
+    response_bytes: Histogram,
+    startup_millis: AtomicU64,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Self {
+            invocations: AtomicU64::new(0),
+            errors: Default::default(),
+            latency: Histogram::new(&LATENCY_BUCKETS_MICROS),
+            body_read: Histogram::new(&LATENCY_BUCKETS_MICROS),
+            handler: Histogram::new(&LATENCY_BUCKETS_MICROS),
+            response_write: Histogram::new(&LATENCY_BUCKETS_MICROS),
+            request_bytes: Histogram::new(&SIZE_BUCKETS_BYTES),
+            response_bytes: Histogram::new(&SIZE_BUCKETS_BYTES),
+            startup_millis: AtomicU64::new(0),
+        }
+    }
+
+    /// Records a completed invocation and its latency.
+    pub(crate) fn record_invocation(&self, latency: Duration) {
+        self.invocations.fetch_add(1, Ordering::Relaxed);
+        self.latency
+            .observe(u64::try_from(latency.as_micros()).unwrap_or(u64::MAX));
+        #[cfg(feature = "opentelemetry")]
+        crate::otel::record_invocation(latency);
+        #[cfg(feature = "statsd")]
+        crate::statsd::record_invocation(latency);
+    }
+
+    /// Records the phases of a completed invocation.
+    pub(crate) fn record_phases(&self, phases: &Phases) {
+        for (histogram, elapsed) in [
+            (&self.body_read, phases.body_read),
+            (&self.handler, phases.handler),
+            (&self.response_write, phases.response_write),
+        ] {
+            histogram.observe(u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX));
+        }
+    }
+
+    /// Records an invocation that failed with an error of `kind`.
+    pub(crate) fn record_error(&self, kind: ErrorKind) {
+        if let Some(i) = ERROR_KINDS.iter().position(|k| *k == kind) {
+            self.errors[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn record_request_size(&self, bytes: usize) {
+        self.request_bytes.observe(bytes as u64);
+    }
+
+    pub(crate) fn record_response_size(&self, bytes: usize) {
+        self.response_bytes.observe(bytes as u64);
+    }
+
+    /// Records how long the function took to start serving.
+    pub(crate) fn record_startup(&self, startup: Duration) {
+        self.startup_millis.store(
+            u64::try_from(startup.as_millis()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Returns a copy of the current values.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            invocations: self.invocations.load(Ordering::Relaxed),
+            errors: ERROR_KINDS
+                .iter()
+                .zip(self.errors.iter())
+                .map(|(kind, count)| (*kind, count.load(Ordering::Relaxed)))
+                .collect(),
+            latency_micros: self.latency.snapshot(),
+            body_read_micros: self.body_read.snapshot(),
+            handler_micros: self.handler.snapshot(),
+            response_write_micros: self.response_write.snapshot(),
+            request_bytes: self.request_bytes.snapshot(),
+            response_bytes: self.response_bytes.snapshot(),
+            accept_failures: crate::socket::accept_stats().failures(),
+            startup_millis: self.startup_millis.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// MetricsSnapshot is a point in time copy of the FDK metrics, handed to exporters.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Number of invocations handled, health probes excluded.
+    pub invocations: u64,
+    /// Number of failed invocations for each error kind.
+    pub errors: Vec<(ErrorKind, u64)>,
+    /// Time from receiving the request to producing the response, in microseconds.
+    pub latency_micros: HistogramSnapshot,
+    /// Time to receive the request bodies from the Fn agent, in microseconds.
+    pub body_read_micros: HistogramSnapshot,
+    /// Time spent in the handler, in microseconds.
+    pub handler_micros: HistogramSnapshot,
+    /// Time to write the responses to the Fn agent, in microseconds.
+    pub response_write_micros: HistogramSnapshot,
+    /// Size of the request bodies, in bytes.
+    pub request_bytes: HistogramSnapshot,
+    /// Size of the encoded response bodies, in bytes.
+    pub response_bytes: HistogramSnapshot,
+    /// Number of failed accepts on the listener socket.
+    pub accept_failures: u64,
+    /// Time from the start of `run` to the listener accepting invocations, in milliseconds.
+    pub startup_millis: u64,
+}
+
+impl MetricsSnapshot {
+    /// Returns the number of failed invocations of any kind.
+    pub fn error_total(&self) -> u64 {
+        self.errors.iter().map(|(_, count)| count).sum()
+    }
+}
+
+/// MetricsExporter is a sink for the FDK metrics. It is called periodically with a fresh
+/// snapshot, and a last time during the flush phase of the shutdown.
+pub trait MetricsExporter: Send + Sync {
+    /// Exports a snapshot. It runs on the blocking thread pool, so it may block.
+    fn export(&self, snapshot: &MetricsSnapshot);
+
+    /// Flushes buffered data. Called after the last export before the process exits.
+    fn flush(&self) {}
+}
+
+/// Periodically hands snapshots to `exporter` until the returned task is aborted.
+pub(crate) fn spawn_exporter(
+    exporter: std::sync::Arc<dyn MetricsExporter>,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            let exporter = exporter.clone();
+            let _ =
+                tokio::task::spawn_blocking(move || exporter.export(&global().snapshot())).await;
+        }
+    })
+}
+
+/// Exports a final snapshot to every exporter and flushes them.
+pub(crate) fn flush_exporters(exporters: &[std::sync::Arc<dyn MetricsExporter>]) {
+    if exporters.is_empty() {
+        return;
+    }
+    let snapshot = global().snapshot();
+    for exporter in exporters {
+        exporter.export(&snapshot);
+        exporter.flush();
+    }
+}
@@ -0,0 +1,122 @@
+//! Compatibility with renamed configuration.
+//!
+//! When an env var of the FDK is renamed, it is added to `RENAMED_VARS`: the old name keeps
+//! working, copied to the new one when the new one is not set, and a deprecation notice
+//! naming the replacement is logged at startup. Renamed builder methods are kept as
+//! `#[deprecated]` aliases of the new ones for a release.
+//!
+//! `run_check_config_command` reports everything in the environment that needs migrating.
+
+use crate::logging::{self, Level};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Rename is an env var renamed in release `since`.
+struct Rename {
+    old: &'static str,
+    new: &'static str,
+    since: &'static str,
+}
+
+/// Env vars renamed since their introduction, oldest first.
+const RENAMED_VARS: [Rename; 0] = [];
+
+/// Copies the value of every renamed variable set in `config` under its new name, unless the
+/// new name is set too.
+pub(crate) fn apply_renames(config: &mut HashMap<String, String>) {
+    for rename in &RENAMED_VARS {
+        if let Some(value) = config.get(rename.old).cloned() {
+            config.entry(rename.new.to_owned()).or_insert(value);
+        }
+    }
+}
+
+/// MigrationNotice is a setting of the environment that needs migrating.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MigrationNotice {
+    /// The deprecated env var.
+    pub name: String,
+    /// What replaces it.
+    pub replacement: String,
+    /// What to change, naming both.
+    pub message: String,
+}
+
+impl fmt::Display for MigrationNotice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Returns a notice for each renamed variable set in `config`, then the legacy contract
+/// warnings.
+pub(crate) fn notices(config: &HashMap<String, String>) -> Vec<MigrationNotice> {
+    let mut notices: Vec<MigrationNotice> = RENAMED_VARS
+        .iter()
+        .filter(|rename| config.contains_key(rename.old))
+        .map(|rename| MigrationNotice {
+            name: rename.old.to_owned(),
+            replacement: rename.new.to_owned(),
+            message: if config.contains_key(rename.new) {
+                format!(
+                    "{} was renamed to {} in {} and is ignored since {} is set; unset it",
+                    rename.old, rename.new, rename.since, rename.new
+                )
+            } else {
+                format!(
+                    "{} was renamed to {} in {}; set {} instead",
+                    rename.old, rename.new, rename.since, rename.new
+                )
+            },
+        })
+        .collect();
+    notices.extend(crate::legacy::env_notices(config));
+    notices
+}
+
+/// Logs a deprecation notice for each renamed variable set in the process environment.
+pub(crate) fn warn_renamed_env(config: &HashMap<String, String>) {
+    for rename in RENAMED_VARS
+        .iter()
+        .filter(|rename| config.contains_key(rename.old))
+    {
+        logging::log(
+            Level::Warn,
+            format_args!(
+                "deprecated config {}: renamed to {} in {}; set {} instead",
+                rename.old, rename.new, rename.since, rename.new
+            ),
+        );
+    }
+}
+
+/// Returns the migration notices for the process environment.
+pub fn check_config() -> Vec<MigrationNotice> {
+    notices(&std::env::vars().filter(|(_, v)| !v.is_empty()).collect())
+}
+
+/// Prints the migration notices and exits when the binary was started with `--check-config`,
+/// and returns otherwise. Call it at the beginning of `main`:
+///
+/// ```rust,ignore
+/// fdk::run_check_config_command();
+/// ```
+///
+/// The process exits with status 1 if anything needs migrating.
+pub fn run_check_config_command() {
+    if !std::env::args_os()
+        .skip(1)
+        .any(|arg| arg == "--check-config")
+    {
+        return;
+    }
+    let notices = check_config();
+    if notices.is_empty() {
+        println!("config: nothing to migrate");
+        std::process::exit(0);
+    }
+    for notice in &notices {
+        println!("{}", notice);
+    }
+    std::process::exit(1);
+}
@@ -0,0 +1,43 @@
+use hyper::{HeaderMap, Uri};
+
+/// Looks a header up under its own name, then under the `Fn-Http-H-` prefixed name used for
+/// headers forwarded by HTTP triggers.
+pub(crate) fn forwarded_header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .or_else(|| headers.get(format!("Fn-Http-H-{}", name).as_str()))
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+}
+
+/// OciHeaders holds the OCI specific headers of an invocation on OCI Functions.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OciHeaders {
+    /// The `opc-request-id` header, the OCI wide ID of the request to quote in support cases.
+    pub request_id: Option<String>,
+    /// The `oci-original-url` header, the URL the caller invoked before routing.
+    pub original_url: Option<Uri>,
+    /// The `oci-compartment-id` header, the OCID of the compartment of the function.
+    pub compartment_id: Option<String>,
+    /// The `oci-subject-id` header, the OCID of the principal invoking the function.
+    pub subject_id: Option<String>,
+    /// The `oci-subject-tenancy-id` header, the OCID of the tenancy of the invoking principal.
+    pub subject_tenancy_id: Option<String>,
+    /// The `oci-subject-type` header, e.g. `user` or `resource`.
+    pub subject_type: Option<String>,
+}
+
+impl OciHeaders {
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Self {
+        let get = |name: &str| forwarded_header(headers, name).map(str::to_owned);
+        Self {
+            request_id: get("opc-request-id"),
+            original_url: forwarded_header(headers, "oci-original-url")
+                .and_then(|v| v.parse::<Uri>().ok()),
+            compartment_id: get("oci-compartment-id"),
+            subject_id: get("oci-subject-id"),
+            subject_tenancy_id: get("oci-subject-tenancy-id"),
+            subject_type: get("oci-subject-type"),
+        }
+    }
+}
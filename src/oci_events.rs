@@ -0,0 +1,132 @@
+//! Typed payloads of the OCI Events service.
+//!
+//! Functions triggered by an OCI Events rule receive the event as a JSON envelope in the
+//! CloudEvents 0.1 format. `OciEvent` is that envelope, generic over its `data`, which for
+//! resource events is a `ResourceEventData` generic over the service specific details.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! Function::run(|ctx: &mut RuntimeContext, event: ObjectStorageEvent| {
+//!     let details = &event.data.additional_details;
+//!     Ok(format!("{} uploaded to {}", event.data.resource_name, details.bucket_name))
+//! })
+//! ```
+//!
+//! or, in a handler taking another input,
+//! `ctx.as_oci_event::<ResourceEventData<ObjectStorageDetails>>()`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// OciEvent is the envelope of an event emitted by OCI Events.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OciEvent<T = serde_json::Value> {
+    /// The type of the event, e.g. `com.oraclecloud.objectstorage.createobject`.
+    pub event_type: String,
+    /// The version of the CloudEvents specification of the envelope, `0.1`.
+    pub cloud_events_version: String,
+    /// The version of the schema of `event_type`.
+    pub event_type_version: String,
+    /// The service emitting the event, e.g. `ObjectStorage`.
+    pub source: String,
+    /// The ID of the event.
+    #[serde(rename = "eventID")]
+    pub event_id: String,
+    /// When the event occurred, in RFC 3339 format.
+    #[serde(default)]
+    pub event_time: Option<String>,
+    /// The content type of `data`, usually `application/json`.
+    #[serde(default)]
+    pub content_type: Option<String>,
+    /// The OCI extensions of the event.
+    #[serde(default)]
+    pub extensions: Option<EventExtensions>,
+    /// The payload of the event.
+    pub data: T,
+}
+
+/// EventExtensions holds the OCI extension attributes of an event.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventExtensions {
+    /// The OCID of the compartment of the resource emitting the event.
+    #[serde(default)]
+    pub compartment_id: Option<String>,
+}
+
+/// ResourceEventData is the payload of the events emitted about an OCI resource.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceEventData<D = serde_json::Value> {
+    /// The OCID of the compartment of the resource.
+    #[serde(default)]
+    pub compartment_id: Option<String>,
+    /// The name of the compartment of the resource.
+    #[serde(default)]
+    pub compartment_name: Option<String>,
+    /// The name of the resource, e.g. the name of an object.
+    #[serde(default)]
+    pub resource_name: String,
+    /// The ID of the resource, an OCID or, for objects, their path.
+    #[serde(default)]
+    pub resource_id: String,
+    /// The availability domain of the resource, `all` for regional resources.
+    #[serde(default)]
+    pub availability_domain: Option<String>,
+    /// The free-form tags of the resource.
+    #[serde(default)]
+    pub freeform_tags: HashMap<String, String>,
+    /// The defined tags of the resource, by namespace.
+    #[serde(default)]
+    pub defined_tags: HashMap<String, HashMap<String, serde_json::Value>>,
+    /// The details specific to the service emitting the event.
+    pub additional_details: D,
+}
+
+/// ObjectStorageDetails are the additional details of Object Storage object events.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ObjectStorageDetails {
+    /// The Object Storage namespace of the bucket.
+    pub namespace: String,
+    /// The name of the bucket of the object.
+    pub bucket_name: String,
+    /// The OCID of the bucket of the object.
+    #[serde(default)]
+    pub bucket_id: Option<String>,
+    /// The entity tag of the object.
+    #[serde(default)]
+    pub e_tag: Option<String>,
+    /// The archival state of the object, e.g. `Available` or `Archived`.
+    #[serde(default)]
+    pub archival_state: Option<String>,
+}
+
+/// AutonomousDatabaseDetails are the additional details of Autonomous Database events.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AutonomousDatabaseDetails {
+    /// The name of the database.
+    #[serde(default)]
+    pub db_name: Option<String>,
+    /// The workload of the database, e.g. `OLTP` or `DW`.
+    #[serde(default)]
+    pub db_workload: Option<String>,
+    /// The lifecycle state of the database, e.g. `AVAILABLE`.
+    #[serde(default)]
+    pub lifecycle_state: Option<String>,
+    /// Whether the database is an Always Free one.
+    #[serde(default)]
+    pub is_free_tier: Option<bool>,
+    /// The other details, which depend on the event type.
+    #[serde(flatten)]
+    pub other: HashMap<String, serde_json::Value>,
+}
+
+/// ObjectStorageEvent is an event about an Object Storage object, e.g. its creation.
+pub type ObjectStorageEvent = OciEvent<ResourceEventData<ObjectStorageDetails>>;
+
+/// AutonomousDatabaseEvent is an event about an Autonomous Database, e.g. a backup.
+pub type AutonomousDatabaseEvent = OciEvent<ResourceEventData<AutonomousDatabaseDetails>>;
@@ -0,0 +1,291 @@
+use crate::body::Body;
+use crate::errors::FunctionError;
+use crate::logging::{self, Level};
+use crate::metrics::LATENCY_BUCKETS_MICROS;
+use crate::oci::forwarded_header;
+use crate::trace::TraceContext;
+use hyper::{HeaderMap, Response};
+use opentelemetry::context::{FutureExt, WithContext};
+use opentelemetry::metrics::{Histogram, MeterProvider};
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::{
+    SpanContext, SpanId, SpanKind, Status, TraceContextExt, TraceFlags, TraceId, TraceState,
+    Tracer, TracerProvider,
+};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+static TRACING: OnceLock<Tracing> = OnceLock::new();
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// The only OTLP protocol the exporters speak.
+const PROTOCOL: &str = "http/protobuf";
+
+/// Tracing starts a span per invocation and exports them over OTLP/HTTP.
+pub(crate) struct Tracing {
+    provider: SdkTracerProvider,
+    tracer: SdkTracer,
+}
+
+/// Metrics exports the FDK metrics over OTLP/HTTP.
+struct Metrics {
+    provider: SdkMeterProvider,
+    invoke_duration: Histogram<f64>,
+}
+
+/// Sets up the OTLP exporters of traces and metrics whose endpoint is configured in the
+/// environment. The exporters honour the standard `OTEL_EXPORTER_OTLP_*` variables, and
+/// `OTEL_SDK_DISABLED`, `OTEL_TRACES_EXPORTER` and `OTEL_METRICS_EXPORTER` turn them off.
+pub(crate) fn init() -> Result<(), FunctionError> {
+    let config = &crate::context::CONFIG_FROM_ENV;
+    if config
+        .get("OTEL_SDK_DISABLED")
+        .is_some_and(|v| v.eq_ignore_ascii_case("true"))
+    {
+        return Ok(());
+    }
+    let service_name = config
+        .get("OTEL_SERVICE_NAME")
+        .or_else(|| config.get("FN_FN_NAME"))
+        .cloned()
+        .unwrap_or_else(|| "fn".to_owned());
+    let resource = Resource::builder().with_service_name(service_name).build();
+    if exports(config, "TRACES")? {
+        init_traces(resource.clone())?;
+    }
+    if exports(config, "METRICS")? {
+        init_metrics(resource)?;
+    }
+    Ok(())
+}
+
+/// Returns whether `signal`, `TRACES` or `METRICS`, is exported: an endpoint is set for it
+/// and its exporter is not `none`. Fails if its protocol is not HTTP/protobuf.
+fn exports(config: &HashMap<String, String>, signal: &str) -> Result<bool, FunctionError> {
+    let endpoint_var = format!("OTEL_EXPORTER_OTLP_{}_ENDPOINT", signal);
+    if !config.contains_key(&endpoint_var) && !config.contains_key("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        return Ok(false);
+    }
+    let exporter_var = format!("OTEL_{}_EXPORTER", signal);
+    if config.get(&exporter_var).is_some_and(|v| v == "none") {
+        return Ok(false);
+    }
+    let protocol_var = format!("OTEL_EXPORTER_OTLP_{}_PROTOCOL", signal);
+    for var in [protocol_var.as_str(), "OTEL_EXPORTER_OTLP_PROTOCOL"] {
+        match config.get(var) {
+            Some(v) if v != PROTOCOL => {
+                return Err(FunctionError::Initialization {
+                    inner: format!(
+                        "Invalid {} specified: {}, only {} is supported",
+                        var, v, PROTOCOL
+                    ),
+                    source: None,
+                })
+            }
+            Some(_) => break,
+            None => {}
+        }
+    }
+    Ok(true)
+}
+
+fn init_traces(resource: Resource) -> Result<(), FunctionError> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()
+        .map_err(|e| FunctionError::Initialization {
+            inner: format!("Failed to set up the OTLP exporter: {}", e),
+            source: Some(Box::new(e)),
+        })?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    opentelemetry::global::set_tracer_provider(provider.clone());
+    let tracer = provider.tracer("fdk");
+    let _ = TRACING.set(Tracing { provider, tracer });
+    Ok(())
+}
+
+/// Exports the invocation and error counts, and the invocation latencies, every
+/// `OTEL_METRIC_EXPORT_INTERVAL` milliseconds, one minute by default. The meter provider is
+/// made global, so the metrics of the user function are exported along.
+fn init_metrics(resource: Resource) -> Result<(), FunctionError> {
+    let exporter = opentelemetry_otlp::MetricExporter::builder()
+        .with_http()
+        .build()
+        .map_err(|e| FunctionError::Initialization {
+            inner: format!("Failed to set up the OTLP metrics exporter: {}", e),
+            source: Some(Box::new(e)),
+        })?;
+    let provider = SdkMeterProvider::builder()
+        .with_reader(PeriodicReader::builder(exporter).build())
+        .with_resource(resource)
+        .build();
+    opentelemetry::global::set_meter_provider(provider.clone());
+
+    let meter = provider.meter("fdk");
+    meter
+        .u64_observable_counter("faas.invocations")
+        .with_description("Invocations handled, health probes excluded.")
+        .with_callback(|observer| {
+            observer.observe(crate::metrics::global().snapshot().invocations, &[])
+        })
+        .build();
+    meter
+        .u64_observable_counter("faas.errors")
+        .with_description("Failed invocations by error kind.")
+        .with_callback(|observer| {
+            for (kind, count) in crate::metrics::global().snapshot().errors {
+                observer.observe(count, &[KeyValue::new("error.type", format!("{:?}", kind))]);
+            }
+        })
+        .build();
+    let invoke_duration = meter
+        .f64_histogram("faas.invoke_duration")
+        .with_description("Time from receiving the request to producing the response.")
+        .with_unit("s")
+        .with_boundaries(
+            LATENCY_BUCKETS_MICROS
+                .iter()
+                .map(|micros| *micros as f64 / 1e6)
+                .collect(),
+        )
+        .build();
+    let _ = METRICS.set(Metrics {
+        provider,
+        invoke_duration,
+    });
+    Ok(())
+}
+
+/// Records the latency of a completed invocation, if metrics are exported.
+pub(crate) fn record_invocation(latency: Duration) {
+    if let Some(metrics) = METRICS.get() {
+        metrics.invoke_duration.record(latency.as_secs_f64(), &[]);
+    }
+}
+
+/// Returns the tracing set up by `init`, if any.
+pub(crate) fn tracing() -> Option<&'static Tracing> {
+    TRACING.get()
+}
+
+/// Exports the pending spans and metrics and stops the exporters.
+pub(crate) fn shutdown() {
+    if let Some(tracing) = TRACING.get() {
+        if let Err(e) = tracing.provider.shutdown() {
+            logging::log(
+                Level::Warn,
+                format_args!("failed to flush the OTLP exporter: {}", e),
+            );
+        }
+    }
+    if let Some(metrics) = METRICS.get() {
+        if let Err(e) = metrics.provider.shutdown() {
+            logging::log(
+                Level::Warn,
+                format_args!("failed to flush the OTLP metrics exporter: {}", e),
+            );
+        }
+    }
+}
+
+impl Tracing {
+    /// Starts the span of an invocation, as a child of the trace the headers carry, and
+    /// returns the context holding it.
+    pub(crate) fn start(&self, headers: &HeaderMap) -> Context {
+        let parent = parent_context(headers);
+        let mut attributes = Vec::new();
+        if let Some(call_id) = forwarded_header(headers, "Fn-Call-Id") {
+            attributes.push(KeyValue::new("faas.invocation_id", call_id.to_owned()));
+        }
+        let config = &crate::context::CONFIG_FROM_ENV;
+        for (key, var) in [
+            ("faas.name", "FN_FN_NAME"),
+            ("fn.app_id", "FN_APP_ID"),
+            ("fn.fn_id", "FN_FN_ID"),
+        ] {
+            if let Some(value) = config.get(var) {
+                attributes.push(KeyValue::new(key, value.clone()));
+            }
+        }
+        let span = self
+            .tracer
+            .span_builder("invoke")
+            .with_kind(SpanKind::Server)
+            .with_attributes(attributes)
+            .start_with_context(&self.tracer, &parent);
+        parent.with_span(span)
+    }
+}
+
+/// Makes `cx`, if any, the current context while `future` is polled, so spans started by the
+/// user function are children of the invocation span.
+pub(crate) fn instrument<F: Future>(future: F, cx: Option<&Context>) -> WithContext<F> {
+    future.with_context(cx.cloned().unwrap_or_else(Context::current))
+}
+
+/// Ends the span of `cx`, recording the status reported in `response`.
+pub(crate) fn end(cx: &Context, response: &Response<Body>) {
+    let span = cx.span();
+    let status = response
+        .headers()
+        .get("Fn-Http-Status")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or_else(|| i64::from(response.status().as_u16()));
+    span.set_attribute(KeyValue::new("http.response.status_code", status));
+    if status >= 500 || !response.status().is_success() {
+        span.set_status(Status::error(format!("status {}", status)));
+    }
+    span.end();
+}
+
+/// Reads the parent span from the B3 headers forwarded by the Fn agent, or from a W3C
+/// `traceparent` header.
+fn parent_context(headers: &HeaderMap) -> Context {
+    if let Some(span_context) = b3_span_context(headers) {
+        return Context::new().with_remote_span_context(span_context);
+    }
+    TraceContextPropagator::new().extract(&HeaderExtractor(headers))
+}
+
+fn b3_span_context(headers: &HeaderMap) -> Option<SpanContext> {
+    let b3 = TraceContext::from_headers(headers, &crate::context::CONFIG_FROM_ENV);
+    // 64 bit trace IDs are left padded to 128 bits.
+    let trace_id = TraceId::from_hex(&format!("{:0>32}", b3.trace_id?)).ok()?;
+    let span_id = SpanId::from_hex(&b3.span_id?).ok()?;
+    // Without an explicit decision the invocation is sampled.
+    let flags = if b3.sampled.unwrap_or(true) {
+        TraceFlags::SAMPLED
+    } else {
+        TraceFlags::default()
+    };
+    Some(SpanContext::new(
+        trace_id,
+        span_id,
+        flags,
+        true,
+        TraceState::default(),
+    ))
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        forwarded_header(self.0, key)
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
@@ -0,0 +1,10 @@
+//! The items most functions need, to import at once with `use fdk::prelude::*;`.
+//!
+//! Note that `Result` is the FDK alias, `Result<T>` with a `FunctionError`.
+
+pub use crate::routes;
+pub use crate::routing::{ContentTypeRouter, Params, RouteTable, Router};
+pub use crate::{
+    ContentType, FnResponse, Function, FunctionError, HttpRequestInfo, InputCoercible,
+    OutputCoercible, RawBody, Result, RuntimeContext, StreamSender,
+};
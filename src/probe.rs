@@ -0,0 +1,71 @@
+use crate::body::Body;
+use hyper::{Request, Response, StatusCode};
+
+/// ProbeKind is the kind of health check a probe request asks for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProbeKind {
+    /// Is the process alive?
+    Live,
+    /// Can the function take invocations right now?
+    Ready,
+}
+
+/// ProbeConfig designates which requests are health probes. Probes are answered right away
+/// without decoding the body or calling the user function.
+#[derive(Clone, Debug)]
+pub struct ProbeConfig {
+    /// Header whose value, `live` or `ready`, marks a probe.
+    pub header: String,
+    /// Path prefix marking a probe; `<path>/live` and `<path>/ready` select the kind and the
+    /// bare path is a readiness probe.
+    pub path: Option<String>,
+    /// Treat requests without an `Fn-Call-Id` header, which the Fn agent always sends, as
+    /// readiness probes.
+    pub missing_call_id: bool,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            header: "Fn-Probe".to_owned(),
+            path: Some("/health".to_owned()),
+            missing_call_id: false,
+        }
+    }
+}
+
+impl ProbeConfig {
+    /// Returns the kind of probe `req` is, or None for regular invocations.
+    pub(crate) fn classify<B>(&self, req: &Request<B>) -> Option<ProbeKind> {
+        if let Some(value) = req.headers().get(self.header.as_str()) {
+            return match value.as_bytes() {
+                b"live" => Some(ProbeKind::Live),
+                _ => Some(ProbeKind::Ready),
+            };
+        }
+        if let Some(path) = &self.path {
+            if let Some(rest) = req.uri().path().strip_prefix(path.as_str()) {
+                match rest {
+                    "/live" => return Some(ProbeKind::Live),
+                    "" | "/" | "/ready" => return Some(ProbeKind::Ready),
+                    _ => {}
+                }
+            }
+        }
+        if self.missing_call_id && !req.headers().contains_key("Fn-Call-Id") {
+            return Some(ProbeKind::Ready);
+        }
+        None
+    }
+}
+
+/// Answers a probe with a plain 200, or 503 when a readiness probe finds the function
+/// unable to take invocations.
+pub(crate) fn probe_response(kind: ProbeKind, ready: bool) -> Response<Body> {
+    let (status, body) = match kind {
+        ProbeKind::Live => (StatusCode::OK, "live"),
+        ProbeKind::Ready if ready => (StatusCode::OK, "ready"),
+        ProbeKind::Ready => (StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+    };
+    crate::utils::plain_response(status, Body::from(body))
+}
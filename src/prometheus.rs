@@ -0,0 +1,252 @@
+use crate::body::Body;
+use crate::errors::FunctionError;
+use crate::logging::{self, Level};
+use crate::metrics::{self, HistogramSnapshot, MetricsSnapshot};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::fmt::Write;
+use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use url::Url;
+
+/// Path serving the metrics on the metrics listener.
+const METRICS_PATH: &str = "/metrics";
+
+/// Returns the address set in `FN_METRICS_LISTENER`, if any.
+pub(crate) fn listener_from_env() -> Option<String> {
+    crate::context::CONFIG_FROM_ENV
+        .get("FN_METRICS_LISTENER")
+        .cloned()
+}
+
+/// MetricsListener serves the Prometheus exposition of the FDK metrics, on a TCP port or on a
+/// unix socket besides the Fn listener.
+pub(crate) enum MetricsListener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+impl MetricsListener {
+    /// Binds `address`, either `tcp://<host>:<port>` or `unix:///<path>`.
+    pub(crate) async fn bind(address: &str) -> Result<Self, FunctionError> {
+        let malformed = || FunctionError::Initialization {
+            inner: format!("Malformed FN_METRICS_LISTENER specified: {}", address),
+            source: None,
+        };
+        let url = Url::parse(address).map_err(|_| malformed())?;
+        match url.scheme() {
+            "tcp" => {
+                let host = url.host_str().ok_or_else(malformed)?;
+                let port = url.port().ok_or_else(malformed)?;
+                Ok(Self::Tcp(TcpListener::bind((host, port)).await?))
+            }
+            "unix" if !url.path().is_empty() => {
+                let path = PathBuf::from(url.path());
+                let _ = std::fs::remove_file(&path);
+                Ok(Self::Unix(UnixListener::bind(&path)?, path))
+            }
+            _ => Err(malformed()),
+        }
+    }
+
+    /// Returns the socket file to remove on shutdown, if any.
+    pub(crate) fn socket_file(&self) -> Option<PathBuf> {
+        match self {
+            Self::Tcp(_) => None,
+            Self::Unix(_, path) => Some(path.clone()),
+        }
+    }
+
+    /// Accepts scrapes until the task running it is aborted.
+    pub(crate) async fn serve(self) {
+        loop {
+            let accepted = match &self {
+                Self::Tcp(listener) => listener
+                    .accept()
+                    .await
+                    .map(|(stream, _)| tokio::spawn(serve_connection(stream))),
+                Self::Unix(listener, _) => listener
+                    .accept()
+                    .await
+                    .map(|(stream, _)| tokio::spawn(serve_connection(stream))),
+            };
+            if let Err(e) = accepted {
+                logging::log(
+                    Level::Warn,
+                    format_args!("metrics listener failed to accept: {}", e),
+                );
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+async fn serve_connection<S>(stream: S)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = hyper::service::service_fn(|req: Request<Incoming>| async move {
+        Ok::<_, hyper::Error>(scrape(&req))
+    });
+    let _ = http1::Builder::new()
+        .serve_connection(TokioIo::new(stream), service)
+        .await;
+}
+
+fn scrape<B>(req: &Request<B>) -> Response<Body> {
+    if req.method() != Method::GET || req.uri().path() != METRICS_PATH {
+        return crate::utils::plain_response(StatusCode::NOT_FOUND, Body::from("not found"));
+    }
+    let mut response = Response::new(Body::from(render(&metrics::global().snapshot())));
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("text/plain; version=0.0.4"),
+    );
+    response
+}
+
+/// Returns the labels identifying the function, from `FN_APP_ID` and `FN_FN_ID`.
+fn function_labels() -> String {
+    let config = &crate::context::CONFIG_FROM_ENV;
+    [("app_id", "FN_APP_ID"), ("fn_id", "FN_FN_ID")]
+        .iter()
+        .filter_map(|(label, var)| {
+            config
+                .get(*var)
+                .map(|v| format!("{}=\"{}\"", label, escape(v)))
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Renders `snapshot` in the Prometheus text exposition format.
+pub(crate) fn render(snapshot: &MetricsSnapshot) -> String {
+    let labels = function_labels();
+    let with = |extra: &str| match (labels.is_empty(), extra.is_empty()) {
+        (true, true) => String::new(),
+        (true, false) => format!("{{{}}}", extra),
+        (false, true) => format!("{{{}}}", labels),
+        (false, false) => format!("{{{},{}}}", labels, extra),
+    };
+    let mut out = String::new();
+
+    let _ = writeln!(
+        out,
+        "# HELP fdk_invocations_total Invocations handled by the function.\n# TYPE fdk_invocations_total counter\nfdk_invocations_total{} {}",
+        with(""),
+        snapshot.invocations
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP fdk_errors_total Failed invocations by error kind.\n# TYPE fdk_errors_total counter"
+    );
+    for (kind, count) in &snapshot.errors {
+        let _ = writeln!(
+            out,
+            "fdk_errors_total{} {}",
+            with(&format!("kind=\"{:?}\"", kind)),
+            count
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP fdk_accept_failures_total Failed accepts on the Fn listener.\n# TYPE fdk_accept_failures_total counter\nfdk_accept_failures_total{} {}",
+        with(""),
+        snapshot.accept_failures
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP fdk_startup_seconds Time for the function to start accepting invocations.\n# TYPE fdk_startup_seconds gauge\nfdk_startup_seconds{} {}",
+        with(""),
+        snapshot.startup_millis as f64 / 1_000.0
+    );
+
+    render_histogram(
+        &mut out,
+        "fdk_invocation_duration_seconds",
+        "Time to handle an invocation.",
+        &snapshot.latency_micros,
+        1_000_000.0,
+        &with,
+    );
+    for (name, help, histogram) in [
+        (
+            "fdk_body_read_duration_seconds",
+            "Time to receive the request body from the Fn agent.",
+            &snapshot.body_read_micros,
+        ),
+        (
+            "fdk_handler_duration_seconds",
+            "Time spent in the handler.",
+            &snapshot.handler_micros,
+        ),
+        (
+            "fdk_response_write_duration_seconds",
+            "Time to write the response to the Fn agent.",
+            &snapshot.response_write_micros,
+        ),
+    ] {
+        render_histogram(&mut out, name, help, histogram, 1_000_000.0, &with);
+    }
+    render_histogram(
+        &mut out,
+        "fdk_request_body_bytes",
+        "Size of the request bodies.",
+        &snapshot.request_bytes,
+        1.0,
+        &with,
+    );
+    render_histogram(
+        &mut out,
+        "fdk_response_body_bytes",
+        "Size of the encoded response bodies.",
+        &snapshot.response_bytes,
+        1.0,
+        &with,
+    );
+    out
+}
+
+/// Renders a histogram, dividing bounds and sum by `scale` to get the base unit.
+fn render_histogram(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    histogram: &HistogramSnapshot,
+    scale: f64,
+    with: &dyn Fn(&str) -> String,
+) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+    for (bound, count) in histogram.cumulative() {
+        let le = format!("le=\"{}\"", bound as f64 / scale);
+        let _ = writeln!(out, "{}_bucket{} {}", name, with(&le), count);
+    }
+    let _ = writeln!(
+        out,
+        "{}_bucket{} {}",
+        name,
+        with("le=\"+Inf\""),
+        histogram.count
+    );
+    let _ = writeln!(
+        out,
+        "{}_sum{} {}",
+        name,
+        with(""),
+        histogram.sum as f64 / scale
+    );
+    let _ = writeln!(out, "{}_count{} {}", name, with(""), histogram.count);
+}
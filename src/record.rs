@@ -0,0 +1,96 @@
+//! Recording of invocations, and their replay.
+//!
+//! When `FN_RECORD_DIR` is set, the headers and body of every invocation are written to a
+//! `fdk-record-<call ID>-<millis>.req` file in that directory, in the format of crash dumps and
+//! with the same headers redacted. `Function::replay` runs a handler on a recorded invocation,
+//! or on a crash dump, to reproduce a failure locally. Recording writes every request to disk
+//! and is meant for debugging only.
+
+use crate::body::Body;
+use crate::crash_dump::{CrashDumpConfig, RequestSnapshot};
+use crate::errors::FunctionError;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{HeaderMap, Request};
+use std::path::{Path, PathBuf};
+
+/// Returns the recording settings if `FN_RECORD_DIR` is set. Request bodies are kept whole.
+pub(crate) fn recorder_from_env() -> Option<CrashDumpConfig> {
+    let dir = crate::context::CONFIG_FROM_ENV.get("FN_RECORD_DIR")?;
+    if dir.is_empty() {
+        return None;
+    }
+    Some(CrashDumpConfig {
+        dir: PathBuf::from(dir),
+        max_body_bytes: usize::MAX,
+        ..CrashDumpConfig::default()
+    })
+}
+
+/// Writes the invocation to the recording directory. The body is the decompressed one, so the
+/// `Content-Encoding` of the request is left out.
+pub(crate) fn record(config: &CrashDumpConfig, call_id: &str, headers: &HeaderMap, body: &[u8]) {
+    let mut headers = headers.clone();
+    headers.remove("Content-Encoding");
+    headers.remove("Fn-Http-H-Content-Encoding");
+    let snapshot = RequestSnapshot::capture_with(config, call_id, &headers, body);
+    let path = config.dir.join(snapshot.file_name("fdk-record", "req"));
+    if let Err(e) = snapshot.write_to(&path, "recorded") {
+        eprintln!("Failed to record call {}: {}", call_id, e);
+    }
+}
+
+/// Reads the request of a recorded invocation or a crash dump.
+pub(crate) fn read(path: &Path) -> Result<Request<Body>, FunctionError> {
+    let invalid = |reason: &str| FunctionError::InvalidInput {
+        inner: format!("Invalid recording {}: {}", path.display(), reason),
+        source: None,
+    };
+    let data = std::fs::read(path).map_err(|e| FunctionError::IO {
+        inner: format!("Failed to read recording {}: {}", path.display(), e),
+        source: Some(Box::new(e)),
+    })?;
+
+    let split = data
+        .windows(9)
+        .position(|w| w == b"\n\n<body: ")
+        .ok_or_else(|| invalid("no body marker"))?;
+    let head = std::str::from_utf8(&data[..split]).map_err(|_| invalid("headers not UTF-8"))?;
+    let rest = &data[split + 2..];
+    let marker_end = rest
+        .iter()
+        .position(|b| *b == b'\n')
+        .ok_or_else(|| invalid("unterminated body marker"))?;
+    let marker = std::str::from_utf8(&rest[..marker_end]).map_err(|_| invalid("body marker"))?;
+    let body = rest[marker_end + 1..].to_vec();
+    let (captured, original) = marker
+        .strip_prefix("<body: ")
+        .and_then(|m| m.strip_suffix(" bytes>"))
+        .and_then(|m| m.split_once(" of "))
+        .ok_or_else(|| invalid("malformed body marker"))?;
+    if captured != original {
+        eprintln!(
+            "Replaying {}: the body was truncated from {} to {} bytes",
+            path.display(),
+            original,
+            captured
+        );
+    }
+
+    let mut builder = Request::post("/");
+    for line in head.lines() {
+        let (name, value) = line
+            .split_once(": ")
+            .ok_or_else(|| invalid("malformed header line"))?;
+        if name == "Reason" || name == "Call ID" {
+            continue;
+        }
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| invalid(&format!("invalid header name {}", name)))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|_| invalid(&format!("invalid value of header {}", name)))?;
+        builder = builder.header(name, value);
+    }
+    builder
+        .body(Body::from(body))
+        .map_err(|e| invalid(&e.to_string()))
+}
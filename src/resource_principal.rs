@@ -0,0 +1,256 @@
+//! Resource principal authentication of functions running on OCI Functions.
+//!
+//! OCI Functions injects a resource principal session token (RPST) and its private key into
+//! the function container through the `OCI_RESOURCE_PRINCIPAL_*` environment variables.
+//! `ResourcePrincipal` loads them and signs requests to OCI APIs with the OCI HTTP signature
+//! scheme, as the function itself.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! let principal = ctx.oci_resource_principal()?;
+//! let uri: Uri = format!(
+//!     "https://objectstorage.{}.oraclecloud.com/n/",
+//!     principal.region()
+//! )
+//! .parse()
+//! .unwrap();
+//! let mut headers = HeaderMap::new();
+//! principal.sign(&Method::GET, &uri, &mut headers, b"")?;
+//! ```
+
+use crate::errors::FunctionError;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hyper::header::{
+    HeaderName, HeaderValue, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, DATE, HOST,
+};
+use hyper::{HeaderMap, Method, Uri};
+use pkcs8::DecodePrivateKey;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs1v15::SigningKey;
+use rsa::signature::{SignatureEncoding, Signer};
+use rsa::RsaPrivateKey;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Version of the resource principal environment supported, where the token and the key are
+/// provided directly rather than fetched from an endpoint.
+const SUPPORTED_VERSION: &str = "2.2";
+
+/// ResourcePrincipal is the session token and key of the function's resource principal.
+#[derive(Clone)]
+pub struct ResourcePrincipal {
+    session_token: String,
+    signing_key: SigningKey<Sha256>,
+    region: String,
+    tenancy_id: Option<String>,
+    compartment_id: Option<String>,
+    expires_at: Option<SystemTime>,
+}
+
+impl fmt::Debug for ResourcePrincipal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResourcePrincipal")
+            .field("region", &self.region)
+            .field("tenancy_id", &self.tenancy_id)
+            .field("compartment_id", &self.compartment_id)
+            .field("expires_at", &self.expires_at)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns the config variable `name`, read from the file it names when it is an absolute path.
+fn value_or_file(
+    config: &HashMap<String, String>,
+    name: &str,
+) -> Result<Option<String>, FunctionError> {
+    match config.get(name) {
+        Some(v) if v.starts_with('/') => std::fs::read_to_string(v)
+            .map(|v| Some(v.trim().to_owned()))
+            .map_err(|e| FunctionError::Initialization {
+                inner: format!("Failed to read {} from {}: {}", name, v, e),
+                source: Some(Box::new(e)),
+            }),
+        Some(v) => Ok(Some(v.trim().to_owned())),
+        None => Ok(None),
+    }
+}
+
+fn required(config: &HashMap<String, String>, name: &str) -> Result<String, FunctionError> {
+    value_or_file(config, name)?.ok_or_else(|| FunctionError::Initialization {
+        inner: format!(
+            "No {} specified, the function is not running with a resource principal",
+            name
+        ),
+        source: None,
+    })
+}
+
+fn invalid(name: &str, e: impl fmt::Display) -> FunctionError {
+    FunctionError::Initialization {
+        inner: format!("Invalid {} specified: {}", name, e),
+        source: None,
+    }
+}
+
+impl ResourcePrincipal {
+    /// Loads the resource principal from `OCI_RESOURCE_PRINCIPAL_VERSION`,
+    /// `OCI_RESOURCE_PRINCIPAL_RPST`, `OCI_RESOURCE_PRINCIPAL_PRIVATE_PEM`,
+    /// `OCI_RESOURCE_PRINCIPAL_PRIVATE_PEM_PASSPHRASE` and `OCI_RESOURCE_PRINCIPAL_REGION`.
+    /// The token, key and passphrase may be given as values or as absolute paths of files.
+    pub(crate) fn from_config(config: &HashMap<String, String>) -> Result<Self, FunctionError> {
+        let version = required(config, "OCI_RESOURCE_PRINCIPAL_VERSION")?;
+        if version != SUPPORTED_VERSION {
+            return Err(invalid("OCI_RESOURCE_PRINCIPAL_VERSION", version));
+        }
+        let session_token = required(config, "OCI_RESOURCE_PRINCIPAL_RPST")?;
+        let pem = required(config, "OCI_RESOURCE_PRINCIPAL_PRIVATE_PEM")?;
+        let passphrase = value_or_file(config, "OCI_RESOURCE_PRINCIPAL_PRIVATE_PEM_PASSPHRASE")?;
+        let region = required(config, "OCI_RESOURCE_PRINCIPAL_REGION")?;
+
+        let private_key = match passphrase {
+            Some(passphrase) => RsaPrivateKey::from_pkcs8_encrypted_pem(&pem, passphrase)
+                .map_err(|e| invalid("OCI_RESOURCE_PRINCIPAL_PRIVATE_PEM", e))?,
+            None => RsaPrivateKey::from_pkcs8_pem(&pem)
+                .or_else(|_| RsaPrivateKey::from_pkcs1_pem(&pem))
+                .map_err(|e| invalid("OCI_RESOURCE_PRINCIPAL_PRIVATE_PEM", e))?,
+        };
+        let claims = session_token
+            .split('.')
+            .nth(1)
+            .and_then(|claims| URL_SAFE_NO_PAD.decode(claims.trim_end_matches('=')).ok())
+            .and_then(|claims| serde_json::from_slice::<serde_json::Value>(&claims).ok())
+            .ok_or_else(|| invalid("OCI_RESOURCE_PRINCIPAL_RPST", "not a JWT"))?;
+        let claim = |name: &str| claims.get(name).and_then(|v| v.as_str()).map(str::to_owned);
+
+        Ok(Self {
+            tenancy_id: claim("res_tenant"),
+            compartment_id: claim("res_compartment"),
+            expires_at: claims
+                .get("exp")
+                .and_then(|v| v.as_u64())
+                .map(|exp| UNIX_EPOCH + Duration::from_secs(exp)),
+            session_token,
+            signing_key: SigningKey::new(private_key),
+            region,
+        })
+    }
+
+    /// Returns the resource principal session token.
+    pub fn session_token(&self) -> &str {
+        &self.session_token
+    }
+
+    /// Returns the key ID to sign requests with, `ST$` followed by the session token.
+    pub fn key_id(&self) -> String {
+        format!("ST${}", self.session_token)
+    }
+
+    /// Returns the region of the function, e.g. `us-ashburn-1`.
+    pub fn region(&self) -> &str {
+        &self.region
+    }
+
+    /// Returns the OCID of the tenancy of the function, from the `res_tenant` claim.
+    pub fn tenancy_id(&self) -> Option<&str> {
+        self.tenancy_id.as_deref()
+    }
+
+    /// Returns the OCID of the compartment of the function, from the `res_compartment` claim.
+    pub fn compartment_id(&self) -> Option<&str> {
+        self.compartment_id.as_deref()
+    }
+
+    /// Returns when the session token expires, from the `exp` claim.
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.expires_at
+    }
+
+    /// Returns whether the session token has expired.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|exp| exp <= SystemTime::now())
+    }
+
+    /// Signs a request to an OCI API, setting its `date`, `host` and `authorization` headers,
+    /// and for `POST`, `PUT` and `PATCH` requests its `content-length`, `content-type` and
+    /// `x-content-sha256` headers computed from `body`. Headers already set are kept.
+    pub fn sign(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &mut HeaderMap,
+        body: &[u8],
+    ) -> Result<(), FunctionError> {
+        let invalid_header = |e: hyper::header::InvalidHeaderValue| FunctionError::InvalidInput {
+            inner: format!("Failed to sign request: {}", e),
+            source: Some(Box::new(e)),
+        };
+        if !headers.contains_key(DATE) {
+            let date = DateTime::<Utc>::from(SystemTime::now())
+                .format("%a, %d %b %Y %H:%M:%S GMT")
+                .to_string();
+            headers.insert(DATE, HeaderValue::from_str(&date).map_err(invalid_header)?);
+        }
+        if !headers.contains_key(HOST) {
+            let host = uri.authority().ok_or_else(|| FunctionError::InvalidInput {
+                inner: format!("Failed to sign request: no host in {}", uri),
+                source: None,
+            })?;
+            headers.insert(
+                HOST,
+                HeaderValue::from_str(host.as_str()).map_err(invalid_header)?,
+            );
+        }
+        let mut signed = vec!["date", "(request-target)", "host"];
+        if matches!(*method, Method::POST | Method::PUT | Method::PATCH) {
+            let digest = STANDARD.encode(Sha256::digest(body));
+            headers
+                .entry(CONTENT_LENGTH)
+                .or_insert_with(|| HeaderValue::from(body.len()));
+            headers
+                .entry(CONTENT_TYPE)
+                .or_insert_with(|| HeaderValue::from_static("application/json"));
+            headers
+                .entry(HeaderName::from_static("x-content-sha256"))
+                .or_insert(HeaderValue::from_str(&digest).map_err(invalid_header)?);
+            signed.extend(["content-length", "content-type", "x-content-sha256"]);
+        }
+
+        let target = uri.path_and_query().map_or("/", |p| p.as_str());
+        let mut signing_string = Vec::with_capacity(signed.len());
+        for name in &signed {
+            let value = match *name {
+                "(request-target)" => format!("{} {}", method.as_str().to_lowercase(), target),
+                name => headers
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default()
+                    .to_owned(),
+            };
+            signing_string.push(format!("{}: {}", name, value));
+        }
+        let signature = self
+            .signing_key
+            .try_sign(signing_string.join("\n").as_bytes())
+            .map_err(|e| FunctionError::System {
+                inner: format!("Failed to sign request: {}", e),
+                source: Some(Box::new(e)),
+            })?;
+
+        let authorization = format!(
+            "Signature version=\"1\",keyId=\"{}\",algorithm=\"rsa-sha256\",headers=\"{}\",signature=\"{}\"",
+            self.key_id(),
+            signed.join(" "),
+            STANDARD.encode(signature.to_bytes())
+        );
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&authorization).map_err(invalid_header)?,
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,426 @@
+use crate::body::Body;
+use crate::coercions::OutputCoercible;
+use crate::context::RuntimeContext;
+use crate::errors::FunctionError;
+use crate::logging::{self, Level};
+use hyper::body::{Bytes, Frame};
+use hyper::header::{HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, LOCATION};
+use hyper::{HeaderMap, StatusCode};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+
+/// Chunks a streamed response buffers before `StreamSender::send` waits for the caller.
+const STREAM_CAPACITY: usize = 16;
+
+/// Size of the chunks files are streamed in.
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// FnResponse is a handler output setting the status, headers and raw body of the response
+/// itself, whatever the accepted content type.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// fn handler(ctx: &mut RuntimeContext, _: String) -> Result<FnResponse> {
+///     FnResponse::temporary_redirect("https://example.com/login")
+/// }
+/// ```
+#[derive(Debug)]
+pub struct FnResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    stream: Option<StreamBody>,
+}
+
+impl FnResponse {
+    /// Creates a response with `status` and `body`.
+    pub fn new<B: Into<Vec<u8>>>(status: StatusCode, body: B) -> Self {
+        Self {
+            status,
+            headers: HeaderMap::new(),
+            body: body.into(),
+            stream: None,
+        }
+    }
+
+    /// Creates a response with `status` whose body of `content_type` is written through the
+    /// returned sender once the handler has returned, e.g. from a spawned task. The body ends
+    /// when the sender is dropped.
+    ///
+    /// Trailers added to the sender are sent as HTTP trailers when the Fn agent accepts them,
+    /// i.e. over HTTP/2 with `TE: trailers`. Otherwise they are appended to the body as a final
+    /// JSON line, `{"fn_trailers":{"name":"value"}}`, on a line of its own.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let (response, sender) = FnResponse::stream(StatusCode::OK, "application/x-ndjson");
+    /// tokio::spawn(async move {
+    ///     let mut count = 0;
+    ///     for record in records {
+    ///         sender.send(record.to_json_line()).await?;
+    ///         count += 1;
+    ///     }
+    ///     sender.trailer("X-Record-Count", &count.to_string())
+    /// });
+    /// Ok(response)
+    /// ```
+    pub fn stream(status: StatusCode, content_type: &str) -> (Self, StreamSender) {
+        let (sender, receiver) = mpsc::channel(STREAM_CAPACITY);
+        let trailers = Arc::new(Mutex::new(HeaderMap::new()));
+        let mut response = Self::new(status, Vec::new());
+        if let Ok(value) = HeaderValue::from_str(content_type) {
+            response.headers.insert(CONTENT_TYPE, value);
+        }
+        response.stream = Some(StreamBody {
+            receiver,
+            trailers: trailers.clone(),
+            http_trailers: false,
+            ends_with_newline: true,
+            finished: false,
+        });
+        (response, StreamSender { sender, trailers })
+    }
+
+    /// Creates an empty response redirecting the caller to `url` with a 3xx `status`.
+    pub fn redirect(url: &str, status: StatusCode) -> Result<Self, FunctionError> {
+        if !status.is_redirection() {
+            return Err(FunctionError::InvalidInput {
+                inner: format!("Invalid redirect status code {}", status.as_u16()),
+                source: None,
+            });
+        }
+        Self::new(status, Vec::new()).with_header(LOCATION.as_str(), url)
+    }
+
+    /// Creates an empty `308 Permanent Redirect` response to `url`.
+    pub fn permanent_redirect(url: &str) -> Result<Self, FunctionError> {
+        Self::redirect(url, StatusCode::PERMANENT_REDIRECT)
+    }
+
+    /// Creates an empty `307 Temporary Redirect` response to `url`.
+    pub fn temporary_redirect(url: &str) -> Result<Self, FunctionError> {
+        Self::redirect(url, StatusCode::TEMPORARY_REDIRECT)
+    }
+
+    /// Creates a `200 OK` response streaming the file at `path`, with a Content-Type guessed
+    /// from its extension and its Content-Length, without reading it into memory.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let report = generate_report(ctx.temp_dir()?)?;
+    /// FnResponse::file(report)
+    /// ```
+    pub fn file<P: AsRef<Path>>(path: P) -> Result<Self, FunctionError> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path).map_err(|e| FunctionError::IO {
+            inner: format!("Failed to open {}: {}", path.display(), e),
+            source: Some(Box::new(e)),
+        })?;
+        Self::from_file(tokio::fs::File::from_std(file), content_type_of(path))
+    }
+
+    /// Creates a `200 OK` response streaming `file` as `content_type`, with its
+    /// Content-Length. The file is read from its current position. Must be called from within
+    /// the Tokio runtime, e.g. in a handler.
+    pub fn from_file(file: tokio::fs::File, content_type: &str) -> Result<Self, FunctionError> {
+        let io_error = |e: std::io::Error| FunctionError::IO {
+            inner: format!("Failed to stream file: {}", e),
+            source: Some(Box::new(e)),
+        };
+        let mut file = file.try_into_std().map_err(|_| {
+            io_error(std::io::Error::other(
+                "an operation on the file is in progress",
+            ))
+        })?;
+        let length = file.metadata().map_err(io_error)?.len();
+        let position = std::io::Seek::stream_position(&mut file).map_err(io_error)?;
+        let mut file = tokio::fs::File::from_std(file);
+
+        let (response, sender) = Self::stream(StatusCode::OK, content_type);
+        let response = response.with_header(
+            CONTENT_LENGTH.as_str(),
+            &length.saturating_sub(position).to_string(),
+        )?;
+        tokio::spawn(async move {
+            loop {
+                let mut chunk = vec![0; FILE_CHUNK_SIZE];
+                match file.read(&mut chunk).await {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        chunk.truncate(n);
+                        if sender.send(chunk).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        logging::log(Level::Warn, format_args!("failed to stream file: {}", e));
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(response)
+    }
+
+    /// Adds a header the caller receives. Like the headers added to the context, it is sent
+    /// as `Fn-Http-H-{name}` when the function is invoked through an HTTP trigger.
+    pub fn with_header(mut self, name: &str, value: &str) -> Result<Self, FunctionError> {
+        let invalid = |e: &dyn std::fmt::Display| FunctionError::InvalidInput {
+            inner: format!("Invalid response header {}: {}", name, e),
+            source: None,
+        };
+        let name = HeaderName::from_str(name).map_err(|e| invalid(&e))?;
+        let value = HeaderValue::from_str(value).map_err(|e| invalid(&e))?;
+        self.headers.append(name, value);
+        Ok(self)
+    }
+
+    /// Sets the Content-Type of the response, replacing the one negotiated from the Accept
+    /// header of the request. The body is sent as is.
+    pub fn with_content_type(mut self, content_type: &str) -> Result<Self, FunctionError> {
+        let value =
+            HeaderValue::from_str(content_type).map_err(|e| FunctionError::InvalidInput {
+                inner: format!("Invalid response content type {}: {}", content_type, e),
+                source: Some(Box::new(e)),
+            })?;
+        self.headers.insert(CONTENT_TYPE, value);
+        Ok(self)
+    }
+
+    /// Converts the output of a handler to a response, encoded like the FDK encodes the
+    /// outputs of the function. Outputs setting their own response are kept as they are.
+    pub(crate) fn from_output<S: OutputCoercible>(
+        ctx: &RuntimeContext,
+        mut output: S,
+    ) -> Result<Self, FunctionError> {
+        let parts = output.take_response_parts();
+        let stream = output.take_stream();
+        let format = crate::function::response_format(ctx);
+        let body = match stream {
+            Some(_) => Vec::new(),
+            None => crate::function::encode_body(&format, output)?,
+        };
+        let (status, headers) = match parts {
+            Some(parts) => parts,
+            None => {
+                let mut headers = HeaderMap::new();
+                if ctx.response_content_type().is_none() {
+                    if let Ok(value) = HeaderValue::from_str(&format.as_header_value()) {
+                        headers.insert(CONTENT_TYPE, value);
+                    }
+                }
+                (ctx.get_status_code().unwrap_or(StatusCode::OK), headers)
+            }
+        };
+        Ok(Self {
+            status,
+            headers,
+            body,
+            stream,
+        })
+    }
+
+    /// Returns the status of the response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Returns the headers of the response under their own names, without the `Fn-Http-H-`
+    /// prefix added for HTTP triggers.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Returns the body of the response, empty if it is streamed.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
+
+impl OutputCoercible for FnResponse {
+    fn try_encode_json(self) -> Result<Vec<u8>, FunctionError> {
+        Ok(self.body)
+    }
+    fn try_encode_xml(self) -> Result<Vec<u8>, FunctionError> {
+        Ok(self.body)
+    }
+    fn try_encode_yaml(self) -> Result<Vec<u8>, FunctionError> {
+        Ok(self.body)
+    }
+    fn try_encode_plain(self) -> Result<Vec<u8>, FunctionError> {
+        Ok(self.body)
+    }
+    fn try_encode_urlencoded(self) -> Result<Vec<u8>, FunctionError> {
+        Ok(self.body)
+    }
+
+    fn take_response_parts(&mut self) -> Option<(StatusCode, HeaderMap)> {
+        Some((self.status, std::mem::take(&mut self.headers)))
+    }
+
+    fn take_stream(&mut self) -> Option<StreamBody> {
+        self.stream.take()
+    }
+}
+
+/// StreamSender writes the body and trailers of a streamed `FnResponse`.
+#[derive(Clone, Debug)]
+pub struct StreamSender {
+    sender: mpsc::Sender<Bytes>,
+    trailers: Arc<Mutex<HeaderMap>>,
+}
+
+impl StreamSender {
+    /// Sends a chunk of the body, waiting while the caller is slower than the function. Fails
+    /// once the caller went away.
+    pub async fn send<B: Into<Bytes>>(&self, chunk: B) -> Result<(), FunctionError> {
+        self.sender
+            .send(chunk.into())
+            .await
+            .map_err(|_| stream_closed())
+    }
+
+    /// Sends a chunk of the body from a thread outside of the async runtime.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from within an async execution context.
+    pub fn blocking_send<B: Into<Bytes>>(&self, chunk: B) -> Result<(), FunctionError> {
+        self.sender
+            .blocking_send(chunk.into())
+            .map_err(|_| stream_closed())
+    }
+
+    /// Adds a trailer, sent once the body ends.
+    pub fn trailer(&self, name: &str, value: &str) -> Result<(), FunctionError> {
+        let invalid = |e: &dyn std::fmt::Display| FunctionError::InvalidInput {
+            inner: format!("Invalid response trailer {}: {}", name, e),
+            source: None,
+        };
+        let name = HeaderName::from_str(name).map_err(|e| invalid(&e))?;
+        let value = HeaderValue::from_str(value).map_err(|e| invalid(&e))?;
+        if let Ok(mut trailers) = self.trailers.lock() {
+            trailers.append(name, value);
+        }
+        Ok(())
+    }
+}
+
+fn stream_closed() -> FunctionError {
+    FunctionError::IO {
+        inner: "The caller closed the response stream".into(),
+        source: None,
+    }
+}
+
+/// StreamBody is the receiving end of a streamed response, taken by the FDK from the output
+/// of the function.
+#[derive(Debug)]
+pub struct StreamBody {
+    receiver: mpsc::Receiver<Bytes>,
+    trailers: Arc<Mutex<HeaderMap>>,
+    http_trailers: bool,
+    ends_with_newline: bool,
+    finished: bool,
+}
+
+impl StreamBody {
+    /// Returns a body yielding the chunks as they are sent, then the trailers, as HTTP
+    /// trailers if `http_trailers` is set or as a final JSON line otherwise.
+    pub(crate) fn into_body(mut self, http_trailers: bool) -> Body {
+        self.http_trailers = http_trailers;
+        Body::stream(self)
+    }
+
+    pub(crate) fn poll_frame(&mut self, cx: &mut Context<'_>) -> Poll<Option<Frame<Bytes>>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+        loop {
+            match self.receiver.poll_recv(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(chunk)) if chunk.is_empty() => continue,
+                Poll::Ready(Some(chunk)) => {
+                    self.ends_with_newline = chunk.ends_with(b"\n");
+                    return Poll::Ready(Some(Frame::data(chunk)));
+                }
+                Poll::Ready(None) => break,
+            }
+        }
+        self.finished = true;
+        let trailers = self
+            .trailers
+            .lock()
+            .map(|mut trailers| std::mem::take(&mut *trailers))
+            .unwrap_or_default();
+        if trailers.is_empty() {
+            Poll::Ready(None)
+        } else if self.http_trailers {
+            Poll::Ready(Some(Frame::trailers(trailers)))
+        } else {
+            Poll::Ready(Some(Frame::data(trailer_chunk(
+                &trailers,
+                self.ends_with_newline,
+            ))))
+        }
+    }
+
+    pub(crate) fn is_end_stream(&self) -> bool {
+        self.finished
+    }
+}
+
+/// Returns the content type of a file from its extension, `application/octet-stream` if
+/// unknown.
+fn content_type_of(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "txt" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "yaml" | "yml" => "application/yaml",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "wasm" => "application/wasm",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Renders `trailers` as the final `{"fn_trailers":{...}}` line of a body.
+fn trailer_chunk(trailers: &HeaderMap, ends_with_newline: bool) -> Bytes {
+    let mut fields = serde_json::Map::new();
+    for (name, value) in trailers {
+        fields.insert(
+            name.as_str().to_owned(),
+            String::from_utf8_lossy(value.as_bytes())
+                .into_owned()
+                .into(),
+        );
+    }
+    let line = serde_json::json!({ "fn_trailers": fields });
+    let prefix = if ends_with_newline { "" } else { "\n" };
+    Bytes::from(format!("{}{}\n", prefix, line))
+}
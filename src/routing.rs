@@ -0,0 +1,741 @@
+//! Routing of HTTP invocations to handlers by method and path.
+//!
+//! Static tables are built at compile time with the [`routes!`](crate::routes) macro, which
+//! rejects malformed paths, unknown methods and duplicate routes when the table is compiled,
+//! and are matched without allocating. A [`Router`] is built at run time from typed handlers
+//! and runs as the function itself, answering `404 Not Found` and `405 Method Not Allowed`.
+
+use crate::coercions::{ContentType, InputCoercible, JsonStrictness, OutputCoercible, RawBody};
+use crate::context::RuntimeContext;
+use crate::errors::FunctionError;
+use crate::function::Result;
+use crate::response::FnResponse;
+use hyper::StatusCode;
+
+/// Methods accepted in route tables.
+const METHODS: [&str; 9] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "PATCH", "OPTIONS", "CONNECT", "TRACE",
+];
+
+/// Route is an entry of a `RouteTable`. Paths are made of `/` separated segments, either
+/// literal or `:name` parameters matching any non empty segment.
+#[derive(Debug)]
+pub struct Route<H> {
+    method: &'static str,
+    path: &'static str,
+    handler: H,
+}
+
+impl<H> Route<H> {
+    /// Creates a route, panicking if `method` or `path` is invalid. Used in constant
+    /// expressions by `routes!`, it turns a typo into a build failure.
+    pub const fn new(method: &'static str, path: &'static str, handler: H) -> Self {
+        validate_method(method);
+        validate_path(path);
+        Self {
+            method,
+            path,
+            handler,
+        }
+    }
+
+    /// Returns the method of the route.
+    pub fn method(&self) -> &'static str {
+        self.method
+    }
+
+    /// Returns the path pattern of the route.
+    pub fn path(&self) -> &'static str {
+        self.path
+    }
+
+    /// Returns the handler of the route.
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+}
+
+/// RouteTable is a static list of routes, matched in order.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// type Handler = fn(&mut RuntimeContext, &Params) -> Result<String>;
+///
+/// static ROUTES: RouteTable<Handler> = fdk::routes! {
+///     GET "/users/:id" => get_user,
+///     POST "/users" => create_user,
+/// };
+///
+/// if let Some(route) = ROUTES.find("GET", "/users/42") {
+///     assert_eq!(route.params().get("id"), Some("42"));
+/// }
+/// ```
+#[derive(Debug)]
+pub struct RouteTable<H: 'static> {
+    routes: &'static [Route<H>],
+}
+
+impl<H> RouteTable<H> {
+    /// Creates a table from `routes`, panicking if two routes have the same method and
+    /// equivalent paths.
+    pub const fn new(routes: &'static [Route<H>]) -> Self {
+        let mut i = 0;
+        while i < routes.len() {
+            let mut j = i + 1;
+            while j < routes.len() {
+                if str_eq(routes[i].method, routes[j].method)
+                    && same_shape(routes[i].path, routes[j].path)
+                {
+                    panic!("duplicate route");
+                }
+                j += 1;
+            }
+            i += 1;
+        }
+        Self { routes }
+    }
+
+    /// Returns the routes of the table.
+    pub fn routes(&self) -> &'static [Route<H>] {
+        self.routes
+    }
+
+    /// Returns the first route matching `method` and `path`, ignoring a trailing `/`.
+    pub fn find<'p>(&self, method: &str, path: &'p str) -> Option<RouteMatch<'static, 'p, H>> {
+        self.routes
+            .iter()
+            .find(|route| route.method == method && matches(route.path, path))
+            .map(|route| RouteMatch {
+                route,
+                params: Params {
+                    pattern: route.path,
+                    path,
+                },
+            })
+    }
+}
+
+type RouterHandler =
+    Box<dyn Fn(&mut RuntimeContext, &Params, &[u8]) -> Result<FnResponse> + Send + Sync>;
+
+/// Router dispatches HTTP invocations to typed handlers on the `Fn-Http-Method` and the path
+/// of the `Fn-Http-Request-Url` of the request. Requests to no route get `404 Not Found`, and
+/// requests to a route with another method `405 Method Not Allowed` with an `Allow` header.
+///
+/// Each handler decodes the body and encodes its output like a function does. Empty bodies
+/// decode as JSON `null`, so that handlers of bodiless requests can take `()` or an
+/// `Option`. Invocations without an HTTP trigger are routed as `POST /`.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let router = Router::new()
+///     .base_path("/t/shop")
+///     .get("/users/:id", |ctx, params, _: ()| get_user(ctx, params.get("id")))
+///     .post("/users", |ctx, _, user: NewUser| create_user(ctx, user));
+/// Function::run(router.into_handler()).await
+/// ```
+#[derive(Default)]
+pub struct Router {
+    base_path: String,
+    routes: Vec<Route<RouterHandler>>,
+}
+
+impl Router {
+    /// Creates a router without routes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Strips `prefix` from request paths before matching them, e.g. the `/t/{app}` prefix of
+    /// HTTP trigger URLs. Requests whose path does not start with it get `404 Not Found`.
+    pub fn base_path(mut self, prefix: &str) -> Self {
+        self.base_path = prefix.trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Adds a route, matched after the routes added before it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `method` or `path` is invalid, like `routes!` fails the build.
+    pub fn route<T, S, H>(mut self, method: &'static str, path: &'static str, handler: H) -> Self
+    where
+        T: InputCoercible,
+        S: OutputCoercible,
+        H: Fn(&mut RuntimeContext, &Params, T) -> Result<S> + Send + Sync + 'static,
+    {
+        let handler = move |ctx: &mut RuntimeContext, params: &Params, body: &[u8]| {
+            let arg = decode_input(ctx, body)?;
+            let output = handler(ctx, params, arg)?;
+            FnResponse::from_output(ctx, output)
+        };
+        self.routes
+            .push(Route::new(method, path, Box::new(handler)));
+        self
+    }
+
+    /// Adds a `GET` route.
+    pub fn get<T, S, H>(self, path: &'static str, handler: H) -> Self
+    where
+        T: InputCoercible,
+        S: OutputCoercible,
+        H: Fn(&mut RuntimeContext, &Params, T) -> Result<S> + Send + Sync + 'static,
+    {
+        self.route("GET", path, handler)
+    }
+
+    /// Adds a `POST` route.
+    pub fn post<T, S, H>(self, path: &'static str, handler: H) -> Self
+    where
+        T: InputCoercible,
+        S: OutputCoercible,
+        H: Fn(&mut RuntimeContext, &Params, T) -> Result<S> + Send + Sync + 'static,
+    {
+        self.route("POST", path, handler)
+    }
+
+    /// Adds a `PUT` route.
+    pub fn put<T, S, H>(self, path: &'static str, handler: H) -> Self
+    where
+        T: InputCoercible,
+        S: OutputCoercible,
+        H: Fn(&mut RuntimeContext, &Params, T) -> Result<S> + Send + Sync + 'static,
+    {
+        self.route("PUT", path, handler)
+    }
+
+    /// Adds a `DELETE` route.
+    pub fn delete<T, S, H>(self, path: &'static str, handler: H) -> Self
+    where
+        T: InputCoercible,
+        S: OutputCoercible,
+        H: Fn(&mut RuntimeContext, &Params, T) -> Result<S> + Send + Sync + 'static,
+    {
+        self.route("DELETE", path, handler)
+    }
+
+    /// Adds a `PATCH` route.
+    pub fn patch<T, S, H>(self, path: &'static str, handler: H) -> Self
+    where
+        T: InputCoercible,
+        S: OutputCoercible,
+        H: Fn(&mut RuntimeContext, &Params, T) -> Result<S> + Send + Sync + 'static,
+    {
+        self.route("PATCH", path, handler)
+    }
+
+    /// Dispatches an invocation to the handler of its route.
+    pub fn handle(&self, ctx: &mut RuntimeContext, body: RawBody) -> Result<FnResponse> {
+        let method = ctx
+            .method()
+            .map_or("POST", |method| method.as_str())
+            .to_owned();
+        let path = ctx.request_url().map_or("/", |url| url.path()).to_owned();
+        let path = match path.strip_prefix(self.base_path.as_str()) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+            _ => return not_found(),
+        };
+
+        let mut allowed = Vec::new();
+        for route in self.routes.iter().filter(|route| matches(route.path, path)) {
+            if route.method == method {
+                let params = Params {
+                    pattern: route.path,
+                    path,
+                };
+                return (route.handler)(ctx, &params, &body.0);
+            }
+            allowed.push(route.method);
+        }
+        if allowed.is_empty() {
+            return not_found();
+        }
+        FnResponse::new(
+            StatusCode::METHOD_NOT_ALLOWED,
+            serde_json::json!({ "error": "method not allowed" }).to_string(),
+        )
+        .with_content_type("application/json")?
+        .with_header("Allow", &allowed.join(", "))
+    }
+
+    /// Turns the router into the handler of a function.
+    pub fn into_handler(
+        self,
+    ) -> impl Fn(&mut RuntimeContext, RawBody) -> Result<FnResponse> + Send + Sync + 'static {
+        move |ctx, body| self.handle(ctx, body)
+    }
+}
+
+/// Decodes the input of a handler like a function does, with empty bodies decoding as JSON
+/// `null`.
+fn decode_input<T: InputCoercible>(ctx: &RuntimeContext, body: &[u8]) -> Result<T> {
+    if let Some(arg) = T::try_decode_request(ctx, body) {
+        return arg;
+    }
+    let body = match (body.is_empty(), ctx.content_type()) {
+        (true, ContentType::JSON) => b"null".as_ref(),
+        _ => body,
+    };
+    crate::function::decode_body(ctx.content_type(), JsonStrictness::Strict, body).map_err(|e| {
+        match e {
+            e @ FunctionError::InvalidField { .. } => e,
+            e => FunctionError::Coercion {
+                inner: format!("Error while deserializing request body: {}", e),
+                source: Some(Box::new(e)),
+            },
+        }
+    })
+}
+
+type MediaTypeHandler = Box<dyn Fn(&mut RuntimeContext, &[u8]) -> Result<FnResponse> + Send + Sync>;
+
+/// ContentTypeRouter dispatches invocations to typed handlers on the media type of their
+/// `Content-Type`, e.g. to take JSON and multipart bodies in one function. Media types are
+/// matched without case nor parameters, in registration order, and may end with a `/*`
+/// wildcard. Requests of no registered type get `415 Unsupported Media Type`.
+///
+/// Handlers decode the body like a function does: take a `RawBody` for media types the FDK
+/// does not decode.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let router = ContentTypeRouter::new()
+///     .on("application/json", |ctx, order: Order| place_order(ctx, order))
+///     .on("multipart/form-data", |ctx, body: RawBody| upload(ctx, body));
+/// Function::run(router.into_handler()).await
+/// ```
+#[derive(Default)]
+pub struct ContentTypeRouter {
+    handlers: Vec<(String, MediaTypeHandler)>,
+    fallback: Option<MediaTypeHandler>,
+}
+
+impl ContentTypeRouter {
+    /// Creates a router without handlers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the handler of requests of `media_type`, such as `application/json` or `text/*`.
+    pub fn on<T, S, H>(mut self, media_type: &str, handler: H) -> Self
+    where
+        T: InputCoercible,
+        S: OutputCoercible,
+        H: Fn(&mut RuntimeContext, T) -> Result<S> + Send + Sync + 'static,
+    {
+        self.handlers
+            .push((media_type.to_ascii_lowercase(), typed_handler(handler)));
+        self
+    }
+
+    /// Sets the handler of requests of no registered media type, or without a Content-Type.
+    pub fn fallback<T, S, H>(mut self, handler: H) -> Self
+    where
+        T: InputCoercible,
+        S: OutputCoercible,
+        H: Fn(&mut RuntimeContext, T) -> Result<S> + Send + Sync + 'static,
+    {
+        self.fallback = Some(typed_handler(handler));
+        self
+    }
+
+    /// Dispatches an invocation to the handler of its media type.
+    pub fn handle(&self, ctx: &mut RuntimeContext, body: RawBody) -> Result<FnResponse> {
+        let media_type = ctx
+            .header_str(hyper::header::CONTENT_TYPE.as_str())
+            .map(|v| {
+                v.split(';')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_ascii_lowercase()
+            });
+        let handler = media_type
+            .as_deref()
+            .and_then(|media_type| {
+                self.handlers
+                    .iter()
+                    .find(|(pattern, _)| media_type_matches(pattern, media_type))
+            })
+            .map(|(_, handler)| handler)
+            .or(self.fallback.as_ref());
+        match handler {
+            Some(handler) => handler(ctx, &body.0),
+            None => FnResponse::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                serde_json::json!({ "error": "unsupported media type" }).to_string(),
+            )
+            .with_content_type("application/json"),
+        }
+    }
+
+    /// Turns the router into the handler of a function.
+    pub fn into_handler(
+        self,
+    ) -> impl Fn(&mut RuntimeContext, RawBody) -> Result<FnResponse> + Send + Sync + 'static {
+        move |ctx, body| self.handle(ctx, body)
+    }
+}
+
+fn typed_handler<T, S, H>(handler: H) -> MediaTypeHandler
+where
+    T: InputCoercible,
+    S: OutputCoercible,
+    H: Fn(&mut RuntimeContext, T) -> Result<S> + Send + Sync + 'static,
+{
+    Box::new(move |ctx: &mut RuntimeContext, body: &[u8]| {
+        let arg = decode_input(ctx, body)?;
+        let output = handler(ctx, arg)?;
+        FnResponse::from_output(ctx, output)
+    })
+}
+
+fn media_type_matches(pattern: &str, media_type: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(top_level) => media_type
+            .split_once('/')
+            .is_some_and(|(t, _)| t == top_level),
+        None => pattern == media_type,
+    }
+}
+
+fn not_found() -> Result<FnResponse> {
+    FnResponse::new(
+        StatusCode::NOT_FOUND,
+        serde_json::json!({ "error": "not found" }).to_string(),
+    )
+    .with_content_type("application/json")
+}
+
+/// RouteMatch is a route matching a request path.
+#[derive(Debug)]
+pub struct RouteMatch<'t, 'p, H> {
+    route: &'t Route<H>,
+    params: Params<'p>,
+}
+
+impl<'t, 'p, H> RouteMatch<'t, 'p, H> {
+    /// Returns the matched route.
+    pub fn route(&self) -> &'t Route<H> {
+        self.route
+    }
+
+    /// Returns the handler of the matched route.
+    pub fn handler(&self) -> &'t H {
+        &self.route.handler
+    }
+
+    /// Returns the parameters of the path.
+    pub fn params(&self) -> &Params<'p> {
+        &self.params
+    }
+}
+
+/// Params are the values of the `:name` segments of a matched path, read from the path on
+/// demand.
+#[derive(Clone, Copy, Debug)]
+pub struct Params<'p> {
+    pattern: &'static str,
+    path: &'p str,
+}
+
+impl<'p> Params<'p> {
+    /// Returns the value of the `:name` parameter.
+    pub fn get(&self, name: &str) -> Option<&'p str> {
+        self.iter().find(|(n, _)| *n == name).map(|(_, v)| v)
+    }
+
+    /// Returns the names and values of the parameters, in path order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &'p str)> {
+        segments(self.pattern)
+            .zip(segments(self.path))
+            .filter_map(|(pattern, value)| pattern.strip_prefix(':').map(|name| (name, value)))
+    }
+}
+
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    let path = path.strip_prefix('/').unwrap_or(path);
+    let path = path.strip_suffix('/').unwrap_or(path);
+    path.split('/').filter(|s| !s.is_empty())
+}
+
+fn matches(pattern: &str, path: &str) -> bool {
+    let mut patterns = segments(pattern);
+    let mut values = segments(path);
+    loop {
+        match (patterns.next(), values.next()) {
+            (None, None) => return true,
+            (Some(p), Some(v)) if p.starts_with(':') || p == v => {}
+            _ => return false,
+        }
+    }
+}
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+const fn validate_method(method: &str) {
+    let mut i = 0;
+    while i < METHODS.len() {
+        if str_eq(method, METHODS[i]) {
+            return;
+        }
+        i += 1;
+    }
+    panic!("unknown route method");
+}
+
+/// Checks `path` is `/` or `/` separated non empty segments without a trailing `/`, with
+/// unique, non empty `:name` parameters made of alphanumerics and `_`.
+const fn validate_path(path: &str) {
+    let bytes = path.as_bytes();
+    if bytes.is_empty() || bytes[0] != b'/' {
+        panic!("route paths must start with '/'");
+    }
+    if bytes.len() == 1 {
+        return;
+    }
+    if bytes[bytes.len() - 1] == b'/' {
+        panic!("route paths must not end with '/'");
+    }
+    let mut i = 1;
+    while i < bytes.len() {
+        let start = i;
+        let mut end = i;
+        while end < bytes.len() && bytes[end] != b'/' {
+            match bytes[end] {
+                b'?' | b'#' | b' ' | b'%' | b'*' => panic!("invalid character in route path"),
+                b':' if end != start => panic!("':' must start a route path segment"),
+                _ => {}
+            }
+            end += 1;
+        }
+        if end == start {
+            panic!("empty segment in route path");
+        }
+        if bytes[start] == b':' {
+            if end == start + 1 {
+                panic!("empty route parameter name");
+            }
+            let mut k = start + 1;
+            while k < end {
+                if !(bytes[k].is_ascii_alphanumeric() || bytes[k] == b'_') {
+                    panic!("route parameter names must be alphanumeric");
+                }
+                k += 1;
+            }
+            if param_defined_before(bytes, start) {
+                panic!("duplicate route parameter");
+            }
+        }
+        i = end + 1;
+    }
+}
+
+/// Returns true if the parameter starting at `at` appears earlier in `path`.
+const fn param_defined_before(path: &[u8], at: usize) -> bool {
+    let name_len = segment_end(path, at) - at;
+    let mut i = 1;
+    while i < at {
+        let end = segment_end(path, i);
+        if path[i] == b':' && end - i == name_len {
+            let mut k = 0;
+            while k < name_len && path[i + k] == path[at + k] {
+                k += 1;
+            }
+            if k == name_len {
+                return true;
+            }
+        }
+        i = end + 1;
+    }
+    false
+}
+
+const fn segment_end(path: &[u8], start: usize) -> usize {
+    let mut end = start;
+    while end < path.len() && path[end] != b'/' {
+        end += 1;
+    }
+    end
+}
+
+/// Returns true if `a` and `b` match the same paths, i.e. only differ in parameter names.
+const fn same_shape(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        let (a_end, b_end) = (segment_end(a, i), segment_end(b, j));
+        let a_param = a_end > i && a[i] == b':';
+        let b_param = b_end > j && b[j] == b':';
+        if a_param != b_param {
+            return false;
+        }
+        if !a_param {
+            if a_end - i != b_end - j {
+                return false;
+            }
+            let mut k = 0;
+            while k < a_end - i {
+                if a[i + k] != b[j + k] {
+                    return false;
+                }
+                k += 1;
+            }
+        }
+        i = a_end + 1;
+        j = b_end + 1;
+    }
+    i >= a.len() && j >= b.len()
+}
+
+/// Builds a [`RouteTable`] at compile time from `METHOD "path" => handler` entries. Malformed
+/// paths, unknown methods and duplicate routes fail the build.
+///
+/// The handlers must share a type, usually a function pointer type given by the annotation of
+/// the table.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// static ROUTES: RouteTable<Handler> = fdk::routes! {
+///     GET "/users/:id" => get_user,
+///     POST "/users" => create_user,
+/// };
+/// ```
+#[macro_export]
+macro_rules! routes {
+    ($($method:ident $path:literal => $handler:expr),* $(,)?) => {{
+        const _: () = $crate::routing::validate(&[$((stringify!($method), $path)),*]);
+        $crate::routing::RouteTable::new(&[
+            $($crate::routing::Route::new(stringify!($method), $path, $handler)),*
+        ])
+    }};
+}
+
+/// Validates the methods and paths of a table, so `routes!` fails the build on invalid routes
+/// even when the table is built at run time.
+#[doc(hidden)]
+pub const fn validate(routes: &[(&'static str, &'static str)]) {
+    let mut i = 0;
+    while i < routes.len() {
+        validate_method(routes[i].0);
+        validate_path(routes[i].1);
+        let mut j = i + 1;
+        while j < routes.len() {
+            if str_eq(routes[i].0, routes[j].0) && same_shape(routes[i].1, routes[j].1) {
+                panic!("duplicate route");
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Handler = fn() -> &'static str;
+
+    static ROUTES: RouteTable<Handler> = crate::routes! {
+        GET "/users/:id" => || "get_user",
+        GET "/users/:id/orders/:order" => || "get_order",
+        POST "/users" => || "create_user",
+    };
+
+    fn router() -> Router {
+        Router::new()
+            .base_path("/t/shop/")
+            .get("/users/:id", |_, params, _: ()| {
+                Ok(params.get("id").unwrap_or_default().to_owned())
+            })
+            .put("/users/:id", |_, _, _: ()| Ok(()))
+            .post("/users", |_, _, _: ()| Ok(()))
+    }
+
+    fn dispatch(method: hyper::Method, url: &str) -> FnResponse {
+        let mut ctx = RuntimeContext::builder()
+            .method(method)
+            .request_url(url)
+            .build();
+        router().handle(&mut ctx, RawBody(Vec::new())).unwrap()
+    }
+
+    #[test]
+    fn parameters_are_read_from_the_path() {
+        let matched = ROUTES.find("GET", "/users/42/orders/7").unwrap();
+        assert_eq!((matched.handler())(), "get_order");
+        assert_eq!(matched.params().get("id"), Some("42"));
+        assert_eq!(matched.params().get("order"), Some("7"));
+        assert_eq!(matched.params().get("name"), None);
+        assert_eq!(
+            matched.params().iter().collect::<Vec<_>>(),
+            [("id", "42"), ("order", "7")]
+        );
+    }
+
+    #[test]
+    fn trailing_slash_is_ignored() {
+        let matched = ROUTES.find("GET", "/users/42/").unwrap();
+        assert_eq!((matched.handler())(), "get_user");
+        assert_eq!(matched.params().get("id"), Some("42"));
+        assert!(ROUTES.find("POST", "/users/").is_some());
+    }
+
+    #[test]
+    fn empty_segments_do_not_match_parameters() {
+        assert!(ROUTES.find("GET", "/users/").is_none());
+        assert!(ROUTES.find("GET", "/users/42/orders").is_none());
+        assert!(ROUTES.find("DELETE", "/users/42").is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "route paths must not end with '/'")]
+    fn trailing_slash_in_pattern_is_rejected() {
+        Route::new("GET", "/users/", ());
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate route parameter")]
+    fn duplicate_parameter_is_rejected() {
+        Route::new("GET", "/users/:id/orders/:id", ());
+    }
+
+    #[test]
+    fn router_passes_parameters_to_the_handler() {
+        let response = dispatch(hyper::Method::GET, "/t/shop/users/42/");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), b"\"42\"");
+    }
+
+    #[test]
+    fn router_answers_unknown_paths_with_404() {
+        for url in ["/t/shop/orders", "/t/shopping/users/42", "/users/42"] {
+            let response = dispatch(hyper::Method::GET, url);
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", url);
+        }
+    }
+
+    #[test]
+    fn router_answers_other_methods_with_405() {
+        let response = dispatch(hyper::Method::DELETE, "/t/shop/users/42");
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()["Allow"], "GET, PUT");
+    }
+}
@@ -0,0 +1,88 @@
+use crate::errors::FunctionError;
+
+/// RuntimeFlavor selects the scheduler of the tokio runtime built by
+/// `FunctionBuilder::run_blocking`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuntimeFlavor {
+    /// Runs every task on the thread calling `run_blocking`.
+    CurrentThread,
+    /// Runs tasks on a pool of worker threads.
+    MultiThread,
+}
+
+/// RuntimeConfig sizes the tokio runtime built by `FunctionBuilder::run_blocking`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuntimeConfig {
+    pub flavor: RuntimeFlavor,
+    /// Number of worker threads of a multi-thread runtime. Defaults to the number of CPUs.
+    pub worker_threads: Option<usize>,
+    /// Maximum number of threads running blocking tasks. Defaults to tokio's, 512.
+    pub max_blocking_threads: Option<usize>,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            flavor: RuntimeFlavor::MultiThread,
+            worker_threads: None,
+            max_blocking_threads: None,
+        }
+    }
+}
+
+impl RuntimeConfig {
+    /// Returns the defaults overridden by `FN_RUNTIME_FLAVOR` (`current_thread` or
+    /// `multi_thread`), `FN_WORKER_THREADS` and `FN_MAX_BLOCKING_THREADS`.
+    pub(crate) fn from_env() -> Result<Self, FunctionError> {
+        let config = &crate::context::CONFIG_FROM_ENV;
+        let number = |name: &str| match config.get(name) {
+            Some(v) => match v.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(Some(n)),
+                _ => Err(FunctionError::Initialization {
+                    inner: format!("Invalid {} specified: {}", name, v),
+                    source: None,
+                }),
+            },
+            None => Ok(None),
+        };
+        let flavor = match config.get("FN_RUNTIME_FLAVOR").map(String::as_str) {
+            Some("current_thread") => RuntimeFlavor::CurrentThread,
+            Some("multi_thread") | None => RuntimeFlavor::MultiThread,
+            Some(v) => {
+                return Err(FunctionError::Initialization {
+                    inner: format!("Invalid FN_RUNTIME_FLAVOR specified: {}", v),
+                    source: None,
+                })
+            }
+        };
+        Ok(Self {
+            flavor,
+            worker_threads: number("FN_WORKER_THREADS")?,
+            max_blocking_threads: number("FN_MAX_BLOCKING_THREADS")?,
+        })
+    }
+
+    /// Builds the runtime.
+    pub(crate) fn build(&self) -> Result<tokio::runtime::Runtime, FunctionError> {
+        let mut builder = match self.flavor {
+            RuntimeFlavor::CurrentThread => tokio::runtime::Builder::new_current_thread(),
+            RuntimeFlavor::MultiThread => {
+                let mut builder = tokio::runtime::Builder::new_multi_thread();
+                if let Some(threads) = self.worker_threads {
+                    builder.worker_threads(threads);
+                }
+                builder
+            }
+        };
+        if let Some(threads) = self.max_blocking_threads {
+            builder.max_blocking_threads(threads);
+        }
+        builder
+            .enable_all()
+            .build()
+            .map_err(|e| FunctionError::Initialization {
+                inner: format!("Failed to build the tokio runtime: {}", e),
+                source: Some(Box::new(e)),
+            })
+    }
+}
@@ -0,0 +1,127 @@
+//! Resolution of config values stored in OCI Vault.
+//!
+//! A config variable whose value is `vault:` followed by the OCID of a Vault secret, e.g.
+//! `DB_PASSWORD=vault:ocid1.vaultsecret.oc1..xxx`, is replaced by the current version of the
+//! secret, fetched with the resource principal of the function. With
+//! `FunctionBuilder::resolve_secrets`, this happens once at startup and `RuntimeContext::config`
+//! returns the secrets, so they never appear in the function configuration.
+//!
+//! The Secrets endpoint of the region of the function is used, unless `FN_SECRETS_ENDPOINT`
+//! sets another one.
+
+use crate::errors::FunctionError;
+use crate::resource_principal::ResourcePrincipal;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper::{HeaderMap, Method, Uri};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Prefix of the config values naming a Vault secret.
+pub const VAULT_PREFIX: &str = "vault:";
+
+/// How long fetching a secret may take.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Returns the OCID of the secret named by `value`, if it names one.
+pub fn secret_id(value: &str) -> Option<&str> {
+    value
+        .strip_prefix(VAULT_PREFIX)
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+}
+
+/// Returns `config` with the values naming Vault secrets replaced by the secrets, fetched
+/// concurrently with the resource principal of the function. Fails if any secret cannot be
+/// fetched.
+pub async fn resolve(
+    config: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, FunctionError> {
+    let references: Vec<(&String, &str)> = config
+        .iter()
+        .filter_map(|(key, value)| secret_id(value).map(|id| (key, id)))
+        .collect();
+    let mut resolved = config.clone();
+    if references.is_empty() {
+        return Ok(resolved);
+    }
+
+    let principal = ResourcePrincipal::from_config(config)?;
+    let endpoint = match config.get("FN_SECRETS_ENDPOINT") {
+        Some(endpoint) => endpoint.trim_end_matches('/').to_owned(),
+        None => format!(
+            "https://secrets.vaults.{}.oci.oraclecloud.com",
+            principal.region()
+        ),
+    };
+    let client = reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .build()
+        .map_err(|e| FunctionError::Initialization {
+            inner: format!("Failed to create the Vault client: {}", e),
+            source: Some(Box::new(e)),
+        })?;
+    let secrets = futures::future::try_join_all(references.into_iter().map(|(key, id)| {
+        let (client, principal, endpoint) = (&client, &principal, &endpoint);
+        async move {
+            fetch(client, principal, endpoint, id)
+                .await
+                .map(|secret| (key.clone(), secret))
+                .map_err(|e| FunctionError::Initialization {
+                    inner: format!("Failed to fetch secret {} of {}: {}", id, key, e),
+                    source: None,
+                })
+        }
+    }))
+    .await?;
+    resolved.extend(secrets);
+    Ok(resolved)
+}
+
+/// Fetches the current version of the secret `id` as text.
+async fn fetch(
+    client: &reqwest::Client,
+    principal: &ResourcePrincipal,
+    endpoint: &str,
+    id: &str,
+) -> Result<String, String> {
+    let uri: Uri = format!("{}/20190301/secretbundles/{}", endpoint, id)
+        .parse()
+        .map_err(|e| format!("invalid URL: {}", e))?;
+    let mut headers = HeaderMap::new();
+    principal
+        .sign(&Method::GET, &uri, &mut headers, b"")
+        .map_err(|e| e.to_string())?;
+
+    let response = client
+        .get(uri.to_string())
+        .headers(headers)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = response.status();
+    let body = response.bytes().await.map_err(|e| e.to_string())?;
+    if !status.is_success() {
+        return Err(format!(
+            "Vault answered {}: {}",
+            status,
+            String::from_utf8_lossy(&body)
+        ));
+    }
+
+    let bundle: serde_json::Value =
+        serde_json::from_slice(&body).map_err(|e| format!("invalid secret bundle: {}", e))?;
+    let content = &bundle["secretBundleContent"];
+    let encoded = content["content"]
+        .as_str()
+        .ok_or("secret bundle without content")?;
+    match content["contentType"].as_str() {
+        Some("BASE64") | None => {
+            let decoded = STANDARD
+                .decode(encoded)
+                .map_err(|e| format!("invalid secret content: {}", e))?;
+            String::from_utf8(decoded).map_err(|_| "secret content is not UTF-8".to_owned())
+        }
+        Some(other) => Err(format!("unsupported secret content type {}", other)),
+    }
+}
@@ -0,0 +1,148 @@
+use crate::coercions::{ContentType, InputCoercible};
+use crate::function::encode_body;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+
+/// FormatSupport is the outcome of round-tripping a value through one content type.
+#[derive(Clone, Debug)]
+pub struct FormatSupport {
+    pub content_type: ContentType,
+    pub result: Result<(), String>,
+}
+
+/// SelfTestReport lists which content types the input and output types of a function
+/// can actually be decoded from and encoded to.
+#[derive(Clone, Debug)]
+pub struct SelfTestReport {
+    pub input: Vec<FormatSupport>,
+    pub output: Vec<FormatSupport>,
+}
+
+impl SelfTestReport {
+    /// Returns true if every content type round-tripped for both types.
+    pub fn all_supported(&self) -> bool {
+        self.input
+            .iter()
+            .chain(self.output.iter())
+            .all(|f| f.result.is_ok())
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (direction, formats) in [("input", &self.input), ("output", &self.output)] {
+            for format in formats {
+                match &format.result {
+                    Ok(()) => writeln!(
+                        f,
+                        "{:<7}{:<36}ok",
+                        direction,
+                        format.content_type.as_header_value()
+                    )?,
+                    Err(e) => writeln!(
+                        f,
+                        "{:<7}{:<36}unsupported: {}",
+                        direction,
+                        format.content_type.as_header_value(),
+                        e
+                    )?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Encodes `value`, decodes the bytes back and checks that encoding the decoded value
+/// produces the same bytes.
+fn round_trip<V>(content_type: &ContentType, value: V) -> Result<(), String>
+where
+    V: Serialize + DeserializeOwned,
+{
+    let encoded = encode_body(content_type, value).map_err(|e| e.to_string())?;
+    let decoded: V = match content_type {
+        ContentType::JSON => V::try_decode_json(&encoded),
+        ContentType::YAML => V::try_decode_yaml(&encoded),
+        ContentType::XML => V::try_decode_xml(&encoded),
+        ContentType::Plain => V::try_decode_plain(&encoded),
+        ContentType::URLEncoded => V::try_decode_urlencoded(&encoded),
+    }
+    .map_err(|e| e.to_string())?;
+    let reencoded = encode_body(content_type, decoded).map_err(|e| e.to_string())?;
+    if reencoded != encoded {
+        return Err("value changed after decoding".into());
+    }
+    Ok(())
+}
+
+fn check_formats<V>(value: &V) -> Vec<FormatSupport>
+where
+    V: Serialize + DeserializeOwned + Clone,
+{
+    ContentType::ALL
+        .iter()
+        .map(|content_type| FormatSupport {
+            content_type: content_type.clone(),
+            result: round_trip(content_type, value.clone()),
+        })
+        .collect()
+}
+
+/// Round-trips the default values of a function's input and output types through every
+/// content type, catching serde incompatibilities (e.g. maps in XML) before deploying.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let report = fdk::selftest::<MyInput, MyOutput>();
+/// print!("{}", report);
+/// ```
+pub fn selftest<T, S>() -> SelfTestReport
+where
+    T: Default + Serialize + DeserializeOwned + Clone,
+    S: Default + Serialize + DeserializeOwned + Clone,
+{
+    selftest_with(T::default(), S::default())
+}
+
+/// Like `selftest`, but round-trips the given example values.
+pub fn selftest_with<T, S>(input: T, output: S) -> SelfTestReport
+where
+    T: Serialize + DeserializeOwned + Clone,
+    S: Serialize + DeserializeOwned + Clone,
+{
+    SelfTestReport {
+        input: check_formats(&input),
+        output: check_formats(&output),
+    }
+}
+
+/// Runs `selftest` and exits when the binary was started with the `selftest` subcommand, and
+/// returns otherwise. Call it at the beginning of `main`:
+///
+/// ```rust,ignore
+/// fdk::run_selftest_command::<String, String>();
+/// ```
+///
+/// The process exits with status 1 if any content type is unsupported.
+pub fn run_selftest_command<T, S>()
+where
+    T: Default + Serialize + DeserializeOwned + Clone,
+    S: Default + Serialize + DeserializeOwned + Clone,
+{
+    let matches = clap::App::new("fdk")
+        .setting(clap::AppSettings::AllowExternalSubcommands)
+        .subcommand(
+            clap::SubCommand::with_name("selftest")
+                .about("Checks which content types the function input and output support"),
+        )
+        .get_matches_from_safe(std::env::args_os());
+
+    if let Ok(matches) = matches {
+        if matches.subcommand_matches("selftest").is_some() {
+            let report = selftest::<T, S>();
+            print!("{}", report);
+            std::process::exit(if report.all_supported() { 0 } else { 1 });
+        }
+    }
+}
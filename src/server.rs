@@ -0,0 +1,124 @@
+//! The HTTP server the Fn agent sends invocations to.
+
+use crate::errors::FunctionError;
+use hyper_util::rt::{TokioExecutor, TokioTimer};
+use hyper_util::server::conn::auto;
+use std::time::Duration;
+
+/// Smallest read buffer hyper accepts for HTTP/1.1 connections.
+const MIN_BUF_SIZE: usize = 8192;
+
+/// HttpProtocol selects the HTTP versions the FDK speaks with the Fn agent. Over HTTP/2 the
+/// agent can multiplex concurrent invocations on one connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HttpProtocol {
+    /// HTTP/1.1, or HTTP/2 when the agent opens the connection with the HTTP/2 preface.
+    #[default]
+    Auto,
+    /// HTTP/1.1 only.
+    Http1,
+    /// HTTP/2 only, with prior knowledge.
+    Http2,
+}
+
+/// ServerConfig tunes the connections of the Fn agent. The defaults are hyper's, except that
+/// idle HTTP/1.1 connections are never timed out.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// Keeps HTTP/1.1 connections open between invocations. Defaults to true.
+    pub keep_alive: bool,
+    /// How long an HTTP/1.1 connection may wait for the headers of a request, idle keep-alive
+    /// connections included, before it is closed. Defaults to no limit.
+    pub header_read_timeout: Option<Duration>,
+    /// Maximum number of bytes buffered per HTTP/1.1 connection, at least 8192. Defaults to
+    /// hyper's, about 400 KB.
+    pub max_buf_size: Option<usize>,
+    /// Flushes pipelined HTTP/1.1 responses together instead of one by one.
+    pub pipeline_flush: bool,
+    /// Interval of the pings keeping HTTP/2 connections alive. Defaults to no pings.
+    pub http2_keep_alive_interval: Option<Duration>,
+    /// How long to wait for the acknowledgement of an HTTP/2 ping before closing the
+    /// connection. Defaults to 20 seconds.
+    pub http2_keep_alive_timeout: Option<Duration>,
+    /// Maximum number of concurrent invocations on an HTTP/2 connection. Defaults to 200.
+    pub http2_max_concurrent_streams: Option<u32>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            keep_alive: true,
+            header_read_timeout: None,
+            max_buf_size: None,
+            pipeline_flush: false,
+            http2_keep_alive_interval: None,
+            http2_keep_alive_timeout: None,
+            http2_max_concurrent_streams: None,
+        }
+    }
+}
+
+/// ServerOptions holds the builder settings of the HTTP server.
+#[derive(Default)]
+pub(crate) struct ServerOptions {
+    pub(crate) protocol: Option<HttpProtocol>,
+    pub(crate) config: ServerConfig,
+}
+
+impl ServerOptions {
+    /// Returns the connection builder, with the settings not set on the builder read from
+    /// `FN_HTTP_PROTOCOL`, `auto`, `http1` or `http2`.
+    pub(crate) fn connection_builder(&self) -> Result<auto::Builder<TokioExecutor>, FunctionError> {
+        let config = &crate::context::CONFIG_FROM_ENV;
+        let protocol = match self.protocol {
+            Some(protocol) => protocol,
+            None => match config.get("FN_HTTP_PROTOCOL").map(String::as_str) {
+                Some("auto") | None => HttpProtocol::Auto,
+                Some("http1") => HttpProtocol::Http1,
+                Some("http2") => HttpProtocol::Http2,
+                Some(v) => {
+                    return Err(FunctionError::Initialization {
+                        inner: format!("Invalid FN_HTTP_PROTOCOL specified: {}", v),
+                        source: None,
+                    })
+                }
+            },
+        };
+        let config = &self.config;
+        if config.max_buf_size.is_some_and(|size| size < MIN_BUF_SIZE) {
+            return Err(FunctionError::Initialization {
+                inner: format!(
+                    "Invalid max_buf_size specified: must be at least {} bytes",
+                    MIN_BUF_SIZE
+                ),
+                source: None,
+            });
+        }
+
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        let mut http1 = builder.http1();
+        http1
+            .timer(TokioTimer::new())
+            .keep_alive(config.keep_alive)
+            .header_read_timeout(config.header_read_timeout)
+            .pipeline_flush(config.pipeline_flush);
+        if let Some(size) = config.max_buf_size {
+            http1.max_buf_size(size);
+        }
+        let mut http2 = builder.http2();
+        http2
+            .timer(TokioTimer::new())
+            .keep_alive_interval(config.http2_keep_alive_interval);
+        if let Some(timeout) = config.http2_keep_alive_timeout {
+            http2.keep_alive_timeout(timeout);
+        }
+        if let Some(max) = config.http2_max_concurrent_streams {
+            http2.max_concurrent_streams(max);
+        }
+        Ok(match protocol {
+            HttpProtocol::Auto => builder,
+            HttpProtocol::Http1 => builder.http1_only(),
+            HttpProtocol::Http2 => builder.http2_only(),
+        })
+    }
+}
@@ -0,0 +1,195 @@
+use crate::logging::{self, Level};
+use crate::metrics::{self, MetricsExporter};
+use futures::future::BoxFuture;
+use std::fmt;
+use std::future::Future;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+
+/// ShutdownPhase names the steps of the shutdown sequence, in the order they run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShutdownPhase {
+    /// The listener stops accepting new connections.
+    StopAccepting,
+    /// In-flight invocations are given time to complete.
+    Drain,
+    /// User registered `on_shutdown` hooks are run.
+    Hooks,
+    /// Metrics exporters and buffered logs are flushed.
+    Flush,
+    /// Socket files created by the FDK are removed.
+    Cleanup,
+}
+
+impl fmt::Display for ShutdownPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::StopAccepting => "stop accepting",
+            Self::Drain => "drain",
+            Self::Hooks => "shutdown hooks",
+            Self::Flush => "flush",
+            Self::Cleanup => "cleanup",
+        };
+        f.write_str(name)
+    }
+}
+
+/// ShutdownTimeouts holds the time budget of every timed shutdown phase. The defaults add up
+/// to less than the 10 seconds grace period container runtimes usually allow after SIGTERM.
+#[derive(Clone, Debug)]
+pub struct ShutdownTimeouts {
+    pub drain: Duration,
+    pub hooks: Duration,
+    pub flush: Duration,
+}
+
+impl Default for ShutdownTimeouts {
+    fn default() -> Self {
+        Self {
+            drain: Duration::from_secs(5),
+            hooks: Duration::from_secs(2),
+            flush: Duration::from_secs(1),
+        }
+    }
+}
+
+/// ExitReason describes why `FunctionBuilder::run` stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitReason {
+    /// A shutdown signal was received and every phase completed in time.
+    CleanShutdown,
+    /// The listener or the signal handlers could not be set up.
+    InitFailure,
+    /// The server stopped because of an unrecoverable error.
+    ServerError,
+    /// The process was killed for running an invocation past its deadline. Invocations
+    /// exceeding their deadline are failed with a timeout error and never stop the process, so
+    /// `run` doesn't return it: it is the code supervisors report for the kill of the Fn agent.
+    DeadlineKill,
+    /// In-flight invocations were still running when the drain timeout expired, i.e. the
+    /// graceful shutdown was cut short.
+    DrainTimeout,
+}
+
+/// ExitCodePolicy maps every `ExitReason` to a process exit code, so orchestration tooling can
+/// tell failure modes apart.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExitCodePolicy {
+    /// Code of `ExitReason::CleanShutdown`, 0 by default.
+    pub clean_shutdown: i32,
+    /// Code of `ExitReason::InitFailure`, 1 by default.
+    pub init_failure: i32,
+    /// Code of `ExitReason::ServerError`, 2 by default.
+    pub server_error: i32,
+    /// Code of `ExitReason::DeadlineKill`, 3 by default.
+    pub deadline_kill: i32,
+    /// Code of `ExitReason::DrainTimeout`, 4 by default.
+    pub drain_timeout: i32,
+}
+
+impl Default for ExitCodePolicy {
+    fn default() -> Self {
+        Self {
+            clean_shutdown: 0,
+            init_failure: 1,
+            server_error: 2,
+            deadline_kill: 3,
+            drain_timeout: 4,
+        }
+    }
+}
+
+impl ExitCodePolicy {
+    /// Returns the exit code assigned to `reason`.
+    pub fn code(&self, reason: ExitReason) -> i32 {
+        match reason {
+            ExitReason::CleanShutdown => self.clean_shutdown,
+            ExitReason::InitFailure => self.init_failure,
+            ExitReason::ServerError => self.server_error,
+            ExitReason::DeadlineKill => self.deadline_kill,
+            ExitReason::DrainTimeout => self.drain_timeout,
+        }
+    }
+}
+
+pub(crate) type ShutdownHook = Box<dyn FnOnce() -> BoxFuture<'static, ()> + Send>;
+
+pub(crate) fn boxed_hook<F, Fut>(hook: F) -> ShutdownHook
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    Box::new(move || Box::pin(hook()))
+}
+
+/// Resolves when the process receives SIGTERM or SIGINT.
+pub(crate) async fn wait_for_signal() -> std::io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = terminate.recv() => {},
+        _ = interrupt.recv() => {},
+    }
+    Ok(())
+}
+
+/// Shutdown runs the phases that follow the drain of in-flight invocations.
+pub(crate) struct Shutdown {
+    pub timeouts: ShutdownTimeouts,
+    pub hooks: Vec<ShutdownHook>,
+    pub exporters: Vec<Arc<dyn MetricsExporter>>,
+    pub socket_files: Vec<std::path::PathBuf>,
+}
+
+impl Shutdown {
+    pub(crate) fn log_phase(phase: ShutdownPhase) {
+        logging::log(Level::Info, format_args!("shutdown: {}", phase));
+    }
+
+    pub(crate) fn log_timeout(phase: ShutdownPhase, timeout: Duration) {
+        logging::log(
+            Level::Warn,
+            format_args!("shutdown: {} did not complete within {:?}", phase, timeout),
+        );
+    }
+
+    /// Runs the hooks, flush and cleanup phases in order, each bounded by its timeout.
+    pub(crate) async fn finish(self) {
+        Self::log_phase(ShutdownPhase::Hooks);
+        let hooks = self.hooks;
+        let run_hooks = async move {
+            for hook in hooks {
+                hook().await;
+            }
+        };
+        if tokio::time::timeout(self.timeouts.hooks, run_hooks)
+            .await
+            .is_err()
+        {
+            Self::log_timeout(ShutdownPhase::Hooks, self.timeouts.hooks);
+        }
+
+        Self::log_phase(ShutdownPhase::Flush);
+        let exporters = self.exporters;
+        let flush = tokio::task::spawn_blocking(move || {
+            metrics::flush_exporters(&exporters);
+            #[cfg(feature = "opentelemetry")]
+            crate::otel::shutdown();
+            let _ = std::io::stdout().flush();
+            let _ = std::io::stderr().flush();
+        });
+        if tokio::time::timeout(self.timeouts.flush, flush)
+            .await
+            .is_err()
+        {
+            Self::log_timeout(ShutdownPhase::Flush, self.timeouts.flush);
+        }
+
+        Self::log_phase(ShutdownPhase::Cleanup);
+        for path in self.socket_files {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
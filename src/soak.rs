@@ -0,0 +1,207 @@
+//! Soak testing of a handler across many warm invocations.
+//!
+//! `SoakTest` drives invocations in-process through the same request pipeline as the Fn
+//! listener, and samples memory and latency once per window so leaks and drift show up before
+//! they reach production containers.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! let report = SoakTest::new(1_000_000)
+//!     .body("application/json", r#"{"name":"soak"}"#)
+//!     .run(handler)
+//!     .await;
+//! println!("{}", report);
+//! assert!(report.rss_growth().unwrap_or(0) < 16 << 20);
+//! ```
+
+use crate::body::Body;
+use crate::coercions::{InputCoercible, OutputCoercible};
+use crate::context::RuntimeContext;
+use crate::function::{self, InvocationOptions, Result};
+use http_body_util::BodyExt;
+use hyper::Request;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// SoakTest configures a soak run.
+pub struct SoakTest {
+    invocations: u64,
+    windows: u64,
+    content_type: String,
+    body: Vec<u8>,
+    headers: Vec<(String, String)>,
+}
+
+impl SoakTest {
+    /// Creates a run of `invocations` invocations with an empty JSON body, sampled over 10
+    /// windows.
+    pub fn new(invocations: u64) -> Self {
+        Self {
+            invocations,
+            windows: 10,
+            content_type: "application/json".to_owned(),
+            body: b"{}".to_vec(),
+            headers: Vec::new(),
+        }
+    }
+
+    /// Sets the number of windows the run is sampled over.
+    pub fn windows(mut self, windows: u64) -> Self {
+        self.windows = windows.max(1);
+        self
+    }
+
+    /// Sets the body sent with every invocation.
+    pub fn body<B: Into<Vec<u8>>>(mut self, content_type: &str, body: B) -> Self {
+        self.content_type = content_type.to_owned();
+        self.body = body.into();
+        self
+    }
+
+    /// Adds a header sent with every invocation.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Drives the invocations against `function` and returns the samples.
+    pub async fn run<T, S, F>(self, function: F) -> SoakReport
+    where
+        T: InputCoercible + Send + 'static,
+        S: OutputCoercible + Send + 'static,
+        F: Fn(&mut RuntimeContext, T) -> Result<S> + Send + Sync + 'static,
+    {
+        let function = Arc::new(function);
+        let mut options = InvocationOptions::default();
+        if let Err(e) = options.prepare() {
+            panic!("invalid FDK configuration: {}", e);
+        }
+        let options = Arc::new(options);
+
+        let per_window = (self.invocations / self.windows).max(1);
+        let mut report = SoakReport {
+            failures: 0,
+            windows: Vec::new(),
+        };
+        let mut done = 0;
+        while done < self.invocations {
+            let count = per_window.min(self.invocations - done);
+            let start = Instant::now();
+            for i in done..done + count {
+                let req = self.request(i);
+                let response =
+                    function::serve_request(function.clone(), options.clone(), req).await;
+                if !succeeded(&response) {
+                    report.failures += 1;
+                }
+                let _ = response.into_body().collect().await;
+            }
+            let elapsed = start.elapsed().as_secs_f64();
+            report.windows.push(SoakWindow {
+                invocations: count,
+                mean_latency: Duration::from_secs_f64(elapsed / count as f64),
+                rss_bytes: resident_memory(),
+            });
+            done += count;
+        }
+        report
+    }
+
+    fn request(&self, i: u64) -> Request<Body> {
+        let mut builder = Request::post("/")
+            .header("Fn-Call-Id", format!("soak-{}", i))
+            .header(hyper::header::CONTENT_TYPE, self.content_type.as_str());
+        for (name, value) in &self.headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        builder
+            .body(Body::from(self.body.clone()))
+            .expect("invalid soak test header")
+    }
+}
+
+fn succeeded<B>(response: &hyper::Response<B>) -> bool {
+    response.status().is_success()
+        && response
+            .headers()
+            .get("Fn-Http-Status")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u16>().ok())
+            .is_none_or(|status| status < 400)
+}
+
+/// Returns the resident set size of the process, where `/proc` is available.
+fn resident_memory() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf only reads a system constant and has no memory safety preconditions.
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * page_size.max(0) as u64)
+}
+
+/// SoakWindow holds the samples taken at the end of a window of invocations.
+#[derive(Clone, Debug)]
+pub struct SoakWindow {
+    /// Number of invocations in the window.
+    pub invocations: u64,
+    /// Mean latency of the invocations of the window.
+    pub mean_latency: Duration,
+    /// Resident memory at the end of the window, where `/proc` is available.
+    pub rss_bytes: Option<u64>,
+}
+
+/// SoakReport holds the outcome of a soak run.
+#[derive(Clone, Debug)]
+pub struct SoakReport {
+    /// Number of invocations answered with an error status.
+    pub failures: u64,
+    /// Samples of each window, in order.
+    pub windows: Vec<SoakWindow>,
+}
+
+impl SoakReport {
+    /// Returns the resident memory growth between the first and the last window, in bytes.
+    pub fn rss_growth(&self) -> Option<i64> {
+        let first = self.windows.first()?.rss_bytes?;
+        let last = self.windows.last()?.rss_bytes?;
+        Some(last as i64 - first as i64)
+    }
+
+    /// Returns the mean latency of the last window divided by the one of the first window.
+    pub fn latency_drift(&self) -> Option<f64> {
+        let first = self.windows.first()?.mean_latency.as_secs_f64();
+        let last = self.windows.last()?.mean_latency.as_secs_f64();
+        if first > 0.0 {
+            Some(last / first)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for SoakReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, window) in self.windows.iter().enumerate() {
+            writeln!(
+                f,
+                "window {}: {} invocations, mean latency {:?}, rss {}",
+                i,
+                window.invocations,
+                window.mean_latency,
+                window
+                    .rss_bytes
+                    .map_or_else(|| "n/a".to_owned(), |b| format!("{} KiB", b / 1024)),
+            )?;
+        }
+        write!(f, "failures: {}", self.failures)?;
+        if let Some(growth) = self.rss_growth() {
+            write!(f, ", rss growth: {} KiB", growth / 1024)?;
+        }
+        if let Some(drift) = self.latency_drift() {
+            write!(f, ", latency drift: {:.2}x", drift)?;
+        }
+        Ok(())
+    }
+}
@@ -1,37 +1,294 @@
+use crate::logging::{self, Level};
 use crate::FunctionError;
-use hyper::server::accept::Accept;
+use lazy_static::lazy_static;
 use std::fs;
 use std::os::unix::fs::{symlink, PermissionsExt};
-use std::path::Path;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::task::{Context, Poll};
-use tokio::net::UnixListener;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::time::Sleep;
 use url::Url;
 
-/// UDS is a wrapper over a UnixListener. It is a `hyper::server::accept::Accept` and can be used with hyper.
-pub struct UDS(UnixListener);
+/// Delay applied after the first failed accept; doubled on every consecutive failure.
+const ACCEPT_BACKOFF_BASE: Duration = Duration::from_millis(10);
+/// Upper bound of the exponential accept backoff.
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+/// Number of consecutive failures after which the accept circuit opens.
+const ACCEPT_CIRCUIT_THRESHOLD: u64 = 10;
+/// Pause applied while the accept circuit is open.
+const ACCEPT_CIRCUIT_COOLDOWN: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref ACCEPT_STATS: AcceptStats = AcceptStats::default();
+}
+
+/// Returns the process-wide accept failure counters of the function listener.
+pub fn accept_stats() -> &'static AcceptStats {
+    &ACCEPT_STATS
+}
+
+/// AcceptStats counts failures of the listener accept loop.
+#[derive(Default, Debug)]
+pub struct AcceptStats {
+    failures: AtomicU64,
+    consecutive_failures: AtomicU64,
+    circuit_trips: AtomicU64,
+}
+
+impl AcceptStats {
+    /// Total number of failed accepts since startup.
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// Number of failed accepts since the last successful one.
+    pub fn consecutive_failures(&self) -> u64 {
+        self.consecutive_failures.load(Ordering::Relaxed)
+    }
+
+    /// Number of times the accept circuit has opened.
+    pub fn circuit_trips(&self) -> u64 {
+        self.circuit_trips.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns true for accept errors caused by resource exhaustion or aborted peers, which are
+/// worth retrying instead of tearing down the server.
+fn is_transient_accept_error(err: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+
+    if matches!(
+        err.kind(),
+        ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionReset
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::OutOfMemory
+    ) {
+        return true;
+    }
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EMFILE) | Some(libc::ENFILE) | Some(libc::ENOBUFS) | Some(libc::ENOMEM)
+    )
+}
+
+fn accept_backoff(consecutive_failures: u64) -> Duration {
+    if consecutive_failures >= ACCEPT_CIRCUIT_THRESHOLD {
+        return ACCEPT_CIRCUIT_COOLDOWN;
+    }
+    let exponent = consecutive_failures.saturating_sub(1).min(16) as u32;
+    ACCEPT_BACKOFF_BASE
+        .checked_mul(1 << exponent)
+        .map_or(ACCEPT_BACKOFF_MAX, |d| d.min(ACCEPT_BACKOFF_MAX))
+}
+
+/// First file descriptor passed by socket activation, `SD_LISTEN_FDS_START`.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Set once the listener passed by socket activation was taken.
+static INHERITED_LISTENER_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Takes the listener passed by a socket-activating supervisor such as systemd, following the
+/// `sd_listen_fds` convention: `LISTEN_PID` names this process and `LISTEN_FDS` counts the
+/// descriptors passed from fd 3. The variables are left set: unsetting them while the
+/// runtime's threads run would race with their reads of the environment, and children ignore
+/// them since `LISTEN_PID` isn't theirs.
+fn inherited_listener() -> Result<Option<std::os::unix::net::UnixListener>, FunctionError> {
+    let config = &crate::context::CONFIG_FROM_ENV;
+    let (pid, fds) = match (config.get("LISTEN_PID"), config.get("LISTEN_FDS")) {
+        (Some(pid), Some(fds)) => (pid, fds),
+        _ => return Ok(None),
+    };
+    if pid.trim().parse::<u32>().ok() != Some(std::process::id()) {
+        return Ok(None);
+    }
+    let fds = fds.trim().parse::<u32>().unwrap_or(0);
+    if fds == 0 || INHERITED_LISTENER_TAKEN.swap(true, Ordering::AcqRel) {
+        return Ok(None);
+    }
+    if fds > 1 {
+        logging::log(
+            Level::Warn,
+            format_args!(
+                "{} sockets passed by socket activation, using the first",
+                fds
+            ),
+        );
+    }
+
+    // SAFETY: the supervisor passed ownership of the descriptor to this process, and it is
+    // taken once thanks to INHERITED_LISTENER_TAKEN.
+    let listener = unsafe {
+        libc::fcntl(LISTEN_FDS_START, libc::F_SETFD, libc::FD_CLOEXEC);
+        std::os::unix::net::UnixListener::from_raw_fd(LISTEN_FDS_START)
+    };
+    let inherited = listener
+        .local_addr()
+        .and_then(|_| listener.set_nonblocking(true));
+    if let Err(e) = inherited {
+        return Err(FunctionError::Initialization {
+            inner: format!(
+                "The socket passed by socket activation is not a unix socket: {}",
+                e
+            ),
+            source: None,
+        });
+    }
+    Ok(Some(listener))
+}
+
+/// UDS is a wrapper over a UnixListener accepting the connections of the Fn agent.
+///
+/// Transient accept errors (e.g. file descriptor exhaustion) are retried with an exponential
+/// backoff instead of being surfaced to the server, which would stop.
+pub struct UDS {
+    listener: Listener,
+    backoff: Option<Pin<Box<Sleep>>>,
+    socket_file_path: PathBuf,
+    phony_socket_file_path: PathBuf,
+    /// False if the listener was passed by socket activation, in which case its socket files
+    /// belong to the supervisor, or has no socket files.
+    owns_socket_files: bool,
+}
+
+/// Mode of the socket file unless configured otherwise, letting any local user connect.
+const DEFAULT_SOCKET_MODE: u32 = 0o666;
+
+/// SocketPermissions are the mode and ownership of the socket file of the listener.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SocketPermissions {
+    pub(crate) mode: Option<u32>,
+    pub(crate) uid: Option<u32>,
+    pub(crate) gid: Option<u32>,
+}
+
+/// ResolvedPermissions are `SocketPermissions` completed from the environment.
+struct ResolvedPermissions {
+    mode: u32,
+    uid: Option<u32>,
+    gid: Option<u32>,
+}
+
+impl SocketPermissions {
+    /// Completes the permissions left unset with `FN_SOCKET_MODE` (octal), `FN_SOCKET_UID`
+    /// and `FN_SOCKET_GID`.
+    fn resolve(&self) -> Result<ResolvedPermissions, FunctionError> {
+        let config = &crate::context::CONFIG_FROM_ENV;
+        let parse = |var: &str, radix: u32| -> Result<Option<u32>, FunctionError> {
+            config
+                .get(var)
+                .map(|v| {
+                    u32::from_str_radix(v.trim(), radix).map_err(|_| {
+                        FunctionError::Initialization {
+                            inner: format!("Malformed {} specified: {}", var, v),
+                            source: None,
+                        }
+                    })
+                })
+                .transpose()
+        };
+        Ok(ResolvedPermissions {
+            mode: match self.mode {
+                Some(mode) => mode,
+                None => parse("FN_SOCKET_MODE", 8)?.unwrap_or(DEFAULT_SOCKET_MODE),
+            },
+            uid: match self.uid {
+                Some(uid) => Some(uid),
+                None => parse("FN_SOCKET_UID", 10)?,
+            },
+            gid: match self.gid {
+                Some(gid) => Some(gid),
+                None => parse("FN_SOCKET_GID", 10)?,
+            },
+        })
+    }
+}
+
+impl ResolvedPermissions {
+    /// Changes the owner of `path`, if configured. Only root may give files away, so the
+    /// ownership is left alone with a warning otherwise.
+    fn chown(&self, path: &Path) -> Result<(), FunctionError> {
+        if self.uid.is_none() && self.gid.is_none() {
+            return Ok(());
+        }
+        // SAFETY: geteuid has no preconditions.
+        if unsafe { libc::geteuid() } != 0 {
+            logging::log(
+                Level::Warn,
+                "not running as root, leaving the socket ownership unchanged",
+            );
+            return Ok(());
+        }
+        std::os::unix::fs::chown(path, self.uid, self.gid).map_err(|e| {
+            FunctionError::Initialization {
+                inner: format!("Failed to change the socket ownership: {}", e),
+                source: Some(Box::new(e)),
+            }
+        })
+    }
+}
 
 impl UDS {
-    pub fn new() -> Result<Self, FunctionError> {
+    /// Listens on `FN_LISTENER`, with the socket file mode and ownership of `permissions`.
+    pub(crate) fn new(permissions: &SocketPermissions) -> Result<Self, FunctionError> {
+        let permissions = permissions.resolve()?;
         let fn_format = std::env::var("FN_FORMAT").unwrap_or_default();
         if fn_format.as_str() != "http-stream" && fn_format.as_str() != "" {
             return Err(FunctionError::Initialization {
                 inner: format!("Unsupported FN_FORMAT specified: {}", fn_format),
+                source: None,
             });
         };
 
+        if let Some(listener) = inherited_listener()? {
+            let socket_file_path = listener
+                .local_addr()
+                .ok()
+                .and_then(|addr| addr.as_pathname().map(Path::to_path_buf))
+                .unwrap_or_default();
+            logging::log(
+                Level::Info,
+                format_args!(
+                    "serving the socket passed by socket activation {}",
+                    socket_file_path.display()
+                ),
+            );
+            return Ok(UDS {
+                listener: Listener::Unix(UnixListener::from_std(listener)?),
+                backoff: None,
+                socket_file_path,
+                phony_socket_file_path: PathBuf::new(),
+                owns_socket_files: false,
+            });
+        }
+
         let fn_listener = std::env::var("FN_LISTENER")?;
         if fn_listener.is_empty() {
             return Err(FunctionError::Initialization {
                 inner: "FN_LISTENER not found in env".to_owned(),
+                source: None,
             });
         };
 
+        if fn_listener.starts_with("vsock://") {
+            return Self::vsock(&fn_listener);
+        }
+        if let Some(name) = fn_listener.strip_prefix("unix-abstract://") {
+            return Self::abstract_unix(name);
+        }
+
         let socket_url = Url::parse(&fn_listener)?;
 
         if socket_url.scheme() != "unix" || socket_url.path() == "" {
             return Err(FunctionError::Initialization {
                 inner: format!("Malformed FN_LISTENER specified: {}", socket_url.as_str()),
+                source: None,
             });
         }
 
@@ -43,22 +300,29 @@ impl UDS {
 
         // Try to clean up old sockets
         {
-            let _ = fs::remove_file(&socket_file_path);
+            let _ = fs::remove_file(socket_file_path);
             let _ = fs::remove_file(&phony_socket_file_path);
         }
 
-        let listener = UnixListener::bind(&phony_socket_file_path.to_str().unwrap())?;
+        let listener = UnixListener::bind(phony_socket_file_path.to_str().unwrap())?;
 
-        let socket = UDS(listener);
-        // Set permissions to 0o666 and set symlink
+        let socket = UDS {
+            listener: Listener::Unix(listener),
+            backoff: None,
+            socket_file_path: socket_file_path.to_path_buf(),
+            phony_socket_file_path: phony_socket_file_path.clone(),
+            owns_socket_files: true,
+        };
+        // Set permissions and ownership, and set symlink
         {
-            let _ = std::fs::set_permissions(
+            std::fs::set_permissions(
                 &phony_socket_file_path,
-                fs::Permissions::from_mode(0o666),
+                fs::Permissions::from_mode(permissions.mode),
             )?;
+            permissions.chown(&phony_socket_file_path)?;
 
-            let _ = symlink(
-                &phony_socket_file_path
+            symlink(
+                phony_socket_file_path
                     .file_name()
                     .unwrap()
                     .to_str()
@@ -68,20 +332,205 @@ impl UDS {
         }
         Ok(socket)
     }
+
+    /// Listens on the `unix-abstract://name` Linux abstract namespace socket, which has no
+    /// file to set permissions on or symlink.
+    #[cfg(target_os = "linux")]
+    fn abstract_unix(name: &str) -> Result<Self, FunctionError> {
+        use std::os::linux::net::SocketAddrExt;
+
+        let name = name.trim_end_matches('/');
+        if name.is_empty() {
+            return Err(FunctionError::Initialization {
+                inner: "Malformed FN_LISTENER specified: empty abstract socket name".to_owned(),
+                source: None,
+            });
+        }
+        let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        let listener = std::os::unix::net::UnixListener::bind_addr(&address)?;
+        listener.set_nonblocking(true)?;
+        Ok(UDS {
+            listener: Listener::Unix(UnixListener::from_std(listener)?),
+            backoff: None,
+            socket_file_path: PathBuf::new(),
+            phony_socket_file_path: PathBuf::new(),
+            owns_socket_files: false,
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn abstract_unix(name: &str) -> Result<Self, FunctionError> {
+        Err(FunctionError::Initialization {
+            inner: format!(
+                "Abstract unix sockets are only supported on Linux: {}",
+                name
+            ),
+            source: None,
+        })
+    }
+
+    /// Listens on the `vsock://cid:port` address of a function running in a microVM.
+    #[cfg(target_os = "linux")]
+    fn vsock(address: &str) -> Result<Self, FunctionError> {
+        let (cid, port) = crate::vsock::parse_address(address)?;
+        let listener = crate::vsock::VsockListener::bind(cid, port)?;
+        Ok(UDS {
+            listener: Listener::Vsock(listener),
+            backoff: None,
+            socket_file_path: PathBuf::new(),
+            phony_socket_file_path: PathBuf::new(),
+            owns_socket_files: false,
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn vsock(address: &str) -> Result<Self, FunctionError> {
+        Err(FunctionError::Initialization {
+            inner: format!("vsock listeners are only supported on Linux: {}", address),
+            source: None,
+        })
+    }
+
+    /// Accepts the next connection. It is cancel safe: a pending backoff is kept for the next
+    /// call.
+    pub(crate) async fn accept(&mut self) -> Result<Connection, FunctionError> {
+        loop {
+            if let Some(backoff) = self.backoff.as_mut() {
+                backoff.await;
+                self.backoff = None;
+            }
+            let listener = &self.listener;
+            match std::future::poll_fn(|cx| listener.poll_accept(cx)).await {
+                Ok(conn) => {
+                    self.record_accept_success();
+                    return Ok(conn);
+                }
+                Err(err) if is_transient_accept_error(&err) => self.record_accept_failure(&err),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    fn record_accept_failure(&mut self, err: &std::io::Error) {
+        ACCEPT_STATS.failures.fetch_add(1, Ordering::Relaxed);
+        let consecutive = ACCEPT_STATS
+            .consecutive_failures
+            .fetch_add(1, Ordering::Relaxed)
+            + 1;
+
+        let delay = accept_backoff(consecutive);
+        if consecutive < ACCEPT_CIRCUIT_THRESHOLD {
+            logging::log(
+                Level::Warn,
+                format_args!("accept failed ({}), retrying in {:?}", err, delay),
+            );
+        } else if consecutive.is_multiple_of(ACCEPT_CIRCUIT_THRESHOLD) {
+            ACCEPT_STATS.circuit_trips.fetch_add(1, Ordering::Relaxed);
+            logging::log(
+                Level::Error,
+                format_args!(
+                    "accept failed {} times in a row ({}), pausing for {:?}",
+                    consecutive, err, delay
+                ),
+            );
+        }
+        self.backoff = Some(Box::pin(tokio::time::sleep(delay)));
+    }
+
+    fn record_accept_success(&mut self) {
+        let consecutive = ACCEPT_STATS.consecutive_failures.swap(0, Ordering::Relaxed);
+        if consecutive >= ACCEPT_CIRCUIT_THRESHOLD {
+            logging::log(
+                Level::Info,
+                format_args!("accept recovered after {} failures", consecutive),
+            );
+        }
+    }
 }
 
-impl Accept for UDS {
-    type Conn = tokio::net::UnixStream;
-    type Error = FunctionError;
+impl Drop for UDS {
+    /// Unlinks the socket and its symlink so later runs can bind the same path. The symlink
+    /// is left alone if it was replaced by another listener in the meantime.
+    fn drop(&mut self) {
+        if !self.owns_socket_files {
+            return;
+        }
+        let points_to_us = fs::read_link(&self.socket_file_path)
+            .map(|target| Some(target.as_os_str()) == self.phony_socket_file_path.file_name())
+            .unwrap_or(false);
+        if points_to_us {
+            let _ = fs::remove_file(&self.socket_file_path);
+        }
+        let _ = fs::remove_file(&self.phony_socket_file_path);
+    }
+}
+
+/// Listener is the socket a `UDS` accepts connections on.
+enum Listener {
+    Unix(UnixListener),
+    #[cfg(target_os = "linux")]
+    Vsock(crate::vsock::VsockListener),
+}
+
+impl Listener {
+    fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<std::io::Result<Connection>> {
+        match self {
+            Self::Unix(listener) => listener
+                .poll_accept(cx)
+                .map_ok(|(stream, _)| Connection::Unix(stream)),
+            #[cfg(target_os = "linux")]
+            Self::Vsock(listener) => listener.poll_accept(cx).map_ok(Connection::Vsock),
+        }
+    }
+}
+
+/// Connection is a connection accepted by a `UDS`.
+pub enum Connection {
+    Unix(UnixStream),
+    #[cfg(target_os = "linux")]
+    Vsock(crate::vsock::VsockStream),
+}
 
-    fn poll_accept(
+impl AsyncRead for Connection {
+    fn poll_read(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
-    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
-        match self.0.poll_accept(cx) {
-            Poll::Pending => Poll::Pending,
-            Poll::Ready(Ok((socket, _address))) => Poll::Ready(Some(Ok(socket))),
-            Poll::Ready(Err(err)) => Poll::Ready(Some(Err(err.into()))),
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(target_os = "linux")]
+            Self::Vsock(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            Self::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(target_os = "linux")]
+            Self::Vsock(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Unix(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(target_os = "linux")]
+            Self::Vsock(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(target_os = "linux")]
+            Self::Vsock(stream) => Pin::new(stream).poll_shutdown(cx),
         }
     }
 }
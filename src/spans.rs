@@ -0,0 +1,30 @@
+use crate::body::Body;
+use crate::oci::forwarded_header;
+use hyper::{HeaderMap, Response};
+use tracing::field::Empty;
+use tracing::Span;
+
+/// Creates the span of an invocation. Decoding, the user function and encoding run in child
+/// spans.
+pub(crate) fn invocation_span(headers: &HeaderMap) -> Span {
+    let config = &crate::context::CONFIG_FROM_ENV;
+    tracing::info_span!(
+        "invocation",
+        call_id = forwarded_header(headers, "Fn-Call-Id").unwrap_or_default(),
+        app_name = config.get("FN_APP_NAME").map_or("", String::as_str),
+        fn_name = config.get("FN_FN_NAME").map_or("", String::as_str),
+        content_type = forwarded_header(headers, "Content-Type").unwrap_or_default(),
+        status = Empty,
+    )
+}
+
+/// Records the status reported in `response` on the invocation span.
+pub(crate) fn record_status(span: &Span, response: &Response<Body>) {
+    let status = response
+        .headers()
+        .get("Fn-Http-Status")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u16>().ok())
+        .unwrap_or_else(|| response.status().as_u16());
+    span.record("status", status);
+}
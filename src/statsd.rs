@@ -0,0 +1,146 @@
+use crate::errors::FunctionError;
+use crate::metrics::{MetricsExporter, MetricsSnapshot};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+static STATSD: OnceLock<Arc<Statsd>> = OnceLock::new();
+
+/// How often the counters are pushed, the default flush interval of StatsD.
+pub(crate) const PUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Largest datagram sent, to stay below the MTU of common networks.
+const MAX_DATAGRAM: usize = 1432;
+
+/// Returns the address set in `FN_STATSD_ADDRESS`, if any.
+pub(crate) fn address_from_env() -> Option<String> {
+    crate::context::CONFIG_FROM_ENV
+        .get("FN_STATSD_ADDRESS")
+        .cloned()
+}
+
+/// Statsd pushes the FDK metrics to a StatsD or DogStatsD server over UDP: the invocation,
+/// error and accept failure counts every `PUSH_INTERVAL`, and the latency of every invocation
+/// as a timing.
+pub(crate) struct Statsd {
+    socket: UdpSocket,
+    prefix: String,
+    tags: String,
+    last: Mutex<MetricsSnapshot>,
+}
+
+/// Sets up the StatsD client sending to `address`, `<host>:<port>`, with the metric names
+/// prefixed with `FN_STATSD_PREFIX`, `fdk` by default, and the DogStatsD tags of
+/// `FN_STATSD_TAGS`, e.g. `env:prod,team:shop`, if set.
+pub(crate) fn init(address: &str) -> Result<Arc<Statsd>, FunctionError> {
+    let malformed = || FunctionError::Initialization {
+        inner: format!("Malformed FN_STATSD_ADDRESS specified: {}", address),
+        source: None,
+    };
+    let target = address
+        .to_socket_addrs()
+        .map_err(|_| malformed())?
+        .next()
+        .ok_or_else(malformed)?;
+    let local: SocketAddr = if target.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(target)?;
+    socket.set_nonblocking(true)?;
+
+    let config = &crate::context::CONFIG_FROM_ENV;
+    let prefix = config
+        .get("FN_STATSD_PREFIX")
+        .cloned()
+        .unwrap_or_else(|| "fdk".to_owned());
+    let tags = match config.get("FN_STATSD_TAGS") {
+        Some(tags) if !tags.is_empty() => format!("|#{}", tags),
+        _ => String::new(),
+    };
+    let statsd = Arc::new(Statsd {
+        socket,
+        prefix,
+        tags,
+        last: Mutex::new(MetricsSnapshot::default()),
+    });
+    let _ = STATSD.set(statsd.clone());
+    Ok(statsd)
+}
+
+/// Sends the latency of a completed invocation, if StatsD is set up.
+pub(crate) fn record_invocation(latency: Duration) {
+    if let Some(statsd) = STATSD.get() {
+        let line = format!(
+            "{}.invocation.latency:{:.3}|ms{}",
+            statsd.prefix,
+            latency.as_secs_f64() * 1000.0,
+            statsd.tags
+        );
+        let _ = statsd.socket.send(line.as_bytes());
+    }
+}
+
+impl Statsd {
+    /// Appends a counter line for the increase from `last` to `current`, if any.
+    fn counter(&self, lines: &mut Vec<String>, name: &str, last: u64, current: u64) {
+        let delta = current.saturating_sub(last);
+        if delta > 0 {
+            lines.push(format!("{}.{}:{}|c{}", self.prefix, name, delta, self.tags));
+        }
+    }
+
+    /// Sends `lines` packed into as few datagrams as possible. Lines that do not make it are
+    /// dropped, as StatsD expects.
+    fn send(&self, lines: &[String]) {
+        let mut datagram = String::new();
+        for line in lines {
+            if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_DATAGRAM {
+                let _ = self.socket.send(datagram.as_bytes());
+                datagram.clear();
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            datagram.push_str(line);
+        }
+        if !datagram.is_empty() {
+            let _ = self.socket.send(datagram.as_bytes());
+        }
+    }
+}
+
+impl MetricsExporter for Statsd {
+    fn export(&self, snapshot: &MetricsSnapshot) {
+        let mut last = match self.last.lock() {
+            Ok(last) => last,
+            Err(_) => return,
+        };
+        let mut lines = Vec::new();
+        self.counter(
+            &mut lines,
+            "invocations",
+            last.invocations,
+            snapshot.invocations,
+        );
+        for (kind, count) in &snapshot.errors {
+            let previous = last
+                .errors
+                .iter()
+                .find(|(k, _)| k == kind)
+                .map_or(0, |(_, count)| *count);
+            let name = format!("errors.{}", format!("{:?}", kind).to_ascii_lowercase());
+            self.counter(&mut lines, &name, previous, *count);
+        }
+        self.counter(
+            &mut lines,
+            "accept_failures",
+            last.accept_failures,
+            snapshot.accept_failures,
+        );
+        self.send(&lines);
+        *last = snapshot.clone();
+    }
+}
@@ -0,0 +1,51 @@
+use crate::errors::FunctionError;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT: AtomicU64 = AtomicU64::new(0);
+
+/// InvocationDir is a temporary directory scoped to an invocation, removed with its contents
+/// when dropped.
+#[derive(Debug)]
+pub(crate) struct InvocationDir {
+    path: PathBuf,
+}
+
+impl InvocationDir {
+    /// Creates a directory under the system temporary directory, named after `call_id`.
+    pub(crate) fn create(call_id: &str) -> Result<Self, FunctionError> {
+        let call_id: String = call_id
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+            .take(64)
+            .collect();
+        let path = std::env::temp_dir().join(format!(
+            "fdk-{}-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed),
+            call_id
+        ));
+        std::fs::create_dir(&path).map_err(|e| FunctionError::IO {
+            inner: format!("Failed to create temp dir {}: {}", path.display(), e),
+            source: Some(Box::new(e)),
+        })?;
+        Ok(Self { path })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for InvocationDir {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                crate::logging::log(
+                    crate::logging::Level::Warn,
+                    format_args!("failed to remove temp dir {}: {}", self.path.display(), e),
+                );
+            }
+        }
+    }
+}
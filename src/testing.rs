@@ -0,0 +1,173 @@
+//! In-process invocation of a handler for tests.
+//!
+//! `TestFunction` drives invocations through the same request pipeline as the Fn listener,
+//! including coercions, error responses and limits, without a socket or the Fn agent.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! let test_fn = TestFunction::new(handler);
+//! let response = test_fn
+//!     .invoke(r#"{"name":"test"}"#)
+//!     .with_header("Fn-Http-H-Authorization", "Bearer token")
+//!     .await;
+//! assert_eq!(response.status(), StatusCode::OK);
+//! let greeting: Greeting = response.json().unwrap();
+//! ```
+
+use crate::body::Body;
+use crate::coercions::{InputCoercible, OutputCoercible};
+use crate::completion;
+use crate::context::RuntimeContext;
+use crate::function::{self, InvocationOptions, Result};
+use http_body_util::BodyExt;
+use hyper::body::Bytes;
+use hyper::{HeaderMap, Request, StatusCode};
+use serde::de::DeserializeOwned;
+use std::future::{Future, IntoFuture};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+type Handler =
+    dyn Fn(Request<Body>) -> Pin<Box<dyn Future<Output = TestResponse> + Send>> + Send + Sync;
+
+/// TestFunction wraps a handler to invoke it in-process.
+pub struct TestFunction {
+    handler: Arc<Handler>,
+    calls: AtomicU64,
+}
+
+impl TestFunction {
+    /// Wraps `function`, configured from the environment like a served function.
+    pub fn new<T, S, F>(function: F) -> Self
+    where
+        T: InputCoercible + Send + 'static,
+        S: OutputCoercible + Send + 'static,
+        F: Fn(&mut RuntimeContext, T) -> Result<S> + Send + Sync + 'static,
+    {
+        let function = Arc::new(function);
+        let mut options = InvocationOptions::default();
+        if let Err(e) = options.prepare() {
+            panic!("invalid FDK configuration: {}", e);
+        }
+        let options = Arc::new(options);
+        let handler = move |req: Request<Body>| {
+            let response = function::serve_request(function.clone(), options.clone(), req);
+            Box::pin(async move {
+                let (parts, body) = response.await.into_parts();
+                let body = body
+                    .collect()
+                    .await
+                    .expect("failed to read the response body")
+                    .to_bytes();
+                TestResponse {
+                    status: completion::reported_status(parts.status, &parts.headers),
+                    headers: parts.headers,
+                    body,
+                }
+            }) as Pin<Box<dyn Future<Output = TestResponse> + Send>>
+        };
+        Self {
+            handler: Arc::new(handler),
+            calls: AtomicU64::new(0),
+        }
+    }
+
+    /// Starts an invocation with a JSON `body`. Awaiting it runs the handler.
+    pub fn invoke<B: Into<Vec<u8>>>(&self, body: B) -> TestInvocation {
+        let call = self.calls.fetch_add(1, Ordering::Relaxed);
+        TestInvocation {
+            handler: self.handler.clone(),
+            body: body.into(),
+            headers: vec![
+                ("Fn-Call-Id".to_owned(), format!("test-{}", call)),
+                (
+                    hyper::header::CONTENT_TYPE.to_string(),
+                    "application/json".to_owned(),
+                ),
+            ],
+        }
+    }
+}
+
+/// TestInvocation is an invocation being set up. Awaiting it runs the handler.
+pub struct TestInvocation {
+    handler: Arc<Handler>,
+    body: Vec<u8>,
+    headers: Vec<(String, String)>,
+}
+
+impl TestInvocation {
+    /// Sets a request header, replacing any previous value.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Sets the content type of the body, `application/json` by default.
+    pub fn content_type(self, content_type: &str) -> Self {
+        self.with_header(hyper::header::CONTENT_TYPE.as_str(), content_type)
+    }
+}
+
+impl IntoFuture for TestInvocation {
+    type Output = TestResponse;
+    type IntoFuture = Pin<Box<dyn Future<Output = TestResponse> + Send>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        let mut builder = Request::post("/");
+        for (name, value) in &self.headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        let req = builder
+            .body(Body::from(self.body))
+            .expect("invalid test header");
+        (self.handler)(req)
+    }
+}
+
+/// TestResponse is the response of a test invocation.
+#[derive(Debug)]
+pub struct TestResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl TestResponse {
+    /// Returns the status reported to the caller, from `Fn-Http-Status` when set.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Returns the response headers as written to the Fn agent.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Returns the value of the header the caller receives as `name`, whether it was set with
+    /// or without the `Fn-Http-H-` prefix.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(format!("Fn-Http-H-{}", name).as_str())
+            .or_else(|| self.headers.get(name))
+            .and_then(|v| v.to_str().ok())
+    }
+
+    /// Returns the raw body.
+    pub fn body(&self) -> &Bytes {
+        &self.body
+    }
+
+    /// Returns the body as text, replacing invalid UTF-8.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// Decodes the body as JSON.
+    pub fn json<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_slice(&self.body)
+    }
+}
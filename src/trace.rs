@@ -0,0 +1,66 @@
+use crate::oci::forwarded_header;
+use hyper::HeaderMap;
+use std::collections::HashMap;
+
+/// TraceContext holds the B3 tracing headers the Fn agent forwards with an invocation, and
+/// the OCI APM configuration of the function.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceContext {
+    /// The hex encoded trace ID, from `X-B3-TraceId` or the single `b3` header.
+    pub trace_id: Option<String>,
+    /// The hex encoded ID of the span of the caller, from `X-B3-SpanId`.
+    pub span_id: Option<String>,
+    /// The hex encoded ID of the parent of the caller span, from `X-B3-ParentSpanId`.
+    pub parent_span_id: Option<String>,
+    /// The sampling decision, from `X-B3-Sampled` or `X-B3-Flags`. None leaves the decision
+    /// to the receiver.
+    pub sampled: Option<bool>,
+    /// True if `OCI_TRACING_ENABLED` is set to `1`.
+    pub tracing_enabled: bool,
+    /// The OCI APM collector URL, from `OCI_TRACE_COLLECTOR_URL`.
+    pub collector_url: Option<String>,
+}
+
+fn parse_sampled(value: &str) -> Option<bool> {
+    match value {
+        "1" | "true" | "d" => Some(true),
+        "0" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+impl TraceContext {
+    pub(crate) fn from_headers(headers: &HeaderMap, config: &HashMap<String, String>) -> Self {
+        let get = |name: &str| forwarded_header(headers, name).map(str::to_owned);
+        let mut context = Self {
+            trace_id: get("X-B3-TraceId"),
+            span_id: get("X-B3-SpanId"),
+            parent_span_id: get("X-B3-ParentSpanId"),
+            sampled: if forwarded_header(headers, "X-B3-Flags") == Some("1") {
+                Some(true)
+            } else {
+                forwarded_header(headers, "X-B3-Sampled").and_then(parse_sampled)
+            },
+            tracing_enabled: config.get("OCI_TRACING_ENABLED").map(String::as_str) == Some("1"),
+            collector_url: config.get("OCI_TRACE_COLLECTOR_URL").cloned(),
+        };
+
+        // b3: {TraceId}-{SpanId}-{SamplingState}-{ParentSpanId}, or just {SamplingState}
+        if context.trace_id.is_none() {
+            if let Some(b3) = forwarded_header(headers, "b3") {
+                let mut parts = b3.split('-');
+                match (parts.next(), parts.next()) {
+                    (Some(trace_id), Some(span_id)) => {
+                        context.trace_id = Some(trace_id.to_owned());
+                        context.span_id = Some(span_id.to_owned());
+                        context.sampled = parts.next().and_then(parse_sampled);
+                        context.parent_span_id = parts.next().map(str::to_owned);
+                    }
+                    (Some(sampled), None) => context.sampled = parse_sampled(sampled),
+                    _ => {}
+                }
+            }
+        }
+        context
+    }
+}
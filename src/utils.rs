@@ -1,7 +1,8 @@
+use crate::body::Body;
 use clap::crate_version;
 use hyper::{
     header::{HeaderName, HeaderValue},
-    Body, HeaderMap, Response, StatusCode,
+    HeaderMap, Response, StatusCode,
 };
 use std::str::FromStr;
 
@@ -16,8 +17,8 @@ fn generic_response(status: StatusCode, body: Option<Body>, headers: HeaderMap)
     {
         let mut headers = headers;
         headers.insert(
-            "Fn-Fdk-Version",
-            HeaderValue::from_str(&format!("fdk-rust/{}", crate_version!())).unwrap(),
+            HeaderName::from_static("fn-fdk-version"),
+            HeaderValue::from_static(concat!("fdk-rust/", crate_version!())),
         );
         let resp_headers = builder.headers_mut().unwrap();
         *resp_headers = headers;
@@ -30,18 +31,23 @@ fn generic_response(status: StatusCode, body: Option<Body>, headers: HeaderMap)
     builder.body(response_body).unwrap()
 }
 
+/// Produces a response carrying `status` on the wire, outside of the Fn envelope.
+pub fn plain_response(status: StatusCode, body: Body) -> Response<Body> {
+    generic_response(status, Some(body), HeaderMap::new())
+}
+
 fn add_status_header(header: Option<HeaderMap>, status: StatusCode) -> HeaderMap {
     header
         .map(|mut hdrs| {
             hdrs.insert(
-                HeaderName::from_str("Fn-Http-Status").unwrap(),
+                HeaderName::from_static("fn-http-status"),
                 status.as_u16().into(),
             );
             hdrs
         })
         .unwrap_or_else(|| {
             make_header_map_with_single_value(
-                HeaderName::from_str("Fn-Http-Status").unwrap(),
+                HeaderName::from_static("fn-http-status"),
                 status.as_u16().into(),
             )
         })
@@ -64,3 +70,58 @@ pub fn unrecoverable_error(
     let response_headers = add_status_header(headers, status);
     generic_response(StatusCode::BAD_GATEWAY, body, response_headers)
 }
+
+/// ResponseEnvelope selects how the status and headers of responses are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResponseEnvelope {
+    /// The Fn http-stream contract: the status is reported in `Fn-Http-Status` over a 200,
+    /// and headers for the caller are prefixed with `Fn-Http-H-`.
+    #[default]
+    Fn,
+    /// Plain HTTP: the status is written on the wire and headers without prefix, to serve
+    /// directly behind a reverse proxy.
+    Plain,
+}
+
+/// Reads `FN_ENVELOPE`, `fn` or `plain`.
+pub(crate) fn envelope_from_env() -> Result<Option<ResponseEnvelope>, crate::FunctionError> {
+    match crate::context::CONFIG_FROM_ENV.get("FN_ENVELOPE") {
+        Some(v) => match v.to_ascii_lowercase().as_str() {
+            "fn" => Ok(Some(ResponseEnvelope::Fn)),
+            "plain" => Ok(Some(ResponseEnvelope::Plain)),
+            _ => Err(crate::FunctionError::Initialization {
+                inner: format!("Invalid FN_ENVELOPE specified: {}", v),
+                source: None,
+            }),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Turns a response in the Fn envelope into a plain HTTP one, with the status of
+/// `Fn-Http-Status` on the wire and the `Fn-Http-H-` prefix stripped from header names.
+pub(crate) fn unwrap_envelope(response: Response<Body>) -> Response<Body> {
+    let (mut parts, body) = response.into_parts();
+    if let Some(status) = parts
+        .headers
+        .remove("Fn-Http-Status")
+        .and_then(|v| v.to_str().ok().and_then(|v| v.parse::<u16>().ok()))
+        .and_then(|v| StatusCode::from_u16(v).ok())
+    {
+        parts.status = status;
+    }
+    let mut headers = HeaderMap::with_capacity(parts.headers.len());
+    let mut name = None;
+    for (key, value) in parts.headers {
+        name = key.or(name);
+        if let Some(name) = &name {
+            let unprefixed = name
+                .as_str()
+                .strip_prefix("fn-http-h-")
+                .and_then(|n| HeaderName::from_str(n).ok());
+            headers.append(unprefixed.unwrap_or_else(|| name.clone()), value);
+        }
+    }
+    parts.headers = headers;
+    Response::from_parts(parts, body)
+}
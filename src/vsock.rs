@@ -0,0 +1,187 @@
+use crate::FunctionError;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Backlog of pending connections of the listener.
+const BACKLOG: libc::c_int = 128;
+
+/// Parses a `vsock://cid:port` listener address. The CID may be `any` to accept connections
+/// addressed to any CID of the VM.
+pub(crate) fn parse_address(address: &str) -> Result<(u32, u32), FunctionError> {
+    let malformed = || FunctionError::Initialization {
+        inner: format!("Malformed vsock listener specified: {}", address),
+        source: None,
+    };
+    let rest = address.strip_prefix("vsock://").ok_or_else(malformed)?;
+    let (cid, port) = rest
+        .trim_end_matches('/')
+        .split_once(':')
+        .ok_or_else(malformed)?;
+    let cid = match cid {
+        "any" | "-1" => libc::VMADDR_CID_ANY,
+        cid => cid.parse().map_err(|_| malformed())?,
+    };
+    let port = port.parse().map_err(|_| malformed())?;
+    Ok((cid, port))
+}
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// VsockListener is a listening `AF_VSOCK` stream socket, used by functions running in
+/// microVMs where the Fn agent connects over vsock.
+pub(crate) struct VsockListener {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl VsockListener {
+    /// Binds a listener to `cid` and `port`.
+    pub(crate) fn bind(cid: u32, port: u32) -> io::Result<Self> {
+        // SAFETY: plain socket calls, the descriptor is owned by `fd` as soon as it is created.
+        unsafe {
+            let fd = check(libc::socket(
+                libc::AF_VSOCK,
+                libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+            ))?;
+            let fd = OwnedFd::from_raw_fd(fd);
+            let mut address: libc::sockaddr_vm = std::mem::zeroed();
+            address.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+            address.svm_cid = cid;
+            address.svm_port = port;
+            check(libc::bind(
+                fd.as_raw_fd(),
+                &address as *const libc::sockaddr_vm as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t,
+            ))?;
+            check(libc::listen(fd.as_raw_fd(), BACKLOG))?;
+            Ok(Self {
+                fd: AsyncFd::new(fd)?,
+            })
+        }
+    }
+
+    pub(crate) fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<VsockStream>> {
+        loop {
+            let mut guard = match self.fd.poll_read_ready(cx) {
+                Poll::Ready(Ok(guard)) => guard,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            };
+            let accepted = guard.try_io(|fd| {
+                // SAFETY: accepting on a listening socket, the new descriptor is owned at once.
+                let conn = check(unsafe {
+                    libc::accept4(
+                        fd.as_raw_fd(),
+                        std::ptr::null_mut(),
+                        std::ptr::null_mut(),
+                        libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                    )
+                })?;
+                Ok(unsafe { OwnedFd::from_raw_fd(conn) })
+            });
+            match accepted {
+                Ok(Ok(conn)) => return Poll::Ready(AsyncFd::new(conn).map(VsockStream)),
+                Ok(Err(e)) => return Poll::Ready(Err(e)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+/// VsockStream is an accepted vsock connection.
+pub struct VsockStream(AsyncFd<OwnedFd>);
+
+impl VsockStream {
+    fn poll_io<F>(&self, cx: &mut Context<'_>, write: bool, mut op: F) -> Poll<io::Result<usize>>
+    where
+        F: FnMut(RawFd) -> isize,
+    {
+        loop {
+            let ready = if write {
+                self.0.poll_write_ready(cx)
+            } else {
+                self.0.poll_read_ready(cx)
+            };
+            let mut guard = match ready {
+                Poll::Ready(Ok(guard)) => guard,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            };
+            match guard.try_io(|fd| {
+                let n = op(fd.as_raw_fd());
+                if n < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            }) {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl AsyncRead for VsockStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let unfilled = buf.initialize_unfilled();
+        // SAFETY: reads at most `unfilled.len()` bytes into the initialized buffer.
+        let read = self.poll_io(cx, false, |fd| unsafe {
+            libc::read(
+                fd,
+                unfilled.as_mut_ptr() as *mut libc::c_void,
+                unfilled.len(),
+            )
+        });
+        match read {
+            Poll::Ready(Ok(n)) => {
+                buf.advance(n);
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl AsyncWrite for VsockStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        // SAFETY: writes at most `buf.len()` bytes from `buf`.
+        self.poll_io(cx, true, |fd| unsafe {
+            libc::send(
+                fd,
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+                libc::MSG_NOSIGNAL,
+            )
+        })
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // SAFETY: shuts the write side of an owned socket down.
+        let result = check(unsafe { libc::shutdown(self.0.as_raw_fd(), libc::SHUT_WR) });
+        Poll::Ready(result.map(|_| ()))
+    }
+}
@@ -0,0 +1,160 @@
+//! State kept across the invocations of a warm container.
+//!
+//! The container of a function serves many invocations before it is recycled, so connection
+//! pools, clients and caches are best created once and reused. `WarmState` holds such a value:
+//! it is created by the first invocation needing it, shared by the concurrent ones, and can be
+//! invalidated when it goes bad, e.g. after a connection error, to be created again by the next
+//! invocation.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! static CLIENT: WarmState<Client> = WarmState::new();
+//!
+//! Function::builder()
+//!     .on_start(|| async {
+//!         CLIENT.get_or_try_init(Client::connect).await?;
+//!         Ok(())
+//!     })
+//!     .run(|_: &mut RuntimeContext, id: String| {
+//!         let client = CLIENT.get_or_init_with(Client::connect_blocking);
+//!         client.find_user(&id).map_err(|e| {
+//!             if e.is_connection() {
+//!                 CLIENT.invalidate();
+//!             }
+//!             e.into()
+//!         })
+//!     })
+//!     .await
+//! ```
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+type InvalidationHook<T> = Box<dyn Fn(&T) + Send + Sync>;
+
+/// WarmState is a value created on first use and kept across warm invocations until
+/// invalidated.
+pub struct WarmState<T> {
+    value: Mutex<Option<Arc<T>>>,
+    init: tokio::sync::Mutex<()>,
+    hooks: Mutex<Vec<InvalidationHook<T>>>,
+}
+
+impl<T> WarmState<T> {
+    /// Returns an empty cell, usable in a `static`.
+    pub const fn new() -> Self {
+        Self {
+            value: Mutex::new(None),
+            init: tokio::sync::Mutex::const_new(()),
+            hooks: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the value if it is set.
+    pub fn get(&self) -> Option<Arc<T>> {
+        self.value.lock().ok().and_then(|value| value.clone())
+    }
+
+    /// Returns the value, creating it with `init` if it is not set. Concurrent callers wait for
+    /// the first one to create it instead of creating their own.
+    pub async fn get_or_init<F, Fut>(&self, init: F) -> Arc<T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        match self
+            .get_or_try_init(|| async { Ok::<T, std::convert::Infallible>(init().await) })
+            .await
+        {
+            Ok(value) => value,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Returns the value, creating it with `init` if it is not set. If `init` fails, the error
+    /// is returned and the next caller tries again.
+    pub async fn get_or_try_init<F, Fut, E>(&self, init: F) -> Result<Arc<T>, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if let Some(value) = self.get() {
+            return Ok(value);
+        }
+        let _init = self.init.lock().await;
+        if let Some(value) = self.get() {
+            return Ok(value);
+        }
+        let value = Arc::new(init().await?);
+        self.set(value.clone());
+        Ok(value)
+    }
+
+    /// Returns the value, creating it with `init` if it is not set, for handlers, which cannot
+    /// await. Concurrent callers may each run `init`, in which case the value of the first to
+    /// finish is kept and returned to all of them.
+    pub fn get_or_init_with<F>(&self, init: F) -> Arc<T>
+    where
+        F: FnOnce() -> T,
+    {
+        if let Some(value) = self.get() {
+            return value;
+        }
+        let value = Arc::new(init());
+        match self.value.lock() {
+            Ok(mut current) => current.get_or_insert(value).clone(),
+            Err(_) => value,
+        }
+    }
+
+    /// Replaces the value, invalidating the previous one.
+    pub fn replace(&self, value: T) {
+        self.invalidate();
+        self.set(Arc::new(value));
+    }
+
+    /// Clears the value so the next `get_or_init` creates it again, and runs the invalidation
+    /// hooks on it. Invocations still holding it keep it until they drop it. Returns the value
+    /// if it was set.
+    pub fn invalidate(&self) -> Option<Arc<T>> {
+        let value = self.value.lock().ok().and_then(|mut value| value.take())?;
+        if let Ok(hooks) = self.hooks.lock() {
+            for hook in hooks.iter() {
+                hook(&value);
+            }
+        }
+        Some(value)
+    }
+
+    /// Registers a hook run on the value when it is invalidated or replaced, e.g. to log it
+    /// or to close connections.
+    pub fn on_invalidate<H>(&self, hook: H)
+    where
+        H: Fn(&T) + Send + Sync + 'static,
+    {
+        if let Ok(mut hooks) = self.hooks.lock() {
+            hooks.push(Box::new(hook));
+        }
+    }
+
+    fn set(&self, value: Arc<T>) {
+        if let Ok(mut current) = self.value.lock() {
+            *current = Some(value);
+        }
+    }
+}
+
+impl<T> Default for WarmState<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for WarmState<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WarmState")
+            .field("value", &self.get())
+            .finish()
+    }
+}